@@ -0,0 +1,133 @@
+use crate::dbo::{ProjectScope, Ticket, TicketWatcher, Watcher};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{ticket_watchers, tickets, users};
+use crate::Db;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+impl Db {
+    /// Sets both the user and the team the ticket is assigned to, `None` unassigns. Deactivated user cannot be
+    /// assigned.
+    #[tracing::instrument(skip(self))]
+    pub fn assign_ticket(
+        &self,
+        scope: &ProjectScope,
+        ticket_id: i32,
+        assignee_id: Option<i32>,
+        assignee_team_id: Option<i32>,
+    ) -> DbResult<Ticket> {
+        let conn = self.get_conn("assign ticket")?;
+        conn.transaction::<_, DbError, _>(|| {
+            ensure_in_scope(&conn, scope, ticket_id)?;
+            if let Some(assignee_id) = assignee_id {
+                let active = users::table
+                    .find(assignee_id)
+                    .select(users::active)
+                    .first::<bool>(&conn)
+                    .optional()?
+                    .ok_or_else(|| DbError::not_found("assignee"))?;
+                if !active {
+                    return Err(DbError::InvalidData(format!(
+                        "user {} is deactivated and cannot be assigned",
+                        assignee_id
+                    )));
+                }
+            }
+
+            diesel::update(tickets::table.find(ticket_id))
+                .set((
+                    tickets::assignee_id.eq(assignee_id),
+                    tickets::assignee_team_id.eq(assignee_team_id),
+                ))
+                .get_result::<Ticket>(&conn)
+                .map_err(DbError::from)
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_watchers(
+        &self,
+        scope: &ProjectScope,
+        ticket_id: i32,
+    ) -> DbResult<Vec<TicketWatcher>> {
+        let conn = self.get_conn("select watchers")?;
+        ensure_in_scope(&conn, scope, ticket_id)?;
+
+        ticket_watchers::table
+            .filter(ticket_watchers::ticket_id.eq(ticket_id))
+            .order(ticket_watchers::id)
+            .load::<TicketWatcher>(&conn)
+            .map_err(|err| DbError::query_error("select watchers", err))
+    }
+
+    /// Adding watcher who already watches the ticket returns the existing entry.
+    #[tracing::instrument(skip(self))]
+    pub fn add_watcher(
+        &self,
+        scope: &ProjectScope,
+        ticket_id: i32,
+        watcher: Watcher,
+    ) -> DbResult<TicketWatcher> {
+        let (user_id, team_id) = match watcher {
+            Watcher::User(user_id) => (Some(user_id), None),
+            Watcher::Team(team_id) => (None, Some(team_id)),
+        };
+
+        let conn = self.get_conn("add watcher")?;
+        conn.transaction::<_, DbError, _>(|| {
+            ensure_in_scope(&conn, scope, ticket_id)?;
+
+            diesel::insert_into(ticket_watchers::table)
+                .values((
+                    ticket_watchers::ticket_id.eq(ticket_id),
+                    ticket_watchers::user_id.eq(user_id),
+                    ticket_watchers::team_id.eq(team_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+
+            let mut query = ticket_watchers::table
+                .filter(ticket_watchers::ticket_id.eq(ticket_id))
+                .into_boxed();
+            query = match watcher {
+                Watcher::User(user_id) => query.filter(ticket_watchers::user_id.eq(user_id)),
+                Watcher::Team(team_id) => query.filter(ticket_watchers::team_id.eq(team_id)),
+            };
+            query.first::<TicketWatcher>(&conn).map_err(DbError::from)
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn remove_watcher(
+        &self,
+        scope: &ProjectScope,
+        ticket_id: i32,
+        watcher_id: i32,
+    ) -> DbResult<usize> {
+        let conn = self.get_conn("remove watcher")?;
+        ensure_in_scope(&conn, scope, ticket_id)?;
+
+        diesel::delete(
+            ticket_watchers::table
+                .find(watcher_id)
+                .filter(ticket_watchers::ticket_id.eq(ticket_id)),
+        )
+        .execute(&conn)
+        .and_then(|rows_affected| match rows_affected {
+            0 => Err(diesel::NotFound),
+            _ => Ok(rows_affected),
+        })
+        .map_err(|err| DbError::query_error("remove watcher", err))
+    }
+}
+
+fn ensure_in_scope(conn: &PgConnection, scope: &ProjectScope, ticket_id: i32) -> DbResult<()> {
+    tickets::table
+        .find(ticket_id)
+        .select(tickets::project_id)
+        .first::<i32>(conn)
+        .optional()?
+        .filter(|project_id| scope.allows(*project_id))
+        .map(|_| ())
+        .ok_or_else(|| DbError::not_found("ticket"))
+}
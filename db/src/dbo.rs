@@ -1,7 +1,55 @@
-use crate::schema::{tickets, users};
+use crate::schema::{external_refs, ticket_history, tickets, users};
+use diesel::sql_types::{BigInt, Date, Double, Float, Int2, Integer, Varchar};
 use std::fmt::Formatter;
 
-#[derive(Debug, Queryable, AsChangeset)]
+/// Project created by migrations, tickets without explicit project belong to it.
+pub const DEFAULT_PROJECT_ID: i32 = 1;
+
+/// Values stored in `tickets.status`. Anything at or above [`status::RESOLVED`] counts as done for reporting.
+pub mod status {
+    pub const OPEN: i16 = 0;
+    pub const IN_PROGRESS: i16 = 1;
+    pub const RESOLVED: i16 = 2;
+    pub const CLOSED: i16 = 3;
+}
+
+/// Values stored in `users.role`.
+pub mod role {
+    pub const ADMIN: &str = "admin";
+    pub const MEMBER: &str = "member";
+    pub const REPORTER: &str = "reporter";
+    pub const VIEWER: &str = "viewer";
+}
+
+/// Values stored in `login_failures.kind`.
+pub mod login_subject {
+    pub const USER: &str = "user";
+    pub const IP: &str = "ip";
+}
+
+/// Failed logins in a row, see [`crate::Db::record_login_failure`].
+#[derive(Debug, QueryableByName)]
+pub struct LoginFailures {
+    #[sql_type = "Integer"]
+    pub failures: i32,
+}
+
+/// Values stored in `tickets.resolution`.
+pub mod resolution {
+    pub const DUPLICATE: &str = "duplicate";
+}
+
+/// Kinds of entries in `ticket_history`. Every entry carries status of the ticket, so even entries
+/// which are not status changes can be used for status reporting.
+pub mod history_event {
+    pub const STATUS: &str = "status";
+    /// ticket was merged into `related_ticket_id` as its duplicate
+    pub const MERGED_INTO: &str = "merged_into";
+    /// `related_ticket_id` was merged into the ticket
+    pub const MERGED_FROM: &str = "merged_from";
+}
+
+#[derive(Debug, Queryable)]
 pub struct Ticket {
     pub id: i32,
     pub author_id: i32,
@@ -9,29 +57,26 @@ pub struct Ticket {
     pub severity: i16,
     pub status: i16,
     pub created: chrono::NaiveDateTime,
+    /// cached rendering of markdown `description`, `None` when it has to be rendered again
+    pub description_html: Option<String>,
+    pub resolution: Option<String>,
+    pub merged_into: Option<i32>,
+    pub project_id: i32,
+    pub assignee_id: Option<i32>,
+    pub assignee_team_id: Option<i32>,
 }
 
-impl Ticket {
-    pub fn new(
-        id: Option<i32>,
-        author_id: i32,
-        description: String,
-        severity: i16,
-        status: Option<i16>,
-    ) -> Self {
-        let now = chrono::Local::now();
-        Ticket {
-            id: id.unwrap_or(0),
-            author_id,
-            description,
-            severity,
-            status: status.unwrap_or(0),
-            created: chrono::NaiveDateTime::from_timestamp(
-                now.timestamp(),
-                now.timestamp_subsec_nanos(),
-            ),
-        }
-    }
+/// Columns a ticket update writes. Author and creation time never change, resolution, merging and
+/// assignment have endpoints of their own.
+#[derive(Debug, AsChangeset)]
+#[table_name = "tickets"]
+pub struct TicketChanges {
+    pub id: i32,
+    /// project the ticket moves to, `None` keeps it where it is
+    pub project_id: Option<i32>,
+    pub description: String,
+    pub severity: i16,
+    pub status: i16,
 }
 
 #[derive(Debug, Insertable)]
@@ -41,6 +86,7 @@ pub struct NewTicket {
     pub(crate) description: String,
     pub(crate) severity: i16,
     pub(crate) status: i16,
+    pub(crate) project_id: i32,
 }
 
 impl NewTicket {
@@ -49,12 +95,253 @@ impl NewTicket {
             author_id,
             description,
             severity,
-            status: 0,
+            status: status::OPEN,
+            project_id: DEFAULT_PROJECT_ID,
+        }
+    }
+
+    pub fn author_id(&self) -> i32 {
+        self.author_id
+    }
+
+    pub fn project_id(&self) -> i32 {
+        self.project_id
+    }
+
+    pub fn with_project(mut self, project_id: i32) -> Self {
+        self.project_id = project_id;
+        self
+    }
+
+    pub fn with_status(mut self, status: i16) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Optional conditions tickets are filtered by when listing or exporting them.
+#[derive(Debug, Default, Clone)]
+pub struct TicketFilter {
+    pub project_id: Option<i32>,
+    pub author_id: Option<i32>,
+    pub status: Option<i16>,
+    pub severity: Option<i16>,
+    pub assignee_id: Option<i32>,
+    pub assignee_team_id: Option<i32>,
+    /// tickets assigned to any team the user with this id is member of
+    pub assigned_to_teams_of: Option<i32>,
+}
+
+/// Projects whose tickets a caller can reach. Tickets outside of the scope are treated as if they did not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProjectScope {
+    /// every project, for admins and for the server itself (CLI, imports)
+    All,
+    Only(Vec<i32>),
+}
+
+impl ProjectScope {
+    /// `None` when the scope is not restricted.
+    pub fn project_ids(&self) -> Option<&[i32]> {
+        match self {
+            ProjectScope::All => None,
+            ProjectScope::Only(ids) => Some(ids.as_slice()),
+        }
+    }
+
+    pub fn allows(&self, project_id: i32) -> bool {
+        match self {
+            ProjectScope::All => true,
+            ProjectScope::Only(ids) => ids.contains(&project_id),
         }
     }
 }
 
-#[derive(Queryable, AsChangeset)]
+#[derive(Debug, Queryable)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Role of a user within single project, takes the same values as `users.role`.
+#[derive(Debug, Queryable)]
+pub struct ProjectMember {
+    pub project_id: i32,
+    pub user_id: i32,
+    pub role: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable)]
+pub struct Team {
+    pub id: i32,
+    pub name: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable)]
+pub struct TeamMember {
+    pub team_id: i32,
+    pub user_id: i32,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Role every member of the team gets in the project.
+#[derive(Debug, Queryable)]
+pub struct ProjectTeam {
+    pub project_id: i32,
+    pub team_id: i32,
+    pub role: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Exactly one of `user_id` and `team_id` is set.
+#[derive(Debug, Queryable)]
+pub struct TicketWatcher {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub user_id: Option<i32>,
+    pub team_id: Option<i32>,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watcher {
+    User(i32),
+    Team(i32),
+}
+
+/// Refresh token is stored only as SHA-256 hash. Tokens rotated from the one issued on login share `family`.
+#[derive(Debug, Queryable)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub family: String,
+    pub expires: chrono::NaiveDateTime,
+    pub revoked: bool,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable)]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires: chrono::NaiveDateTime,
+    pub last_used: Option<chrono::NaiveDateTime>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Invitation link issued by admin, see [`crate::Db::accept_invitation`].
+#[derive(Debug, Queryable)]
+pub struct Invitation {
+    pub id: i32,
+    pub token_hash: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub invited_by: Option<i32>,
+    pub expires: chrono::NaiveDateTime,
+    pub accepted: Option<chrono::NaiveDateTime>,
+    pub accepted_by: Option<i32>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Login of the user, see [`crate::Db::start_session`].
+#[derive(Debug, Queryable)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub family: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires: chrono::NaiveDateTime,
+    pub last_used: chrono::NaiveDateTime,
+    pub revoked: Option<chrono::NaiveDateTime>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Request made by admin as another user, see [`crate::Db::record_impersonation`].
+#[derive(Debug, Queryable)]
+pub struct ImpersonationRecord {
+    pub id: i32,
+    pub actor_id: i32,
+    pub user_id: i32,
+    pub method: String,
+    pub path: String,
+    pub status: Option<i16>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// TOTP second factor of the user, see [`crate::Db::start_totp_enrollment`].
+#[derive(Queryable)]
+pub struct UserTotp {
+    pub user_id: i32,
+    pub secret: String,
+    pub confirmed: Option<chrono::NaiveDateTime>,
+    pub last_step: Option<i64>,
+    pub created: chrono::NaiveDateTime,
+}
+
+impl std::fmt::Debug for UserTotp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserTotp")
+            .field("user_id", &self.user_id)
+            .field("secret", &"*censored*")
+            .field("confirmed", &self.confirmed)
+            .field("last_step", &self.last_step)
+            .field("created", &self.created)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum Rotation {
+    /// presented token was valid and is replaced by the new one, id of its session when it has one
+    Rotated(RefreshToken, Option<i32>),
+    /// presented token was already rotated or revoked, whole family got revoked as it is likely stolen
+    Reused,
+    Expired,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct SimilarTicket {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Varchar"]
+    pub description: String,
+    #[sql_type = "Int2"]
+    pub status: i16,
+    #[sql_type = "Float"]
+    pub similarity: f32,
+}
+
+#[derive(Debug, Queryable)]
+pub struct TicketHistory {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub old_status: Option<i16>,
+    pub new_status: i16,
+    pub created: chrono::NaiveDateTime,
+    pub event: String,
+    pub related_ticket_id: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "ticket_history"]
+pub(crate) struct NewTicketHistory<'a> {
+    pub(crate) ticket_id: i32,
+    pub(crate) old_status: Option<i16>,
+    pub(crate) new_status: i16,
+    pub(crate) event: &'a str,
+    pub(crate) related_ticket_id: Option<i32>,
+}
+
+/// Role of the user is not part of regular updates, it is changed only by [`crate::Db::update_user_role`].
+#[derive(Queryable)]
 pub struct User {
     pub id: i32,
     pub username: String,
@@ -62,6 +349,19 @@ pub struct User {
     pub firstname: String,
     pub lastname: String,
     pub created: chrono::NaiveDateTime,
+    pub role: String,
+    /// where password reset links are sent to
+    pub email: Option<String>,
+    /// when the user proved `email` is theirs, users with unverified email cannot log in with password
+    pub email_verified: Option<chrono::NaiveDateTime>,
+    /// shown instead of first and last name when set
+    pub display_name: Option<String>,
+    /// IANA name, e.g. `Europe/Prague`
+    pub timezone: String,
+    /// BCP 47 language tag, e.g. `en-GB`
+    pub locale: String,
+    /// deactivated users cannot log in, see [`crate::Db::deactivate_user`]
+    pub active: bool,
 }
 
 impl User {
@@ -83,12 +383,45 @@ impl User {
                 now.timestamp(),
                 now.timestamp_subsec_micros(),
             ),
+            role: role::MEMBER.into(),
+            email: None,
+            email_verified: None,
+            display_name: None,
+            timezone: DEFAULT_TIMEZONE.into(),
+            locale: DEFAULT_LOCALE.into(),
+            active: true,
+        }
+    }
+
+    /// Email the user has to verify, unless it is `verified` already.
+    pub fn with_email(mut self, email: Option<String>, verified: bool) -> Self {
+        self.email_verified = email
+            .as_ref()
+            .filter(|_| verified)
+            .map(|_| chrono::Utc::now().naive_utc());
+        self.email = email;
+        self
+    }
+
+    /// Name the user is shown by, display name or first and last name, username when neither is set.
+    pub fn name(&self) -> String {
+        match &self.display_name {
+            Some(display_name) => display_name.clone(),
+            None => match format!("{} {}", self.firstname, self.lastname).trim() {
+                "" => self.username.clone(),
+                name => name.to_string(),
+            },
         }
     }
 
     pub fn id(&self) -> i32 {
         self.id
     }
+
+    /// Whether the user has email which is waiting for verification.
+    pub fn email_unverified(&self) -> bool {
+        self.email.is_some() && self.email_verified.is_none()
+    }
 }
 
 impl std::fmt::Debug for User {
@@ -100,10 +433,72 @@ impl std::fmt::Debug for User {
             .field("firstname", &self.firstname)
             .field("lastname", &self.lastname)
             .field("created", &self.created)
+            .field("role", &self.role)
+            .field("email", &self.email)
+            .field("email_verified", &self.email_verified)
+            .field("display_name", &self.display_name)
+            .field("timezone", &self.timezone)
+            .field("locale", &self.locale)
+            .field("active", &self.active)
             .finish()
     }
 }
 
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Profile fields the user changes on their own, `None` leaves the field as it is.
+#[derive(Debug, Default, AsChangeset)]
+#[table_name = "users"]
+pub struct ProfileChange {
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+    /// `Some(None)` clears the display name
+    pub display_name: Option<Option<String>>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+}
+
+impl ProfileChange {
+    pub fn is_empty(&self) -> bool {
+        self.firstname.is_none()
+            && self.lastname.is_none()
+            && self.display_name.is_none()
+            && self.timezone.is_none()
+            && self.locale.is_none()
+    }
+}
+
+#[derive(Queryable)]
+pub struct Avatar {
+    pub user_id: i32,
+    /// type of `data` detected on upload, e.g. `image/png`
+    pub content_type: String,
+    pub data: Vec<u8>,
+    pub updated: chrono::NaiveDateTime,
+}
+
+impl std::fmt::Debug for Avatar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Avatar")
+            .field("user_id", &self.user_id)
+            .field("content_type", &self.content_type)
+            .field("data", &format_args!("{} bytes", self.data.len()))
+            .field("updated", &self.updated)
+            .finish()
+    }
+}
+
+/// Identity of OpenID Connect or login provider linked to the user, see [`crate::Db::link_identity`].
+#[derive(Debug, Queryable)]
+pub struct UserIdentity {
+    pub id: i32,
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub created: chrono::NaiveDateTime,
+}
+
 #[derive(Insertable)]
 #[table_name = "users"]
 pub struct NewUser {
@@ -111,6 +506,9 @@ pub struct NewUser {
     pub(crate) password: String,
     pub(crate) firstname: String,
     pub(crate) lastname: String,
+    pub(crate) role: String,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified: Option<chrono::NaiveDateTime>,
 }
 
 impl NewUser {
@@ -120,8 +518,34 @@ impl NewUser {
             password,
             firstname,
             lastname,
+            role: role::MEMBER.into(),
+            email: None,
+            email_verified: None,
         }
     }
+
+    pub fn with_role(mut self, role: String) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Email which is trusted right away, e.g. entered by admin or taken from identity provider.
+    pub fn with_email(mut self, email: Option<String>) -> Self {
+        self.email_verified = email.as_ref().map(|_| chrono::Utc::now().naive_utc());
+        self.email = email;
+        self
+    }
+
+    /// Email the user has to verify before logging in with password.
+    pub fn with_unverified_email(mut self, email: String) -> Self {
+        self.email = Some(email);
+        self.email_verified = None;
+        self
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
 }
 
 impl std::fmt::Debug for NewUser {
@@ -131,6 +555,163 @@ impl std::fmt::Debug for NewUser {
             .field("password", &"*censored*")
             .field("firstname", &self.firstname)
             .field("lastname", &self.lastname)
+            .field("email", &self.email)
+            .field("email_verified", &self.email_verified)
             .finish()
     }
 }
+
+#[derive(Debug, QueryableByName)]
+pub struct DailyThroughput {
+    #[sql_type = "Date"]
+    pub day: chrono::NaiveDate,
+    #[sql_type = "BigInt"]
+    pub created: i64,
+    #[sql_type = "BigInt"]
+    pub resolved: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct CycleTime {
+    #[sql_type = "Int2"]
+    pub severity: i16,
+    #[sql_type = "BigInt"]
+    pub tickets: i64,
+    #[sql_type = "Double"]
+    pub mean_seconds: f64,
+    #[sql_type = "Double"]
+    pub p50_seconds: f64,
+    #[sql_type = "Double"]
+    pub p90_seconds: f64,
+    #[sql_type = "Double"]
+    pub p95_seconds: f64,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct TimeInStatus {
+    #[sql_type = "Int2"]
+    pub status: i16,
+    #[sql_type = "BigInt"]
+    pub tickets: i64,
+    #[sql_type = "Double"]
+    pub total_seconds: f64,
+    #[sql_type = "Double"]
+    pub mean_seconds: f64,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct CumulativeFlow {
+    #[sql_type = "Date"]
+    pub day: chrono::NaiveDate,
+    #[sql_type = "Int2"]
+    pub status: i16,
+    #[sql_type = "BigInt"]
+    pub tickets: i64,
+}
+
+#[derive(Debug, Queryable)]
+pub struct ExternalRef {
+    pub id: i32,
+    pub source: String,
+    pub kind: String,
+    pub external_id: String,
+    pub local_id: i32,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "external_refs"]
+pub(crate) struct NewExternalRef<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) kind: &'a str,
+    pub(crate) external_id: &'a str,
+    pub(crate) local_id: i32,
+}
+
+/// User as known by other issue tracker, identified by `external_id` within the source.
+pub struct ExternalUser {
+    pub external_id: String,
+    pub username: String,
+    pub password: String,
+    pub firstname: String,
+    pub lastname: String,
+}
+
+impl std::fmt::Debug for ExternalUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternalUser")
+            .field("external_id", &self.external_id)
+            .field("username", &self.username)
+            .field("password", &"*censored*")
+            .field("firstname", &self.firstname)
+            .field("lastname", &self.lastname)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct ExternalLink {
+    pub relation: String,
+    pub target_external_id: String,
+    /// human readable name of the linked issue in the source tracker, e.g. `PROJ-12`
+    pub target_label: String,
+}
+
+#[derive(Debug)]
+pub struct ExternalTicket {
+    pub external_id: String,
+    pub author_external_id: String,
+    pub description: String,
+    pub severity: i16,
+    pub status: i16,
+    pub created: Option<chrono::NaiveDateTime>,
+    pub links: Vec<ExternalLink>,
+}
+
+#[derive(Debug, Default)]
+pub struct ExternalBatch {
+    pub users: Vec<ExternalUser>,
+    pub tickets: Vec<ExternalTicket>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub users_created: usize,
+    pub users_linked: usize,
+    pub tickets_created: usize,
+    pub tickets_updated: usize,
+}
+
+/// Single row of full database export. Rows are exported in order in which they can be imported back,
+/// i.e. every row comes after rows it references.
+#[derive(Debug)]
+pub enum SnapshotRecord {
+    User(User),
+    UserAvatar(Avatar),
+    UserIdentity(UserIdentity),
+    Team(Team),
+    TeamMember(TeamMember),
+    Project(Project),
+    ProjectMember(ProjectMember),
+    ProjectTeam(ProjectTeam),
+    Ticket(Ticket),
+    TicketWatcher(TicketWatcher),
+    TicketHistory(TicketHistory),
+    ExternalRef(ExternalRef),
+}
+
+#[derive(Debug, Default)]
+pub struct SnapshotSummary {
+    pub users: usize,
+    pub user_avatars: usize,
+    pub user_identities: usize,
+    pub teams: usize,
+    pub team_members: usize,
+    pub projects: usize,
+    pub project_members: usize,
+    pub project_teams: usize,
+    pub tickets: usize,
+    pub ticket_watchers: usize,
+    pub ticket_history: usize,
+    pub external_refs: usize,
+}
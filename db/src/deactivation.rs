@@ -0,0 +1,64 @@
+use crate::dbo::{status, Ticket, User};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{personal_access_tokens, tickets, users};
+use crate::Db;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+impl Db {
+    /// Deactivates the user and deletes their personal access tokens. Sessions are revoked by the caller, so
+    /// they are refused right away also by the revocation cache.
+    #[tracing::instrument(skip(self))]
+    pub fn deactivate_user(&self, user_id: i32) -> DbResult<User> {
+        let conn = self.get_conn("deactivate user")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let user = set_active(&conn, user_id, false)?;
+            let deleted = diesel::delete(
+                personal_access_tokens::table.filter(personal_access_tokens::user_id.eq(user_id)),
+            )
+            .execute(&conn)?;
+            tracing::debug!(
+                deleted,
+                "personal access tokens of deactivated user deleted"
+            );
+            Ok(user)
+        })
+    }
+
+    /// Lets deactivated user log in again, tokens revoked on deactivation stay revoked.
+    #[tracing::instrument(skip(self))]
+    pub fn reactivate_user(&self, user_id: i32) -> DbResult<User> {
+        let conn = self.get_conn("reactivate user")?;
+        conn.transaction::<_, DbError, _>(|| set_active(&conn, user_id, true))
+    }
+
+    /// Tickets assigned to the user which are neither resolved nor closed, the oldest first.
+    #[tracing::instrument(skip(self))]
+    pub fn select_open_assignments(&self, user_id: i32) -> DbResult<Vec<Ticket>> {
+        tickets::table
+            .filter(tickets::assignee_id.eq(user_id))
+            .filter(tickets::status.lt(status::RESOLVED))
+            .order(tickets::id)
+            .load::<Ticket>(&self.get_conn("select open assignments")?)
+            .map_err(|err| DbError::query_error("select open assignments", err))
+    }
+}
+
+fn set_active(conn: &PgConnection, user_id: i32, active: bool) -> DbResult<User> {
+    let user = users::table
+        .find(user_id)
+        .for_update()
+        .first::<User>(conn)
+        .optional()?
+        .ok_or_else(|| DbError::not_found("user"))?;
+    if user.active == active {
+        return Err(DbError::InvalidData(format!(
+            "user is already {}",
+            if active { "active" } else { "deactivated" }
+        )));
+    }
+
+    Ok(diesel::update(users::table.find(user_id))
+        .set(users::active.eq(active))
+        .get_result::<User>(conn)?)
+}
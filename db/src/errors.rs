@@ -1,4 +1,3 @@
-use crate::Db;
 use diesel::result::Error;
 
 pub type DbResult<T> = Result<T, DbError>;
@@ -25,6 +24,10 @@ pub enum DbError {
     Unknown(String),
     #[error("database error: {0}")]
     DatabaseError(String),
+    #[error("invalid data: {0}")]
+    InvalidData(String),
+    #[error("conflicting data: {0}")]
+    Conflict(&'static str),
 }
 
 impl DbError {
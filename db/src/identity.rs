@@ -0,0 +1,135 @@
+use crate::dbo::{NewUser, User};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{oidc_logins, user_identities, users};
+use crate::token::hash_token;
+use crate::{insert_new_user, Db};
+use diesel::prelude::*;
+
+impl Db {
+    /// Remembers login redirected to OpenID Connect provider until it comes back with `state`. Logins which
+    /// never came back are cleaned up on the way.
+    #[tracing::instrument(skip(self, state, nonce, code_verifier))]
+    pub fn insert_oidc_login(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<()> {
+        let conn = self.get_conn("insert OpenID Connect login")?;
+        diesel::delete(
+            oidc_logins::table.filter(oidc_logins::expires.lt(chrono::Utc::now().naive_utc())),
+        )
+        .execute(&conn)
+        .map_err(|err| DbError::query_error("delete expired OpenID Connect logins", err))?;
+
+        diesel::insert_into(oidc_logins::table)
+            .values((
+                oidc_logins::state_hash.eq(hash_token(&conn, state)?),
+                oidc_logins::nonce.eq(nonce),
+                oidc_logins::code_verifier.eq(code_verifier),
+                oidc_logins::expires.eq(expires),
+            ))
+            .execute(&conn)
+            .map(|_| ())
+            .map_err(|err| DbError::insert_error("oidc_logins", err))
+    }
+
+    /// Consumes login started with `state` and returns its nonce and PKCE code verifier. Unknown, used
+    /// or expired state is reported as not found.
+    #[tracing::instrument(skip(self, state))]
+    pub fn take_oidc_login(&self, state: &str) -> DbResult<(String, String)> {
+        let conn = self.get_conn("take OpenID Connect login")?;
+        diesel::delete(
+            oidc_logins::table
+                .filter(oidc_logins::state_hash.eq(hash_token(&conn, state)?))
+                .filter(oidc_logins::expires.gt(chrono::Utc::now().naive_utc())),
+        )
+        .returning((oidc_logins::nonce, oidc_logins::code_verifier))
+        .get_result::<(String, String)>(&conn)
+        .optional()
+        .map_err(|err| DbError::query_error("take OpenID Connect login", err))?
+        .ok_or_else(|| DbError::not_found("OpenID Connect login"))
+    }
+
+    /// User linked to the identity `subject` of `issuer`. Identity seen for the first time is linked to the user
+    /// with email of `new_user` when `link_by_email` is set, so the email has to be verified by the provider, or
+    /// to `new_user` which is created. Username of the created user gets numeric suffix when it is already taken.
+    #[tracing::instrument(skip(self))]
+    pub fn link_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        mut new_user: NewUser,
+        link_by_email: bool,
+    ) -> DbResult<User> {
+        let conn = self.get_conn("link identity")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let linked = user_identities::table
+                .inner_join(users::table)
+                .filter(user_identities::issuer.eq(issuer))
+                .filter(user_identities::subject.eq(subject))
+                .select(users::all_columns)
+                .first::<User>(&conn)
+                .optional()?;
+            if let Some(user) = linked {
+                return Ok(user);
+            }
+
+            let existing = match &new_user.email {
+                Some(address) => users::table
+                    .filter(users::email.eq(address))
+                    .first::<User>(&conn)
+                    .optional()?,
+                None => None,
+            };
+            // whoever registered with the address without verifying it does not get the identity either
+            let existing = match existing {
+                Some(user) if !link_by_email || user.email_unverified() => {
+                    tracing::warn!(user_id = user.id, "email of new identity belongs to another user");
+                    new_user.email = None;
+                    new_user.email_verified = None;
+                    None
+                }
+                existing => existing,
+            };
+            let user = match existing {
+                Some(user) => {
+                    tracing::info!(user_id = user.id, "linking identity to user with the same email");
+                    user
+                }
+                None => {
+                    let taken = users::table
+                        .filter(
+                            users::username
+                                .eq(&new_user.username)
+                                .or(users::username.like(format!("{}-%", new_user.username))),
+                        )
+                        .select(users::username)
+                        .load::<String>(&conn)?;
+                    if let Some(free) = std::iter::once(new_user.username.clone())
+                        .chain((2..).map(|n| format!("{}-{}", new_user.username, n)))
+                        .find(|candidate| !taken.contains(candidate))
+                    {
+                        new_user.username = free;
+                    }
+                    // hashed only for created user, Argon2 would slow down every login of a linked identity
+                    new_user.password = self.hashing.hash(&new_user.password)?;
+                    let user = insert_new_user(&conn, new_user)?;
+                    tracing::info!(user_id = user.id, username = %user.username, "created user of new identity");
+                    user
+                }
+            };
+
+            diesel::insert_into(user_identities::table)
+                .values((
+                    user_identities::user_id.eq(user.id),
+                    user_identities::issuer.eq(issuer),
+                    user_identities::subject.eq(subject),
+                ))
+                .execute(&conn)
+                .map_err(|err| DbError::insert_error("user_identities", err))?;
+            Ok(user)
+        })
+    }
+}
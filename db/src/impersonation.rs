@@ -0,0 +1,58 @@
+use crate::dbo::ImpersonationRecord;
+use crate::errors::{DbError, DbResult};
+use crate::schema::impersonation_audit;
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    /// Records request of `actor_id` made as `user_id` before it is handled, so the trail does not depend on
+    /// the request succeeding. Returns id of the record to complete with [`Db::complete_impersonation`].
+    #[tracing::instrument(skip(self))]
+    pub fn record_impersonation(
+        &self,
+        actor_id: i32,
+        user_id: i32,
+        method: &str,
+        path: &str,
+    ) -> DbResult<i32> {
+        diesel::insert_into(impersonation_audit::table)
+            .values((
+                impersonation_audit::actor_id.eq(actor_id),
+                impersonation_audit::user_id.eq(user_id),
+                impersonation_audit::method.eq(method),
+                impersonation_audit::path.eq(path),
+            ))
+            .returning(impersonation_audit::id)
+            .get_result::<i32>(&self.get_conn("record impersonation")?)
+            .map_err(|err| DbError::insert_error("impersonation_audit", err))
+    }
+
+    /// Sets response status of the recorded request.
+    #[tracing::instrument(skip(self))]
+    pub fn complete_impersonation(&self, id: i32, status: i16) -> DbResult<()> {
+        diesel::update(impersonation_audit::table.find(id))
+            .set(impersonation_audit::status.eq(status))
+            .execute(&self.get_conn("complete impersonation")?)
+            .map(|_| ())
+            .map_err(|err| DbError::update_error("impersonation status", err))
+    }
+
+    /// Audit trail of impersonations, the latest first, optionally only of the impersonated user.
+    #[tracing::instrument(skip(self))]
+    pub fn select_impersonations(
+        &self,
+        user_id: Option<i32>,
+        limit: i64,
+    ) -> DbResult<Vec<ImpersonationRecord>> {
+        let mut query = impersonation_audit::table
+            .order(impersonation_audit::id.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(user_id) = user_id {
+            query = query.filter(impersonation_audit::user_id.eq(user_id));
+        }
+        query
+            .load::<ImpersonationRecord>(&self.get_conn("select impersonations")?)
+            .map_err(|err| DbError::query_error("select impersonations", err))
+    }
+}
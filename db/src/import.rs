@@ -0,0 +1,196 @@
+use crate::dbo::{self, ExternalBatch, ImportSummary};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{external_refs, tickets, users};
+use crate::{insert_new_user, record_status_change, Db};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+pub(crate) const KIND_USER: &str = "user";
+pub(crate) const KIND_TICKET: &str = "ticket";
+
+impl Db {
+    /// Stores users and tickets coming from other issue tracker. Mapping between their original ids
+    /// and our ids is kept in `external_refs`, so importing the same data again updates already imported
+    /// tickets instead of creating duplicates. Whole batch is imported in single transaction.
+    #[tracing::instrument(skip(self, batch), fields(users = batch.users.len(), tickets = batch.tickets.len()))]
+    pub fn import_external(&self, source: &str, batch: ExternalBatch) -> DbResult<ImportSummary> {
+        let conn = self.get_conn("import external")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let mut summary = ImportSummary::default();
+
+            let mut user_ids = HashMap::new();
+            for user in batch.users {
+                let local_id = match find_ref(&conn, source, KIND_USER, &user.external_id)? {
+                    Some(local_id) => local_id,
+                    None => {
+                        let existing = users::table
+                            .filter(users::username.eq(&user.username))
+                            .select(users::id)
+                            .first::<i32>(&conn)
+                            .optional()?;
+
+                        let local_id = match existing {
+                            Some(local_id) => {
+                                summary.users_linked += 1;
+                                local_id
+                            }
+                            None => {
+                                summary.users_created += 1;
+                                insert_new_user(
+                                    &conn,
+                                    dbo::NewUser::new(
+                                        user.username.clone(),
+                                        self.hashing.hash(&user.password)?,
+                                        user.firstname.clone(),
+                                        user.lastname.clone(),
+                                    ),
+                                )?
+                                .id
+                            }
+                        };
+
+                        insert_ref(&conn, source, KIND_USER, &user.external_id, local_id)?;
+                        local_id
+                    }
+                };
+                user_ids.insert(user.external_id, local_id);
+            }
+
+            let mut ticket_ids = HashMap::new();
+            for ticket in batch.tickets.iter() {
+                let author_id = *user_ids.get(&ticket.author_external_id).ok_or_else(|| {
+                    DbError::Unknown(format!(
+                        "author '{}' of ticket '{}' is not part of imported users",
+                        ticket.author_external_id, ticket.external_id
+                    ))
+                })?;
+
+                let existing = match find_ref(&conn, source, KIND_TICKET, &ticket.external_id)? {
+                    Some(local_id) => tickets::table
+                        .find(local_id)
+                        .select(tickets::status)
+                        .for_update()
+                        .first::<i16>(&conn)
+                        .optional()?
+                        .map(|old_status| (local_id, old_status)),
+                    None => None,
+                };
+
+                let local_id = match existing {
+                    Some((local_id, old_status)) => {
+                        diesel::update(tickets::table.find(local_id))
+                            .set((
+                                tickets::author_id.eq(author_id),
+                                tickets::description.eq(&ticket.description),
+                                tickets::severity.eq(ticket.severity),
+                                tickets::status.eq(ticket.status),
+                                tickets::description_html.eq(None::<String>),
+                            ))
+                            .execute(&conn)?;
+                        if old_status != ticket.status {
+                            record_status_change(&conn, local_id, Some(old_status), ticket.status)?;
+                        }
+                        summary.tickets_updated += 1;
+                        local_id
+                    }
+                    None => {
+                        let created = ticket
+                            .created
+                            .unwrap_or_else(|| chrono::Local::now().naive_utc());
+                        // project is not known to other trackers, new tickets end up in the default one
+                        let local_id = diesel::insert_into(tickets::table)
+                            .values((
+                                tickets::author_id.eq(author_id),
+                                tickets::description.eq(&ticket.description),
+                                tickets::severity.eq(ticket.severity),
+                                tickets::status.eq(ticket.status),
+                                tickets::created.eq(created),
+                            ))
+                            .returning(tickets::id)
+                            .get_result::<i32>(&conn)?;
+                        record_status_change(&conn, local_id, None, ticket.status)?;
+                        // ticket previously imported and then deleted gets new id, so the stale reference goes away
+                        diesel::delete(
+                            external_refs::table
+                                .filter(external_refs::source.eq(source))
+                                .filter(external_refs::kind.eq(KIND_TICKET))
+                                .filter(external_refs::external_id.eq(&ticket.external_id)),
+                        )
+                        .execute(&conn)?;
+                        insert_ref(&conn, source, KIND_TICKET, &ticket.external_id, local_id)?;
+                        summary.tickets_created += 1;
+                        local_id
+                    }
+                };
+                ticket_ids.insert(ticket.external_id.as_str(), local_id);
+            }
+
+            // links can point to tickets imported later in the batch, so they are resolved once all tickets exist
+            for ticket in batch.tickets.iter().filter(|t| !t.links.is_empty()) {
+                let mut lines = Vec::with_capacity(ticket.links.len());
+                for link in ticket.links.iter() {
+                    let target = match ticket_ids.get(link.target_external_id.as_str()) {
+                        Some(local_id) => Some(*local_id),
+                        None => find_ref(&conn, source, KIND_TICKET, &link.target_external_id)?,
+                    };
+                    lines.push(match target {
+                        Some(local_id) => {
+                            format!("- {} #{} ({})", link.relation, local_id, link.target_label)
+                        }
+                        None => format!("- {} {}", link.relation, link.target_label),
+                    });
+                }
+
+                diesel::update(tickets::table.find(ticket_ids[ticket.external_id.as_str()]))
+                    .set((
+                        tickets::description.eq(format!(
+                            "{}\n\nLinks:\n{}",
+                            ticket.description,
+                            lines.join("\n")
+                        )),
+                        tickets::description_html.eq(None::<String>),
+                    ))
+                    .execute(&conn)?;
+            }
+
+            Ok(summary)
+        })
+        .map_err(|err| {
+            tracing::error!(%err, %source, "failed to import external data");
+            err
+        })
+    }
+}
+
+fn find_ref(
+    conn: &PgConnection,
+    source: &str,
+    kind: &str,
+    external_id: &str,
+) -> QueryResult<Option<i32>> {
+    external_refs::table
+        .filter(external_refs::source.eq(source))
+        .filter(external_refs::kind.eq(kind))
+        .filter(external_refs::external_id.eq(external_id))
+        .select(external_refs::local_id)
+        .first::<i32>(conn)
+        .optional()
+}
+
+fn insert_ref(
+    conn: &PgConnection,
+    source: &str,
+    kind: &str,
+    external_id: &str,
+    local_id: i32,
+) -> QueryResult<usize> {
+    diesel::insert_into(external_refs::table)
+        .values(&dbo::NewExternalRef {
+            source,
+            kind,
+            external_id,
+            local_id,
+        })
+        .execute(conn)
+}
@@ -3,15 +3,35 @@ extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
 
+mod assignment;
+// diesel 1.4 derives and macros implement traits inside of a const block, which current compilers warn about
+#[allow(non_local_definitions)]
 pub mod dbo;
+mod deactivation;
 pub mod errors;
+mod identity;
+mod impersonation;
+mod import;
+mod lockout;
+mod merge;
+mod mfa;
+mod password;
+mod profile;
+mod project;
+mod registration;
+mod report;
+#[allow(non_local_definitions)]
 mod schema;
+mod session;
+mod snapshot;
+mod team;
+mod token;
 
 use crate::schema::{
-    tickets::{dsl::*, table as tickets_table},
+    tickets::table as tickets_table,
     users::{dsl::*, table as users_table},
 };
-use dbo::{Ticket, User};
+use dbo::{ProjectScope, Ticket, TicketChanges, User};
 use diesel::sql_types::Text;
 use diesel::{
     pg::PgConnection,
@@ -19,16 +39,28 @@ use diesel::{
     r2d2::{ConnectionManager, Pool},
 };
 use errors::{DbError, DbResult};
+pub use password::PasswordHashing;
 use tracing::trace;
 
 embed_migrations!("../migrations");
 
-diesel::sql_function!(fn crypt(pass: Text, salt: Text) -> Text);
+/// Postgres functions used in queries, diesel implements traits for them inside of a const block as for derives.
+#[allow(non_local_definitions)]
+mod functions {
+    use diesel::sql_types::{Bytea, Text};
+
+    // verifies password hashes computed before hashing moved out of the database
+    diesel::sql_function!(fn crypt(pass: Text, salt: Text) -> Text);
+    diesel::sql_function!(fn digest(data: Text, algorithm: Text) -> Bytea);
+    diesel::sql_function!(fn encode(data: Bytea, format: Text) -> Text);
+}
+use functions::crypt;
 
 type PgPool = Pool<ConnectionManager<PgConnection>>;
 
 pub struct Db {
     inner: PgPool,
+    hashing: PasswordHashing,
 }
 
 impl Db {
@@ -42,7 +74,10 @@ impl Db {
             .build(manager)
             .map(|conn| {
                 tracing::trace!("connected to DB");
-                Db { inner: conn }
+                Db {
+                    inner: conn,
+                    hashing: PasswordHashing::default(),
+                }
             })
             .map_err(|err| DbError::connection_error(uri, err))?;
 
@@ -54,6 +89,13 @@ impl Db {
         Ok(db)
     }
 
+    /// Parameters new password hashes are computed with, recommended defaults are used otherwise.
+    pub fn with_password_hashing(mut self, hashing: PasswordHashing) -> DbResult<Self> {
+        hashing.hash("")?;
+        self.hashing = hashing;
+        Ok(self)
+    }
+
     #[tracing::instrument(skip(self))]
     fn get_conn(
         &self,
@@ -80,110 +122,468 @@ impl Db {
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn insert_user(&self, user: dbo::NewUser) -> DbResult<User> {
-        diesel::insert_into(users_table)
-            .values((
-                username.eq(user.username),
-                password.eq(crypt(user.password, "gen_salt('bf', 8)")),
-                firstname.eq(user.firstname),
-                lastname.eq(user.lastname),
-            ))
-            .get_result::<User>(&self.get_conn("insert user")?)
+    pub fn insert_user(&self, mut user: dbo::NewUser) -> DbResult<User> {
+        user.password = self.hashing.hash(&user.password)?;
+        let conn = self.get_conn("insert user")?;
+        conn.transaction(|| insert_new_user(&conn, user))
             .map_err(|e| DbError::insert_error("user", e))
-            .and_then(|u| {
-                trace!("inserted new user");
-                Ok(u)
-            })
+            .inspect(|_| trace!("inserted new user"))
     }
 
+    /// Password is left as it is, it is changed only by [`Db::change_password`] or password reset.
+    /// Email is written only when it differs from the stored one, together with `email_verified` of `user`, so
+    /// changed address is unverified unless the caller vouches for it.
     #[tracing::instrument(skip(self))]
     pub fn update_user(&self, user: &User) -> DbResult<()> {
-        diesel::update(users_table.find(user.id))
-            .set(user)
-            .execute(&self.get_conn("update user")?)
-            .and_then(|rows_affected| {
-                tracing::debug!(%rows_affected, "updated user");
-                match rows_affected {
-                    0 => Err(diesel::NotFound),
-                    _ => Ok(()),
-                }
-            })
-            .map_err(|err| DbError::update_error("user", err))
+        let conn = self.get_conn("update user")?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let stored_email = users_table
+                .find(user.id)
+                .select(email)
+                .for_update()
+                .first::<Option<String>>(&conn)?;
+            let rows_affected = diesel::update(users_table.find(user.id))
+                .set((
+                    username.eq(&user.username),
+                    firstname.eq(&user.firstname),
+                    lastname.eq(&user.lastname),
+                ))
+                .execute(&conn)?;
+            if stored_email != user.email {
+                diesel::update(users_table.find(user.id))
+                    .set((
+                        email.eq(&user.email),
+                        email_verified.eq(user.email_verified),
+                    ))
+                    .execute(&conn)?;
+            }
+            tracing::debug!(%rows_affected, "updated user");
+            Ok(())
+        })
+        .map_err(|err| DbError::update_error("user", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn update_user_role(&self, user_id: i32, new_role: &str) -> DbResult<User> {
+        diesel::update(users_table.find(user_id))
+            .set(role.eq(new_role))
+            .get_result::<User>(&self.get_conn("update user role")?)
+            .map_err(|err| DbError::query_error("update user role", err))
     }
 
+    /// Deletes only user without any activity, who wrote, was assigned or impersonated is deactivated instead so
+    /// they are still shown, see [`Db::deactivate_user`].
     #[tracing::instrument(skip(self))]
     pub fn delete_user(&self, user_id: i32) -> DbResult<usize> {
-        diesel::delete(users_table.filter(crate::schema::users::id.eq(user_id)))
-            .execute(&self.get_conn("delete user")?)
-            .and_then(|rows_affected| {
-                tracing::debug!(%rows_affected, "delete user");
-                match rows_affected {
-                    0 => Err(diesel::NotFound),
-                    _ => Ok(rows_affected),
-                }
-            })
-            .map_err(|err| DbError::query_error("delete user", err))
+        use crate::schema::{impersonation_audit, tickets};
+
+        let conn = self.get_conn("delete user")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let has_tickets = diesel::select(diesel::dsl::exists(
+                tickets::table.filter(
+                    tickets::author_id
+                        .eq(user_id)
+                        .or(tickets::assignee_id.eq(user_id)),
+                ),
+            ))
+            .get_result::<bool>(&conn)?;
+            let was_impersonated = diesel::select(diesel::dsl::exists(
+                impersonation_audit::table.filter(
+                    impersonation_audit::user_id
+                        .eq(user_id)
+                        .or(impersonation_audit::actor_id.eq(user_id)),
+                ),
+            ))
+            .get_result::<bool>(&conn)?;
+            if has_tickets || was_impersonated {
+                tracing::warn!(%user_id, has_tickets, was_impersonated, "refused to delete user with activity");
+                return Err(DbError::Conflict("user has tickets or impersonation audit"));
+            }
+
+            let rows_affected =
+                diesel::delete(users_table.filter(crate::schema::users::id.eq(user_id)))
+                    .execute(&conn)?;
+            tracing::debug!(%rows_affected, "delete user");
+            match rows_affected {
+                0 => Err(diesel::NotFound.into()),
+                _ => Ok(rows_affected),
+            }
+        })
+        .map_err(|err| DbError::query_error("delete user", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_user_ids_by_username(
+        &self,
+        usernames: Vec<String>,
+    ) -> DbResult<Vec<(String, i32)>> {
+        users_table
+            .filter(schema::users::username.eq_any(usernames))
+            .select((schema::users::username, schema::users::id))
+            .load::<(String, i32)>(&self.get_conn("select user ids by username")?)
+            .map_err(|err| DbError::query_error("select user ids by username", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_existing_user_ids(&self, user_ids: Vec<i32>) -> DbResult<Vec<i32>> {
+        users_table
+            .filter(schema::users::id.eq_any(user_ids))
+            .select(schema::users::id)
+            .load::<i32>(&self.get_conn("select existing user ids")?)
+            .map_err(|err| DbError::query_error("select existing user ids", err))
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn select_tickets(&self) -> DbResult<Vec<Ticket>> {
-        tickets
+    pub fn select_tickets(
+        &self,
+        scope: &ProjectScope,
+        filter: &dbo::TicketFilter,
+    ) -> DbResult<Vec<Ticket>> {
+        filtered_tickets(scope, filter)
+            .order(schema::tickets::id)
             .load::<Ticket>(&self.get_conn("select tickets")?)
             .map_err(|err| DbError::query_error("select tickets", err)) //todo we should probably limit this to some reasonable amount
     }
 
+    /// Keyset paginated variant of [`Db::select_tickets`], returns at most `limit` tickets with id greater than `after_id`.
+    #[tracing::instrument(skip(self))]
+    pub fn select_tickets_page(
+        &self,
+        scope: &ProjectScope,
+        filter: &dbo::TicketFilter,
+        after_id: i32,
+        limit: i64,
+    ) -> DbResult<Vec<Ticket>> {
+        filtered_tickets(scope, filter)
+            .filter(schema::tickets::id.gt(after_id))
+            .order(schema::tickets::id)
+            .limit(limit)
+            .load::<Ticket>(&self.get_conn("select tickets page")?)
+            .map_err(|err| DbError::query_error("select tickets page", err))
+    }
+
     #[tracing::instrument(skip(self))]
-    pub fn select_ticket(&self, ticket_id: i32) -> DbResult<Ticket> {
-        tickets_table
+    pub fn select_ticket(&self, scope: &ProjectScope, ticket_id: i32) -> DbResult<Ticket> {
+        scoped_tickets(scope)
             .filter(schema::tickets::id.eq(ticket_id))
             .first::<Ticket>(&self.get_conn("select ticket")?)
             .map_err(|err| DbError::query_error("select ticket", err))
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn insert_ticket(&self, ticket: dbo::NewTicket) -> DbResult<()> {
-        diesel::insert_into(tickets_table)
-            .values(&ticket)
-            .execute(&self.get_conn("insert ticket")?)
-            .map_err(|err| DbError::insert_error("tickets", err))
-            .map(|rows_affected| {
-                tracing::debug!(rows_affected, "inserted new ticket");
-            })
+    pub fn insert_ticket(&self, scope: &ProjectScope, ticket: dbo::NewTicket) -> DbResult<Ticket> {
+        if !scope.allows(ticket.project_id) {
+            return Err(DbError::not_found("project"));
+        }
+
+        let conn = self.get_conn("insert ticket")?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let inserted = diesel::insert_into(tickets_table)
+                .values(&ticket)
+                .get_result::<Ticket>(&conn)?;
+            record_status_change(&conn, inserted.id, None, inserted.status)?;
+            Ok(inserted)
+        })
+        .map_err(|err| DbError::insert_error("tickets", err))
+        .inspect(|inserted| {
+            tracing::debug!(ticket_id = inserted.id, "inserted new ticket");
+        })
+    }
+
+    /// Tickets whose description is similar to `text` by trigram similarity (`pg_trgm`), most similar first.
+    #[tracing::instrument(skip(self, text))]
+    pub fn select_similar_tickets(
+        &self,
+        scope: &ProjectScope,
+        text: &str,
+        threshold: f32,
+        limit: i64,
+    ) -> DbResult<Vec<dbo::SimilarTicket>> {
+        let conn = self.get_conn("select similar tickets")?;
+        conn.transaction::<_, DbError, _>(|| {
+            // `%` uses the trigram index unlike comparing `similarity()`, its threshold is set for this transaction only
+            diesel::sql_query("SELECT set_config('pg_trgm.similarity_threshold', $1, true)")
+                .bind::<Text, _>(threshold.to_string())
+                .execute(&conn)?;
+            diesel::sql_query(
+                r#"
+                SELECT id, description, status, similarity(description, $1) AS similarity
+                FROM tickets
+                WHERE description % $1
+                  AND ($3::int[] IS NULL OR project_id = ANY($3))
+                ORDER BY similarity DESC, id
+                LIMIT $2
+                "#,
+            )
+            .bind::<Text, _>(text)
+            .bind::<diesel::sql_types::BigInt, _>(limit)
+            .bind::<ScopeIds, _>(scope_ids(scope))
+            .load::<dbo::SimilarTicket>(&conn)
+            .map_err(DbError::from)
+        })
+        .map_err(|err| DbError::query_error("select similar tickets", err))
+    }
+
+    /// Inserts all tickets in single transaction, either all of them are stored or none.
+    #[tracing::instrument(skip(self, new_tickets), fields(count = new_tickets.len()))]
+    pub fn insert_tickets(
+        &self,
+        scope: &ProjectScope,
+        new_tickets: Vec<dbo::NewTicket>,
+    ) -> DbResult<usize> {
+        if new_tickets.iter().any(|t| !scope.allows(t.project_id)) {
+            return Err(DbError::not_found("project"));
+        }
+
+        let conn = self.get_conn("insert tickets")?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let mut inserted_count = 0;
+            // keep number of bind parameters per statement well below Postgres limit
+            for chunk in new_tickets.chunks(1000) {
+                let inserted = diesel::insert_into(tickets_table)
+                    .values(chunk)
+                    .get_results::<Ticket>(&conn)?;
+                for ticket in inserted.iter() {
+                    record_status_change(&conn, ticket.id, None, ticket.status)?;
+                }
+                inserted_count += inserted.len();
+            }
+            Ok(inserted_count)
+        })
+        .map_err(|err| DbError::insert_error("tickets", err))
+        .inspect(|&inserted_count| {
+            tracing::debug!(inserted_count, "inserted tickets");
+        })
     }
 
+    /// Both the project the ticket is in and the project it moves to have to be in `scope`.
     #[tracing::instrument(skip(self))]
-    pub fn update_ticket(&self, ticket: Ticket) -> DbResult<()> {
-        diesel::update(tickets_table.find(ticket.id))
-            .set(&ticket)
-            .execute(&self.get_conn("update ticket")?)
-            .map_err(|err| DbError::update_error("ticket", err))
-            .map(|rows_affected| tracing::debug!(%rows_affected, "updated ticket"))
+    pub fn update_ticket(&self, scope: &ProjectScope, ticket: TicketChanges) -> DbResult<()> {
+        if !ticket
+            .project_id
+            .is_none_or(|project_id| scope.allows(project_id))
+        {
+            return Err(DbError::not_found("project"));
+        }
+
+        let conn = self.get_conn("update ticket")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let (old_status, old_description) = tickets_table
+                .find(ticket.id)
+                .select((
+                    schema::tickets::status,
+                    schema::tickets::description,
+                    schema::tickets::project_id,
+                ))
+                .for_update()
+                .first::<(i16, String, i32)>(&conn)
+                .optional()?
+                .filter(|(_, _, old_project)| scope.allows(*old_project))
+                .map(|(old_status, old_description, _)| (old_status, old_description))
+                .ok_or_else(|| DbError::not_found("ticket"))?;
+
+            let rows_affected = diesel::update(tickets_table.find(ticket.id))
+                .set(&ticket)
+                .execute(&conn)?;
+
+            if old_description != ticket.description {
+                diesel::update(tickets_table.find(ticket.id))
+                    .set(schema::tickets::description_html.eq(None::<String>))
+                    .execute(&conn)?;
+            }
+
+            if old_status != ticket.status {
+                record_status_change(&conn, ticket.id, Some(old_status), ticket.status)?;
+            }
+
+            Ok(rows_affected)
+        })
+        .map(|rows_affected| tracing::debug!(%rows_affected, "updated ticket"))
+    }
+
+    /// Stores rendered description, unless the description was changed after it was rendered.
+    #[tracing::instrument(skip(self, rendered_description, html))]
+    pub fn cache_description_html(
+        &self,
+        ticket_id: i32,
+        rendered_description: &str,
+        html: &str,
+    ) -> DbResult<bool> {
+        diesel::update(
+            tickets_table
+                .find(ticket_id)
+                .filter(schema::tickets::description.eq(rendered_description)),
+        )
+        .set(schema::tickets::description_html.eq(html))
+        .execute(&self.get_conn("cache description html")?)
+        .map_err(|err| DbError::update_error("ticket description html", err))
+        .map(|rows_affected| rows_affected == 1)
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn delete_ticket(&self, ticket_id: i32) -> DbResult<usize> {
-        diesel::delete(tickets_table.find(ticket_id))
-            .execute(&self.get_conn("delete ticket")?)
+    pub fn delete_ticket(&self, scope: &ProjectScope, ticket_id: i32) -> DbResult<usize> {
+        let conn = self.get_conn("delete ticket")?;
+        let deleted = match scope.project_ids() {
+            None => diesel::delete(tickets_table.find(ticket_id)).execute(&conn),
+            Some(ids) => diesel::delete(
+                tickets_table
+                    .find(ticket_id)
+                    .filter(schema::tickets::project_id.eq_any(ids.to_vec())),
+            )
+            .execute(&conn),
+        };
+
+        deleted
+            .and_then(|rows_affected| match rows_affected {
+                0 => Err(diesel::NotFound),
+                _ => Ok(rows_affected),
+            })
             .map_err(|err| DbError::query_error("delete ticket", err))
     }
 
+    /// History of the ticket, oldest entry first.
+    #[tracing::instrument(skip(self))]
+    pub fn select_ticket_history(&self, ticket_id: i32) -> DbResult<Vec<dbo::TicketHistory>> {
+        schema::ticket_history::table
+            .filter(schema::ticket_history::ticket_id.eq(ticket_id))
+            .order(schema::ticket_history::id)
+            .load::<dbo::TicketHistory>(&self.get_conn("select ticket history")?)
+            .map_err(|err| DbError::query_error("select ticket history", err))
+    }
+
+    /// Finds user with given username and password. Password hash is recomputed when it is not Argon2id with
+    /// current parameters, so hashes get upgraded as users log in.
     #[tracing::instrument(skip(self, pwd))]
     pub fn check_credentials(&self, usr: &str, pwd: &str) -> DbResult<dbo::User> {
-        let query = users_table
+        let conn = self.get_conn("user auth")?;
+        let user = users_table
             .filter(schema::users::username.eq(usr))
-            .filter(schema::users::password.eq(crypt(pwd, "gen_salt('bf', 8)")));
+            .first::<dbo::User>(&conn)
+            .optional()
+            .map_err(|err| DbError::query_error("user auth", err))?;
 
-        // tracing::trace!(?query, "user authentication query"); this will also log passwords, so we cannot really keep it here
+        let mut user = match user {
+            Some(user) => user,
+            None => {
+                // hash anyway, so unknown usernames cannot be told apart by the response time
+                self.hashing.hash(pwd)?;
+                return Err(DbError::not_found("user"));
+            }
+        };
 
-        let mut found = query
-            .load::<dbo::User>(&self.get_conn("user auth")?)
-            .map_err(|err| DbError::query_error("delete ticket", err))?;
-
-        match found.len() {
-            0 => Err(DbError::not_found("user")),
-            1 => Ok(found.pop().unwrap()),
-            // if we find more users with same name and password then we've reached some kind of invalid state
-            _ => Err(DbError::InvalidResult),
+        match password::verify(&conn, &self.hashing, pwd, &user.password)? {
+            password::Verification::Valid => Ok(user),
+            password::Verification::Invalid => Err(DbError::not_found("user")),
+            password::Verification::Outdated => {
+                tracing::info!(user_id = user.id, "rehashing outdated password hash");
+                let new_hash = self.hashing.hash(pwd)?;
+                // concurrent login could have rehashed it already, either hash is fine then
+                diesel::update(
+                    users_table
+                        .find(user.id)
+                        .filter(password.eq(&user.password)),
+                )
+                .set(password.eq(&new_hash))
+                .execute(&conn)
+                .map_err(|err| DbError::update_error("password hash", err))?;
+                user.password = new_hash;
+                Ok(user)
+            }
         }
     }
 }
+
+/// `user.password` has to be hashed already. New user becomes member of the default project with their
+/// role, like users existing before projects, so they can file tickets right away. Has to run in transaction.
+fn insert_new_user(conn: &PgConnection, user: dbo::NewUser) -> QueryResult<User> {
+    let user = diesel::insert_into(users_table)
+        .values((
+            username.eq(user.username),
+            password.eq(user.password),
+            firstname.eq(user.firstname),
+            lastname.eq(user.lastname),
+            role.eq(user.role),
+            email.eq(user.email),
+            email_verified.eq(user.email_verified),
+        ))
+        .get_result::<User>(conn)?;
+    diesel::insert_into(schema::project_members::table)
+        .values((
+            schema::project_members::project_id.eq(dbo::DEFAULT_PROJECT_ID),
+            schema::project_members::user_id.eq(user.id),
+            schema::project_members::role.eq(&user.role),
+        ))
+        .execute(conn)?;
+    Ok(user)
+}
+
+/// Tickets of projects in `scope`, everything else is filtered out as if it did not exist.
+fn scoped_tickets(scope: &ProjectScope) -> schema::tickets::BoxedQuery<'static, diesel::pg::Pg> {
+    let mut query = tickets_table.into_boxed();
+    if let Some(ids) = scope.project_ids() {
+        query = query.filter(schema::tickets::project_id.eq_any(ids.to_vec()));
+    }
+    query
+}
+
+/// SQL type of `scope_ids`, used by raw queries which filter by project as `$n::int[] IS NULL OR project_id = ANY($n)`.
+type ScopeIds = diesel::sql_types::Nullable<diesel::sql_types::Array<diesel::sql_types::Integer>>;
+
+fn scope_ids(scope: &ProjectScope) -> Option<Vec<i32>> {
+    scope.project_ids().map(<[i32]>::to_vec)
+}
+
+fn filtered_tickets(
+    scope: &ProjectScope,
+    filter: &dbo::TicketFilter,
+) -> schema::tickets::BoxedQuery<'static, diesel::pg::Pg> {
+    let mut query = scoped_tickets(scope);
+
+    if let Some(project) = filter.project_id {
+        query = query.filter(schema::tickets::project_id.eq(project));
+    }
+    if let Some(author) = filter.author_id {
+        query = query.filter(schema::tickets::author_id.eq(author));
+    }
+    if let Some(ticket_status) = filter.status {
+        query = query.filter(schema::tickets::status.eq(ticket_status));
+    }
+    if let Some(ticket_severity) = filter.severity {
+        query = query.filter(schema::tickets::severity.eq(ticket_severity));
+    }
+    if let Some(assignee) = filter.assignee_id {
+        query = query.filter(schema::tickets::assignee_id.eq(assignee));
+    }
+    if let Some(team) = filter.assignee_team_id {
+        query = query.filter(schema::tickets::assignee_team_id.eq(team));
+    }
+    if let Some(member) = filter.assigned_to_teams_of {
+        query = query.filter(
+            schema::tickets::assignee_team_id.eq_any(
+                schema::team_members::table
+                    .filter(schema::team_members::user_id.eq(member))
+                    .select(schema::team_members::team_id.nullable()),
+            ),
+        );
+    }
+
+    query
+}
+
+/// Every status a ticket goes through is kept in `ticket_history`, reports are computed from it.
+fn record_status_change(
+    conn: &PgConnection,
+    ticket_id: i32,
+    old_status: Option<i16>,
+    new_status: i16,
+) -> QueryResult<usize> {
+    diesel::insert_into(schema::ticket_history::table)
+        .values(&dbo::NewTicketHistory {
+            ticket_id,
+            old_status,
+            new_status,
+            event: dbo::history_event::STATUS,
+            related_ticket_id: None,
+        })
+        .execute(conn)
+}
@@ -0,0 +1,67 @@
+use crate::dbo::LoginFailures;
+use crate::errors::{DbError, DbResult};
+use crate::schema::login_failures;
+use crate::Db;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+
+impl Db {
+    /// Until when logins of `key` (see [`crate::dbo::login_subject`]) are refused, `None` when they are not.
+    #[tracing::instrument(skip(self))]
+    pub fn login_locked_until(
+        &self,
+        kind: &str,
+        key: &str,
+    ) -> DbResult<Option<chrono::NaiveDateTime>> {
+        login_failures::table
+            .find((kind, key))
+            .select(login_failures::locked_until)
+            .filter(login_failures::locked_until.gt(chrono::Utc::now().naive_utc()))
+            .first::<Option<chrono::NaiveDateTime>>(&self.get_conn("select login lock")?)
+            .optional()
+            .map(Option::flatten)
+            .map_err(|err| DbError::query_error("select login lock", err))
+    }
+
+    /// Counts failed login and returns number of failures in a row. Failures older than `window_secs`
+    /// are forgotten.
+    #[tracing::instrument(skip(self))]
+    pub fn record_login_failure(&self, kind: &str, key: &str, window_secs: i64) -> DbResult<i32> {
+        diesel::sql_query(
+            r#"
+            INSERT INTO login_failures (kind, key)
+            VALUES ($1, $2)
+            ON CONFLICT (kind, key) DO UPDATE
+            SET failures = CASE
+                    WHEN login_failures.last_failure < NOW() - make_interval(secs => $3) THEN 1
+                    ELSE login_failures.failures + 1
+                END,
+                last_failure = NOW()
+            RETURNING failures
+            "#,
+        )
+        .bind::<Text, _>(kind)
+        .bind::<Text, _>(key)
+        .bind::<BigInt, _>(window_secs)
+        .get_result::<LoginFailures>(&self.get_conn("record login failure")?)
+        .map(|f| f.failures)
+        .map_err(|err| DbError::query_error("record login failure", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn lock_login(&self, kind: &str, key: &str, until: chrono::NaiveDateTime) -> DbResult<()> {
+        diesel::update(login_failures::table.find((kind, key)))
+            .set(login_failures::locked_until.eq(until))
+            .execute(&self.get_conn("lock login")?)
+            .map(|_| ())
+            .map_err(|err| DbError::update_error("login lock", err))
+    }
+
+    /// Forgets failed logins of `key`, which also lifts its lock.
+    #[tracing::instrument(skip(self))]
+    pub fn clear_login_failures(&self, kind: &str, key: &str) -> DbResult<usize> {
+        diesel::delete(login_failures::table.find((kind, key)))
+            .execute(&self.get_conn("clear login failures")?)
+            .map_err(|err| DbError::query_error("clear login failures", err))
+    }
+}
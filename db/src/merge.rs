@@ -0,0 +1,104 @@
+use crate::dbo::{self, history_event, resolution, status, ProjectScope, Ticket};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{ticket_history, ticket_watchers, tickets};
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    /// Closes `source_id` as duplicate of `target_id`. Source keeps pointing to the target through
+    /// `merged_into`, both tickets get the merge recorded in their history and watchers move to the target.
+    /// Returns the target ticket. Both tickets have to be within `scope`.
+    #[tracing::instrument(skip(self))]
+    pub fn merge_ticket(
+        &self,
+        scope: &ProjectScope,
+        source_id: i32,
+        target_id: i32,
+    ) -> DbResult<Ticket> {
+        if source_id == target_id {
+            return Err(DbError::InvalidData(
+                "ticket cannot be merged into itself".into(),
+            ));
+        }
+
+        let conn = self.get_conn("merge ticket")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let mut locked = tickets::table
+                .filter(tickets::id.eq_any(vec![source_id, target_id]))
+                .order(tickets::id)
+                .for_update()
+                .load::<Ticket>(&conn)?;
+            locked.retain(|t| scope.allows(t.project_id));
+
+            let source_idx = locked
+                .iter()
+                .position(|t| t.id == source_id)
+                .ok_or_else(|| DbError::not_found("merged ticket"))?;
+            let source = locked.remove(source_idx);
+            let target = locked
+                .pop()
+                .ok_or_else(|| DbError::not_found("ticket to merge into"))?;
+
+            if let Some(merged_into) = source.merged_into {
+                return Err(DbError::InvalidData(format!(
+                    "ticket {} is already merged into {}",
+                    source.id, merged_into
+                )));
+            }
+            if let Some(merged_into) = target.merged_into {
+                return Err(DbError::InvalidData(format!(
+                    "ticket {} is merged into {}, merge into that one instead",
+                    target.id, merged_into
+                )));
+            }
+
+            diesel::update(tickets::table.find(source.id))
+                .set((
+                    tickets::status.eq(status::CLOSED),
+                    tickets::resolution.eq(resolution::DUPLICATE),
+                    tickets::merged_into.eq(target.id),
+                ))
+                .execute(&conn)?;
+
+            // watchers of the duplicate follow the target, unless they already watch it
+            diesel::sql_query(
+                r#"
+                UPDATE ticket_watchers w
+                SET ticket_id = $2
+                WHERE w.ticket_id = $1
+                  AND NOT EXISTS(SELECT 1
+                                 FROM ticket_watchers t
+                                 WHERE t.ticket_id = $2
+                                   AND (t.user_id = w.user_id OR t.team_id = w.team_id))
+                "#,
+            )
+            .bind::<diesel::sql_types::Integer, _>(source.id)
+            .bind::<diesel::sql_types::Integer, _>(target.id)
+            .execute(&conn)?;
+            diesel::delete(ticket_watchers::table.filter(ticket_watchers::ticket_id.eq(source.id)))
+                .execute(&conn)?;
+
+            diesel::insert_into(ticket_history::table)
+                .values(&vec![
+                    dbo::NewTicketHistory {
+                        ticket_id: source.id,
+                        old_status: Some(source.status),
+                        new_status: status::CLOSED,
+                        event: history_event::MERGED_INTO,
+                        related_ticket_id: Some(target.id),
+                    },
+                    dbo::NewTicketHistory {
+                        ticket_id: target.id,
+                        old_status: Some(target.status),
+                        new_status: target.status,
+                        event: history_event::MERGED_FROM,
+                        related_ticket_id: Some(source.id),
+                    },
+                ])
+                .execute(&conn)?;
+
+            tracing::debug!(source_id, target_id, "ticket merged");
+            Ok(target)
+        })
+    }
+}
@@ -0,0 +1,138 @@
+use crate::dbo::UserTotp;
+use crate::errors::{DbError, DbResult};
+use crate::schema::{recovery_codes, user_totp};
+use crate::token::hash_token;
+use crate::Db;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+impl Db {
+    #[tracing::instrument(skip(self))]
+    pub fn select_totp(&self, user_id: i32) -> DbResult<Option<UserTotp>> {
+        user_totp::table
+            .find(user_id)
+            .first::<UserTotp>(&self.get_conn("select TOTP")?)
+            .optional()
+            .map_err(|err| DbError::query_error("select TOTP", err))
+    }
+
+    /// Stores new TOTP secret of the user, which is used only once it is confirmed by
+    /// [`Db::confirm_totp`]. Enrollment which was not confirmed yet is replaced.
+    #[tracing::instrument(skip(self, secret))]
+    pub fn start_totp_enrollment(&self, user_id: i32, secret: &str) -> DbResult<()> {
+        let conn = self.get_conn("start TOTP enrollment")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let confirmed = user_totp::table
+                .find(user_id)
+                .select(user_totp::confirmed)
+                .for_update()
+                .first::<Option<chrono::NaiveDateTime>>(&conn)
+                .optional()?
+                .flatten();
+            if confirmed.is_some() {
+                return Err(DbError::InvalidData(
+                    "two-factor authentication is already enabled".into(),
+                ));
+            }
+
+            diesel::delete(user_totp::table.find(user_id)).execute(&conn)?;
+            diesel::insert_into(user_totp::table)
+                .values((user_totp::user_id.eq(user_id), user_totp::secret.eq(secret)))
+                .execute(&conn)
+                .map(|_| ())
+                .map_err(|err| DbError::insert_error("user_totp", err))
+        })
+    }
+
+    /// Confirms enrollment with the time step of the first valid code and replaces recovery codes of the user.
+    /// Returns `false` when there is no enrollment to confirm.
+    #[tracing::instrument(skip(self, codes))]
+    pub fn confirm_totp(&self, user_id: i32, step: i64, codes: &[String]) -> DbResult<bool> {
+        let conn = self.get_conn("confirm TOTP")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let confirmed = diesel::update(
+                user_totp::table
+                    .find(user_id)
+                    .filter(user_totp::confirmed.is_null()),
+            )
+            .set((
+                user_totp::confirmed.eq(chrono::Utc::now().naive_utc()),
+                user_totp::last_step.eq(step),
+            ))
+            .execute(&conn)?;
+            if confirmed == 0 {
+                return Ok(false);
+            }
+
+            replace_recovery_codes(&conn, user_id, codes)?;
+            Ok(true)
+        })
+    }
+
+    /// Marks code of time `step` as used, `false` when the code of this or a later step was used already.
+    #[tracing::instrument(skip(self))]
+    pub fn accept_totp_step(&self, user_id: i32, step: i64) -> DbResult<bool> {
+        diesel::update(
+            user_totp::table
+                .find(user_id)
+                .filter(user_totp::confirmed.is_not_null())
+                .filter(
+                    user_totp::last_step
+                        .is_null()
+                        .or(user_totp::last_step.lt(step)),
+                ),
+        )
+        .set(user_totp::last_step.eq(step))
+        .execute(&self.get_conn("accept TOTP step")?)
+        .map(|rows| rows == 1)
+        .map_err(|err| DbError::update_error("user_totp", err))
+    }
+
+    /// Consumes recovery code of the user, `false` when it is unknown or used already.
+    #[tracing::instrument(skip(self, code))]
+    pub fn use_recovery_code(&self, user_id: i32, code: &str) -> DbResult<bool> {
+        let conn = self.get_conn("use recovery code")?;
+        diesel::update(
+            recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id))
+                .filter(recovery_codes::code_hash.eq(hash_token(&conn, code)?))
+                .filter(recovery_codes::used.is_null()),
+        )
+        .set(recovery_codes::used.eq(chrono::Utc::now().naive_utc()))
+        .execute(&conn)
+        .map(|rows| rows == 1)
+        .map_err(|err| DbError::update_error("recovery_codes", err))
+    }
+
+    /// Turns two-factor authentication of the user off, recovery codes are deleted too.
+    #[tracing::instrument(skip(self))]
+    pub fn delete_totp(&self, user_id: i32) -> DbResult<()> {
+        let conn = self.get_conn("delete TOTP")?;
+        conn.transaction::<_, DbError, _>(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                .execute(&conn)?;
+            diesel::delete(user_totp::table.find(user_id)).execute(&conn)?;
+            Ok(())
+        })
+    }
+}
+
+fn replace_recovery_codes(conn: &PgConnection, user_id: i32, codes: &[String]) -> DbResult<()> {
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+        .execute(conn)?;
+
+    let rows = codes
+        .iter()
+        .map(|code| {
+            Ok((
+                recovery_codes::user_id.eq(user_id),
+                recovery_codes::code_hash.eq(hash_token(conn, code)?),
+            ))
+        })
+        .collect::<DbResult<Vec<_>>>()?;
+    diesel::insert_into(recovery_codes::table)
+        .values(&rows)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|err| DbError::insert_error("recovery_codes", err))
+}
@@ -0,0 +1,191 @@
+use crate::dbo::User;
+use crate::errors::{DbError, DbResult};
+use crate::schema::{password_resets, users};
+use crate::token::{hash_token, revoke_user_tokens};
+use crate::{crypt, Db};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, Params, PasswordVerifier};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use std::convert::TryFrom;
+
+/// Argon2id parameters of newly computed password hashes. Hashes computed with other parameters still verify
+/// and are recomputed on the next successful login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordHashing {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashing {
+    /// minimum recommended by OWASP
+    fn default() -> Self {
+        PasswordHashing {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHashing {
+    fn argon2(&self) -> DbResult<Argon2<'static>> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map(|params| Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params))
+            .map_err(|err| DbError::InvalidData(format!("invalid Argon2 parameters: {}", err)))
+    }
+
+    pub(crate) fn hash(&self, password: &str) -> DbResult<String> {
+        self.argon2()?
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map(|hash| hash.to_string())
+            .map_err(|err| DbError::Unknown(format!("failed to hash password: {}", err)))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Verification {
+    Valid,
+    /// password matches but the hash should be recomputed with current parameters
+    Outdated,
+    Invalid,
+}
+
+/// Checks `password` against `stored` hash. Hashes which are not Argon2 were computed by pgcrypto `crypt`
+/// before hashing moved out of the database, those are verified by Postgres.
+pub(crate) fn verify(
+    conn: &PgConnection,
+    hashing: &PasswordHashing,
+    password: &str,
+    stored: &str,
+) -> DbResult<Verification> {
+    if !stored.starts_with("$argon2") {
+        let matches = diesel::select(crypt(password, stored).eq(stored))
+            .get_result::<bool>(conn)
+            .map_err(|err| DbError::query_error("verify legacy password hash", err))?;
+        return Ok(match matches {
+            true => Verification::Outdated,
+            false => Verification::Invalid,
+        });
+    }
+
+    let hash = PasswordHash::new(stored)
+        .map_err(|err| DbError::InvalidData(format!("malformed password hash: {}", err)))?;
+    // parameters are taken from the hash itself
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Ok(Verification::Invalid);
+    }
+
+    let current = hashing.argon2()?;
+    let up_to_date = hash.algorithm == argon2::Algorithm::Argon2id.ident()
+        && Params::try_from(&hash).is_ok_and(|params| &params == current.params());
+    Ok(match up_to_date {
+        true => Verification::Valid,
+        false => Verification::Outdated,
+    })
+}
+
+impl Db {
+    /// Replaces password of the user when `old_password` matches the current one. Refresh tokens of the user
+    /// are revoked, so other logins have to use the new password.
+    #[tracing::instrument(skip(self, old_password, new_password))]
+    pub fn change_password(
+        &self,
+        user_id: i32,
+        old_password: &str,
+        new_password: &str,
+    ) -> DbResult<()> {
+        let new_hash = self.hashing.hash(new_password)?;
+        let conn = self.get_conn("change password")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let current = users::table
+                .find(user_id)
+                .select(users::password)
+                .for_update()
+                .first::<String>(&conn)?;
+
+            if verify(&conn, &self.hashing, old_password, &current)? == Verification::Invalid {
+                return Err(DbError::not_found("user with given password"));
+            }
+
+            diesel::update(users::table.find(user_id))
+                .set(users::password.eq(new_hash))
+                .execute(&conn)?;
+            revoke_user_tokens(&conn, user_id)?;
+            Ok(())
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_user_by_email(&self, address: &str) -> DbResult<User> {
+        users::table
+            .filter(users::email.eq(address))
+            .first::<User>(&self.get_conn("select user by email")?)
+            .map_err(|err| DbError::query_error("select user by email", err))
+    }
+
+    /// Stores hash of a newly issued reset token, tokens issued to the user before stop working.
+    #[tracing::instrument(skip(self, token))]
+    pub fn insert_password_reset(
+        &self,
+        user_id: i32,
+        token: &str,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<()> {
+        let conn = self.get_conn("insert password reset")?;
+        conn.transaction::<_, DbError, _>(|| {
+            diesel::update(
+                password_resets::table
+                    .filter(password_resets::user_id.eq(user_id))
+                    .filter(password_resets::used.is_null()),
+            )
+            .set(password_resets::used.eq(chrono::Utc::now().naive_utc()))
+            .execute(&conn)?;
+
+            diesel::insert_into(password_resets::table)
+                .values((
+                    password_resets::user_id.eq(user_id),
+                    password_resets::token_hash.eq(hash_token(&conn, token)?),
+                    password_resets::expires.eq(expires),
+                ))
+                .execute(&conn)
+                .map(|_| ())
+                .map_err(|err| DbError::insert_error("password_resets", err))
+        })
+    }
+
+    /// Sets password of the user `token` was issued to. Token is consumed, unknown, used or expired token
+    /// is reported as not found.
+    #[tracing::instrument(skip(self, token, new_password))]
+    pub fn reset_password(&self, token: &str, new_password: &str) -> DbResult<User> {
+        let new_hash = self.hashing.hash(new_password)?;
+        let conn = self.get_conn("reset password")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let now = chrono::Utc::now().naive_utc();
+            let (reset_id, user_id) = password_resets::table
+                .filter(password_resets::token_hash.eq(hash_token(&conn, token)?))
+                .filter(password_resets::used.is_null())
+                .filter(password_resets::expires.gt(now))
+                .select((password_resets::id, password_resets::user_id))
+                .for_update()
+                .first::<(i32, i32)>(&conn)
+                .optional()?
+                .ok_or_else(|| DbError::not_found("password reset"))?;
+
+            diesel::update(password_resets::table.find(reset_id))
+                .set(password_resets::used.eq(now))
+                .execute(&conn)?;
+
+            let user = diesel::update(users::table.find(user_id))
+                .set(users::password.eq(new_hash))
+                .get_result::<User>(&conn)?;
+            revoke_user_tokens(&conn, user_id)?;
+
+            Ok(user)
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use crate::dbo::{Avatar, ProfileChange, User};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{user_avatars, users};
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    /// Changes profile fields of the user which are set in `change`, email is changed only by
+    /// [`Db::verify_email`].
+    #[tracing::instrument(skip(self))]
+    pub fn update_profile(&self, user_id: i32, change: &ProfileChange) -> DbResult<User> {
+        let conn = self.get_conn("update profile")?;
+        // diesel refuses update without any column to set
+        if change.is_empty() {
+            return users::table
+                .find(user_id)
+                .first::<User>(&conn)
+                .map_err(|err| DbError::query_error("select user", err));
+        }
+
+        diesel::update(users::table.find(user_id))
+            .set(change)
+            .get_result::<User>(&conn)
+            .map_err(|err| DbError::query_error("update profile", err))
+    }
+
+    /// Whether another user than `user_id` has `email`.
+    #[tracing::instrument(skip(self))]
+    pub fn is_email_taken(&self, email: &str, user_id: i32) -> DbResult<bool> {
+        diesel::select(diesel::dsl::exists(
+            users::table
+                .filter(users::email.eq(email))
+                .filter(users::id.ne(user_id)),
+        ))
+        .get_result::<bool>(&self.get_conn("check email")?)
+        .map_err(|err| DbError::query_error("check email", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_avatar(&self, user_id: i32) -> DbResult<Option<Avatar>> {
+        user_avatars::table
+            .find(user_id)
+            .first::<Avatar>(&self.get_conn("select avatar")?)
+            .optional()
+            .map_err(|err| DbError::query_error("select avatar", err))
+    }
+
+    /// Stores uploaded avatar of the user, replacing the previous one.
+    #[tracing::instrument(skip(self, data))]
+    pub fn upsert_avatar(&self, user_id: i32, content_type: &str, data: &[u8]) -> DbResult<()> {
+        let now = chrono::Utc::now().naive_utc();
+        diesel::insert_into(user_avatars::table)
+            .values((
+                user_avatars::user_id.eq(user_id),
+                user_avatars::content_type.eq(content_type),
+                user_avatars::data.eq(data),
+                user_avatars::updated.eq(now),
+            ))
+            .on_conflict(user_avatars::user_id)
+            .do_update()
+            .set((
+                user_avatars::content_type.eq(content_type),
+                user_avatars::data.eq(data),
+                user_avatars::updated.eq(now),
+            ))
+            .execute(&self.get_conn("upsert avatar")?)
+            .map(|_| ())
+            .map_err(|err| DbError::insert_error("user_avatars", err))
+    }
+
+    /// Deletes uploaded avatar, the user is shown with identicon again. Missing avatar is not an error.
+    #[tracing::instrument(skip(self))]
+    pub fn delete_avatar(&self, user_id: i32) -> DbResult<()> {
+        diesel::delete(user_avatars::table.find(user_id))
+            .execute(&self.get_conn("delete avatar")?)
+            .map(|_| ())
+            .map_err(|err| DbError::query_error("delete avatar", err))
+    }
+}
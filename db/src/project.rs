@@ -0,0 +1,105 @@
+use crate::dbo::{Project, ProjectMember, ProjectScope};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{project_members, project_teams, projects, team_members};
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    #[tracing::instrument(skip(self))]
+    pub fn select_projects(&self, scope: &ProjectScope) -> DbResult<Vec<Project>> {
+        let mut query = projects::table.into_boxed();
+        if let Some(ids) = scope.project_ids() {
+            query = query.filter(projects::id.eq_any(ids.to_vec()));
+        }
+
+        query
+            .order(projects::id)
+            .load::<Project>(&self.get_conn("select projects")?)
+            .map_err(|err| DbError::query_error("select projects", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn insert_project(&self, name: &str) -> DbResult<Project> {
+        diesel::insert_into(projects::table)
+            .values(projects::name.eq(name))
+            .get_result::<Project>(&self.get_conn("insert project")?)
+            .map_err(|err| DbError::insert_error("projects", err))
+    }
+
+    /// Projects the user is a member of, with the role the user has in them. Roles granted to teams of the user
+    /// are included too, so the same project can be listed more than once.
+    #[tracing::instrument(skip(self))]
+    pub fn select_memberships(&self, user_id: i32) -> DbResult<Vec<ProjectMember>> {
+        let conn = self.get_conn("select memberships")?;
+
+        let mut memberships = project_members::table
+            .filter(project_members::user_id.eq(user_id))
+            .order(project_members::project_id)
+            .load::<ProjectMember>(&conn)
+            .map_err(|err| DbError::query_error("select memberships", err))?;
+
+        let through_teams = project_teams::table
+            .inner_join(team_members::table.on(team_members::team_id.eq(project_teams::team_id)))
+            .filter(team_members::user_id.eq(user_id))
+            .select((
+                project_teams::project_id,
+                project_teams::role,
+                project_teams::created,
+            ))
+            .load::<(i32, String, chrono::NaiveDateTime)>(&conn)
+            .map_err(|err| DbError::query_error("select team memberships", err))?;
+
+        memberships.extend(
+            through_teams
+                .into_iter()
+                .map(|(project_id, role, created)| ProjectMember {
+                    project_id,
+                    user_id,
+                    role,
+                    created,
+                }),
+        );
+        Ok(memberships)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_project_members(&self, project_id: i32) -> DbResult<Vec<ProjectMember>> {
+        project_members::table
+            .filter(project_members::project_id.eq(project_id))
+            .order(project_members::user_id)
+            .load::<ProjectMember>(&self.get_conn("select project members")?)
+            .map_err(|err| DbError::query_error("select project members", err))
+    }
+
+    /// Adds the user to the project, or changes the role if the user already is a member.
+    #[tracing::instrument(skip(self))]
+    pub fn upsert_project_member(
+        &self,
+        project_id: i32,
+        user_id: i32,
+        role: &str,
+    ) -> DbResult<ProjectMember> {
+        diesel::insert_into(project_members::table)
+            .values((
+                project_members::project_id.eq(project_id),
+                project_members::user_id.eq(user_id),
+                project_members::role.eq(role),
+            ))
+            .on_conflict((project_members::project_id, project_members::user_id))
+            .do_update()
+            .set(project_members::role.eq(role))
+            .get_result::<ProjectMember>(&self.get_conn("upsert project member")?)
+            .map_err(|err| DbError::insert_error("project_members", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn delete_project_member(&self, project_id: i32, user_id: i32) -> DbResult<usize> {
+        diesel::delete(project_members::table.find((project_id, user_id)))
+            .execute(&self.get_conn("delete project member")?)
+            .and_then(|rows_affected| match rows_affected {
+                0 => Err(diesel::NotFound),
+                _ => Ok(rows_affected),
+            })
+            .map_err(|err| DbError::query_error("delete project member", err))
+    }
+}
@@ -0,0 +1,192 @@
+use crate::dbo::{Invitation, NewUser, User};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{email_verifications, invitations, users};
+use crate::token::hash_token;
+use crate::{insert_new_user, Db};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+impl Db {
+    /// Stores hash of a newly issued invitation token.
+    #[tracing::instrument(skip(self, token))]
+    pub fn insert_invitation(
+        &self,
+        token: &str,
+        email: Option<&str>,
+        role: &str,
+        invited_by: i32,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<Invitation> {
+        let conn = self.get_conn("insert invitation")?;
+        diesel::insert_into(invitations::table)
+            .values((
+                invitations::token_hash.eq(hash_token(&conn, token)?),
+                invitations::email.eq(email),
+                invitations::role.eq(role),
+                invitations::invited_by.eq(invited_by),
+                invitations::expires.eq(expires),
+            ))
+            .get_result::<Invitation>(&conn)
+            .map_err(|err| DbError::insert_error("invitations", err))
+    }
+
+    /// Invitations which were neither accepted nor expired, the newest first.
+    #[tracing::instrument(skip(self))]
+    pub fn select_open_invitations(&self) -> DbResult<Vec<Invitation>> {
+        invitations::table
+            .filter(invitations::accepted.is_null())
+            .filter(invitations::expires.gt(chrono::Utc::now().naive_utc()))
+            .order(invitations::id.desc())
+            .load::<Invitation>(&self.get_conn("select invitations")?)
+            .map_err(|err| DbError::query_error("select invitations", err))
+    }
+
+    /// Withdraws invitation which was not accepted yet.
+    #[tracing::instrument(skip(self))]
+    pub fn delete_invitation(&self, invitation_id: i32) -> DbResult<()> {
+        diesel::delete(
+            invitations::table
+                .find(invitation_id)
+                .filter(invitations::accepted.is_null()),
+        )
+        .execute(&self.get_conn("delete invitation")?)
+        .and_then(|rows_affected| match rows_affected {
+            0 => Err(diesel::NotFound),
+            _ => Ok(()),
+        })
+        .map_err(|err| DbError::query_error("delete invitation", err))
+    }
+
+    /// Creates user invited by `token` with role of the invitation. Email of the invitation replaces the one
+    /// of `new_user` and is verified, the link was sent there. Unknown, accepted or expired invitation is
+    /// reported as not found.
+    #[tracing::instrument(skip(self, token))]
+    pub fn accept_invitation(&self, token: &str, mut new_user: NewUser) -> DbResult<User> {
+        new_user.password = self.hashing.hash(&new_user.password)?;
+        let conn = self.get_conn("accept invitation")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let invitation = invitations::table
+                .filter(invitations::token_hash.eq(hash_token(&conn, token)?))
+                .filter(invitations::accepted.is_null())
+                .filter(invitations::expires.gt(chrono::Utc::now().naive_utc()))
+                .for_update()
+                .first::<Invitation>(&conn)
+                .optional()?
+                .ok_or_else(|| DbError::not_found("invitation"))?;
+
+            new_user.role = invitation.role;
+            if let Some(address) = invitation.email {
+                new_user = new_user.with_email(Some(address));
+            }
+            let user = insert_available_user(&conn, new_user)?;
+
+            diesel::update(invitations::table.find(invitation.id))
+                .set((
+                    invitations::accepted.eq(chrono::Utc::now().naive_utc()),
+                    invitations::accepted_by.eq(user.id),
+                ))
+                .execute(&conn)?;
+            Ok(user)
+        })
+    }
+
+    /// Creates user who registered on their own, username or email which is already taken is invalid.
+    #[tracing::instrument(skip(self))]
+    pub fn register_user(&self, mut new_user: NewUser) -> DbResult<User> {
+        new_user.password = self.hashing.hash(&new_user.password)?;
+        let conn = self.get_conn("register user")?;
+        conn.transaction::<_, DbError, _>(|| insert_available_user(&conn, new_user))
+    }
+
+    /// Stores hash of token which verifies `email` of the user, tokens issued to the user before stop working.
+    #[tracing::instrument(skip(self, token))]
+    pub fn insert_email_verification(
+        &self,
+        user_id: i32,
+        email: &str,
+        token: &str,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<()> {
+        let conn = self.get_conn("insert email verification")?;
+        conn.transaction::<_, DbError, _>(|| {
+            diesel::update(
+                email_verifications::table
+                    .filter(email_verifications::user_id.eq(user_id))
+                    .filter(email_verifications::used.is_null()),
+            )
+            .set(email_verifications::used.eq(chrono::Utc::now().naive_utc()))
+            .execute(&conn)?;
+
+            diesel::insert_into(email_verifications::table)
+                .values((
+                    email_verifications::user_id.eq(user_id),
+                    email_verifications::email.eq(email),
+                    email_verifications::token_hash.eq(hash_token(&conn, token)?),
+                    email_verifications::expires.eq(expires),
+                ))
+                .execute(&conn)
+                .map(|_| ())
+                .map_err(|err| DbError::insert_error("email_verifications", err))
+        })
+    }
+
+    /// Sets the address `token` was issued for as verified email of the user, it is either the email the user
+    /// registered with or the one they changed their profile to. Token is consumed, unknown, used or expired
+    /// token is reported as not found, address which was taken by another user meanwhile is invalid.
+    #[tracing::instrument(skip(self, token))]
+    pub fn verify_email(&self, token: &str) -> DbResult<User> {
+        let conn = self.get_conn("verify email")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let now = chrono::Utc::now().naive_utc();
+            let (verification_id, user_id, address) = email_verifications::table
+                .filter(email_verifications::token_hash.eq(hash_token(&conn, token)?))
+                .filter(email_verifications::used.is_null())
+                .filter(email_verifications::expires.gt(now))
+                .select((
+                    email_verifications::id,
+                    email_verifications::user_id,
+                    email_verifications::email,
+                ))
+                .for_update()
+                .first::<(i32, i32, String)>(&conn)
+                .optional()?
+                .ok_or_else(|| DbError::not_found("email verification"))?;
+
+            diesel::update(email_verifications::table.find(verification_id))
+                .set(email_verifications::used.eq(now))
+                .execute(&conn)?;
+
+            let taken = diesel::select(diesel::dsl::exists(
+                users::table
+                    .filter(users::email.eq(&address))
+                    .filter(users::id.ne(user_id)),
+            ))
+            .get_result::<bool>(&conn)?;
+            if taken {
+                return Err(DbError::InvalidData("email is already taken".into()));
+            }
+
+            Ok(diesel::update(users::table.find(user_id))
+                .set((users::email.eq(address), users::email_verified.eq(now)))
+                .get_result::<User>(&conn)?)
+        })
+    }
+}
+
+fn insert_available_user(conn: &PgConnection, new_user: NewUser) -> DbResult<User> {
+    let taken = diesel::select(diesel::dsl::exists(
+        users::table.filter(
+            users::username
+                .eq(&new_user.username)
+                .or(users::email.eq(new_user.email())),
+        ),
+    ))
+    .get_result::<bool>(conn)?;
+    if taken {
+        return Err(DbError::InvalidData(
+            "username or email is already taken".into(),
+        ));
+    }
+
+    insert_new_user(conn, new_user).map_err(|err| DbError::insert_error("user", err))
+}
@@ -0,0 +1,149 @@
+use crate::dbo::{self, status, ProjectScope};
+use crate::errors::{DbError, DbResult};
+use crate::{scope_ids, Db, ScopeIds};
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::sql_types::{Date, Int2, Integer, Nullable};
+
+impl Db {
+    /// Number of tickets created and resolved per day, days without any activity are included with zeroes.
+    #[tracing::instrument(skip(self))]
+    pub fn report_throughput(
+        &self,
+        scope: &ProjectScope,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> DbResult<Vec<dbo::DailyThroughput>> {
+        diesel::sql_query(
+            r#"
+            SELECT d::date AS day,
+                   (SELECT COUNT(*)
+                    FROM tickets t
+                    WHERE t.created::date = d::date
+                      AND ($4::int[] IS NULL OR t.project_id = ANY($4))) AS created,
+                   (SELECT COUNT(DISTINCT h.ticket_id)
+                    FROM ticket_history h
+                    JOIN tickets t ON t.id = h.ticket_id
+                    WHERE h.new_status >= $3
+                      AND (h.old_status IS NULL OR h.old_status < $3)
+                      AND h.created::date = d::date
+                      AND ($4::int[] IS NULL OR t.project_id = ANY($4))) AS resolved
+            FROM generate_series($1::date, $2::date, interval '1 day') AS d
+            ORDER BY day
+            "#,
+        )
+        .bind::<Date, _>(from)
+        .bind::<Date, _>(to)
+        .bind::<Int2, _>(status::RESOLVED)
+        .bind::<ScopeIds, _>(scope_ids(scope))
+        .load::<dbo::DailyThroughput>(&self.get_conn("report throughput")?)
+        .map_err(|err| DbError::query_error("report throughput", err))
+    }
+
+    /// Time from creation until a ticket was resolved for the first time, grouped by severity.
+    /// Only tickets resolved within `from..=to` are taken into account.
+    #[tracing::instrument(skip(self))]
+    pub fn report_cycle_time(
+        &self,
+        scope: &ProjectScope,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> DbResult<Vec<dbo::CycleTime>> {
+        diesel::sql_query(
+            r#"
+            WITH resolved AS (
+                SELECT t.severity,
+                       EXTRACT(EPOCH FROM (MIN(h.created) - t.created))::float8 AS seconds
+                FROM tickets t
+                JOIN ticket_history h ON h.ticket_id = t.id
+                WHERE h.new_status >= $3
+                  AND ($4::int[] IS NULL OR t.project_id = ANY($4))
+                GROUP BY t.id, t.severity, t.created
+                HAVING MIN(h.created)::date BETWEEN $1 AND $2
+            )
+            SELECT severity,
+                   COUNT(*) AS tickets,
+                   AVG(seconds)::float8 AS mean_seconds,
+                   percentile_cont(0.5) WITHIN GROUP (ORDER BY seconds) AS p50_seconds,
+                   percentile_cont(0.9) WITHIN GROUP (ORDER BY seconds) AS p90_seconds,
+                   percentile_cont(0.95) WITHIN GROUP (ORDER BY seconds) AS p95_seconds
+            FROM resolved
+            GROUP BY severity
+            ORDER BY severity
+            "#,
+        )
+        .bind::<Date, _>(from)
+        .bind::<Date, _>(to)
+        .bind::<Int2, _>(status::RESOLVED)
+        .bind::<ScopeIds, _>(scope_ids(scope))
+        .load::<dbo::CycleTime>(&self.get_conn("report cycle time")?)
+        .map_err(|err| DbError::query_error("report cycle time", err))
+    }
+
+    /// How long tickets spend in each status. Status a ticket is currently in is counted up to now.
+    #[tracing::instrument(skip(self))]
+    pub fn report_time_in_status(
+        &self,
+        scope: &ProjectScope,
+        ticket_id: Option<i32>,
+    ) -> DbResult<Vec<dbo::TimeInStatus>> {
+        diesel::sql_query(
+            r#"
+            WITH spans AS (
+                SELECT h.ticket_id,
+                       h.new_status AS status,
+                       EXTRACT(EPOCH FROM (
+                           COALESCE(LEAD(h.created) OVER (PARTITION BY h.ticket_id ORDER BY h.created, h.id), now())
+                           - h.created
+                       ))::float8 AS seconds
+                FROM ticket_history h
+                JOIN tickets t ON t.id = h.ticket_id
+                WHERE ($1::int IS NULL OR h.ticket_id = $1)
+                  AND ($2::int[] IS NULL OR t.project_id = ANY($2))
+            )
+            SELECT status,
+                   COUNT(DISTINCT ticket_id) AS tickets,
+                   SUM(seconds)::float8 AS total_seconds,
+                   AVG(seconds)::float8 AS mean_seconds
+            FROM spans
+            GROUP BY status
+            ORDER BY status
+            "#,
+        )
+        .bind::<Nullable<Integer>, _>(ticket_id)
+        .bind::<ScopeIds, _>(scope_ids(scope))
+        .load::<dbo::TimeInStatus>(&self.get_conn("report time in status")?)
+        .map_err(|err| DbError::query_error("report time in status", err))
+    }
+
+    /// Number of tickets in each status at the end of every day in `from..=to`.
+    #[tracing::instrument(skip(self))]
+    pub fn report_cumulative_flow(
+        &self,
+        scope: &ProjectScope,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> DbResult<Vec<dbo::CumulativeFlow>> {
+        diesel::sql_query(
+            r#"
+            SELECT d::date AS day, s.status, COUNT(*) AS tickets
+            FROM generate_series($1::date, $2::date, interval '1 day') AS d
+            CROSS JOIN LATERAL (
+                SELECT DISTINCT ON (h.ticket_id) h.new_status AS status
+                FROM ticket_history h
+                JOIN tickets t ON t.id = h.ticket_id
+                WHERE h.created < d::date + 1
+                  AND ($3::int[] IS NULL OR t.project_id = ANY($3))
+                ORDER BY h.ticket_id, h.created DESC, h.id DESC
+            ) s
+            GROUP BY day, s.status
+            ORDER BY day, s.status
+            "#,
+        )
+        .bind::<Date, _>(from)
+        .bind::<Date, _>(to)
+        .bind::<ScopeIds, _>(scope_ids(scope))
+        .load::<dbo::CumulativeFlow>(&self.get_conn("report cumulative flow")?)
+        .map_err(|err| DbError::query_error("report cumulative flow", err))
+    }
+}
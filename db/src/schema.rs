@@ -1,3 +1,202 @@
+table! {
+    email_verifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        email -> Varchar,
+        token_hash -> Varchar,
+        expires -> Timestamp,
+        used -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    external_refs (id) {
+        id -> Int4,
+        source -> Varchar,
+        kind -> Varchar,
+        external_id -> Varchar,
+        local_id -> Int4,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    impersonation_audit (id) {
+        id -> Int4,
+        actor_id -> Int4,
+        user_id -> Int4,
+        method -> Varchar,
+        path -> Varchar,
+        status -> Nullable<Int2>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    invitations (id) {
+        id -> Int4,
+        token_hash -> Varchar,
+        email -> Nullable<Varchar>,
+        role -> Varchar,
+        invited_by -> Nullable<Int4>,
+        expires -> Timestamp,
+        accepted -> Nullable<Timestamp>,
+        accepted_by -> Nullable<Int4>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    login_failures (kind, key) {
+        kind -> Varchar,
+        key -> Varchar,
+        failures -> Int4,
+        last_failure -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oidc_logins (state_hash) {
+        state_hash -> Varchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        expires -> Timestamp,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        expires -> Timestamp,
+        used -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        expires -> Timestamp,
+        last_used -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    project_members (project_id, user_id) {
+        project_id -> Int4,
+        user_id -> Int4,
+        role -> Varchar,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    project_teams (project_id, team_id) {
+        project_id -> Int4,
+        team_id -> Int4,
+        role -> Varchar,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    projects (id) {
+        id -> Int4,
+        name -> Varchar,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Varchar,
+        used -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        family -> Varchar,
+        expires -> Timestamp,
+        revoked -> Bool,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Varchar,
+        expires -> Timestamp,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        family -> Varchar,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        expires -> Timestamp,
+        last_used -> Timestamp,
+        revoked -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    team_members (team_id, user_id) {
+        team_id -> Int4,
+        user_id -> Int4,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    teams (id) {
+        id -> Int4,
+        name -> Varchar,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    ticket_history (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        old_status -> Nullable<Int2>,
+        new_status -> Int2,
+        created -> Timestamptz,
+        event -> Varchar,
+        related_ticket_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    ticket_watchers (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        user_id -> Nullable<Int4>,
+        team_id -> Nullable<Int4>,
+        created -> Timestamptz,
+    }
+}
+
 table! {
     tickets (id) {
         id -> Int4,
@@ -6,6 +205,41 @@ table! {
         severity -> Int2,
         status -> Int2,
         created -> Timestamptz,
+        description_html -> Nullable<Varchar>,
+        resolution -> Nullable<Varchar>,
+        merged_into -> Nullable<Int4>,
+        project_id -> Int4,
+        assignee_id -> Nullable<Int4>,
+        assignee_team_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    user_avatars (user_id) {
+        user_id -> Int4,
+        content_type -> Varchar,
+        data -> Bytea,
+        updated -> Timestamp,
+    }
+}
+
+table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        issuer -> Varchar,
+        subject -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    user_totp (user_id) {
+        user_id -> Int4,
+        secret -> Varchar,
+        confirmed -> Nullable<Timestamp>,
+        last_step -> Nullable<Int8>,
+        created -> Timestamp,
     }
 }
 
@@ -17,9 +251,61 @@ table! {
         firstname -> Varchar,
         lastname -> Varchar,
         created -> Timestamptz,
+        role -> Varchar,
+        email -> Nullable<Varchar>,
+        email_verified -> Nullable<Timestamp>,
+        display_name -> Nullable<Varchar>,
+        timezone -> Varchar,
+        locale -> Varchar,
+        active -> Bool,
     }
 }
 
-joinable!(tickets -> users (author_id));
+joinable!(email_verifications -> users (user_id));
+joinable!(password_resets -> users (user_id));
+joinable!(personal_access_tokens -> users (user_id));
+joinable!(project_members -> projects (project_id));
+joinable!(project_members -> users (user_id));
+joinable!(project_teams -> projects (project_id));
+joinable!(project_teams -> teams (team_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(refresh_tokens -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(team_members -> teams (team_id));
+joinable!(team_members -> users (user_id));
+joinable!(ticket_history -> tickets (ticket_id));
+joinable!(ticket_watchers -> teams (team_id));
+joinable!(ticket_watchers -> tickets (ticket_id));
+joinable!(ticket_watchers -> users (user_id));
+joinable!(tickets -> projects (project_id));
+joinable!(tickets -> teams (assignee_team_id));
+joinable!(user_avatars -> users (user_id));
+joinable!(user_identities -> users (user_id));
+joinable!(user_totp -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(tickets, users,);
+allow_tables_to_appear_in_same_query!(
+    email_verifications,
+    external_refs,
+    impersonation_audit,
+    invitations,
+    login_failures,
+    oidc_logins,
+    password_resets,
+    personal_access_tokens,
+    project_members,
+    project_teams,
+    projects,
+    recovery_codes,
+    refresh_tokens,
+    revoked_tokens,
+    sessions,
+    team_members,
+    teams,
+    ticket_history,
+    ticket_watchers,
+    tickets,
+    user_avatars,
+    user_identities,
+    user_totp,
+    users,
+);
@@ -0,0 +1,101 @@
+use crate::dbo::Session;
+use crate::errors::{DbError, DbResult};
+use crate::schema::sessions;
+use crate::token::{insert_token, revoke_family};
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    /// Records login of the user from given user agent and address, `token` is the first refresh token
+    /// of the session and `family` the one of all tokens rotated from it.
+    #[tracing::instrument(skip(self, token))]
+    pub fn start_session(
+        &self,
+        user_id: i32,
+        token: &str,
+        family: &str,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<Session> {
+        let conn = self.get_conn("start session")?;
+        conn.transaction::<_, DbError, _>(|| {
+            insert_token(&conn, user_id, token, family, expires)?;
+            diesel::insert_into(sessions::table)
+                .values((
+                    sessions::user_id.eq(user_id),
+                    sessions::family.eq(family),
+                    sessions::user_agent.eq(user_agent),
+                    sessions::ip.eq(ip),
+                    sessions::expires.eq(expires),
+                ))
+                .get_result::<Session>(&conn)
+                .map_err(|err| DbError::insert_error("sessions", err))
+        })
+    }
+
+    /// Sessions of the user which were neither revoked nor expired, the most recently used first.
+    #[tracing::instrument(skip(self))]
+    pub fn select_active_sessions(&self, user_id: i32) -> DbResult<Vec<Session>> {
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked.is_null())
+            .filter(sessions::expires.gt(chrono::Utc::now().naive_utc()))
+            .order((sessions::last_used.desc(), sessions::id.desc()))
+            .load::<Session>(&self.get_conn("select sessions")?)
+            .map_err(|err| DbError::query_error("select sessions", err))
+    }
+
+    /// Marks session as used by access token, `false` when the session was revoked.
+    #[tracing::instrument(skip(self))]
+    pub fn use_session(&self, session_id: i32) -> DbResult<bool> {
+        diesel::update(
+            sessions::table
+                .find(session_id)
+                .filter(sessions::revoked.is_null()),
+        )
+        .set(sessions::last_used.eq(chrono::Utc::now().naive_utc()))
+        .execute(&self.get_conn("use session")?)
+        .map(|rows| rows == 1)
+        .map_err(|err| DbError::update_error("sessions", err))
+    }
+
+    /// Revokes session of the user together with its refresh tokens.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_session(&self, user_id: i32, session_id: i32) -> DbResult<Session> {
+        let conn = self.get_conn("revoke session")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let session = diesel::update(
+                sessions::table
+                    .find(session_id)
+                    .filter(sessions::user_id.eq(user_id))
+                    .filter(sessions::revoked.is_null()),
+            )
+            .set(sessions::revoked.eq(chrono::Utc::now().naive_utc()))
+            .get_result::<Session>(&conn)
+            .optional()?
+            .ok_or_else(|| DbError::not_found("session"))?;
+
+            revoke_family(&conn, &session.family)?;
+            Ok(session)
+        })
+    }
+
+    /// Revokes every session of the user together with all refresh tokens, returns the revoked sessions.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_sessions_of_user(&self, user_id: i32) -> DbResult<Vec<Session>> {
+        let conn = self.get_conn("revoke sessions of user")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let revoked = diesel::update(
+                sessions::table
+                    .filter(sessions::user_id.eq(user_id))
+                    .filter(sessions::revoked.is_null()),
+            )
+            .set(sessions::revoked.eq(chrono::Utc::now().naive_utc()))
+            .get_results::<Session>(&conn)?;
+
+            crate::token::revoke_user_tokens(&conn, user_id)?;
+            Ok(revoked)
+        })
+    }
+}
@@ -0,0 +1,471 @@
+use crate::dbo::{
+    Avatar, ExternalRef, Project, ProjectMember, ProjectTeam, SnapshotRecord, SnapshotSummary,
+    Team, TeamMember, Ticket, TicketHistory, TicketWatcher, User, UserIdentity,
+};
+use crate::errors::{DbError, DbResult};
+use crate::import::{KIND_TICKET, KIND_USER};
+use crate::schema::{
+    external_refs, project_members, project_teams, projects, team_members, teams, ticket_history,
+    ticket_watchers, tickets, user_avatars, user_identities, users,
+};
+use crate::Db;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+/// Number of rows loaded at once while exporting.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+impl Db {
+    /// Passes every row of every table to `sink`, table by table so rows come after rows they reference.
+    /// Everything is read in single read-only repeatable read transaction, so the export is consistent snapshot
+    /// even if the DB is being written to in the meantime.
+    #[tracing::instrument(skip(self, sink))]
+    pub fn export_snapshot<F>(&self, mut sink: F) -> DbResult<SnapshotSummary>
+    where
+        F: FnMut(SnapshotRecord) -> Result<(), String>,
+    {
+        let conn = self.get_conn("export snapshot")?;
+        let mut emit = |record: SnapshotRecord| {
+            sink(record).map_err(|err| {
+                tracing::error!(%err, "failed to write exported record");
+                DbError::Unknown(format!("failed to write exported record: {}", err))
+            })
+        };
+
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run::<_, DbError, _>(|| {
+                Ok(SnapshotSummary {
+                    users: export_pages(
+                        |after| {
+                            users::table
+                                .filter(users::id.gt(after))
+                                .order(users::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<User>(&conn)
+                        },
+                        |u| u.id,
+                        |u| emit(SnapshotRecord::User(u)),
+                    )?,
+                    user_avatars: export_pages(
+                        |after| {
+                            user_avatars::table
+                                .filter(user_avatars::user_id.gt(after))
+                                .order(user_avatars::user_id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<Avatar>(&conn)
+                        },
+                        |a| a.user_id,
+                        |a| emit(SnapshotRecord::UserAvatar(a)),
+                    )?,
+                    user_identities: export_pages(
+                        |after| {
+                            user_identities::table
+                                .filter(user_identities::id.gt(after))
+                                .order(user_identities::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<UserIdentity>(&conn)
+                        },
+                        |i| i.id,
+                        |i| emit(SnapshotRecord::UserIdentity(i)),
+                    )?,
+                    teams: export_pages(
+                        |after| {
+                            teams::table
+                                .filter(teams::id.gt(after))
+                                .order(teams::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<Team>(&conn)
+                        },
+                        |t| t.id,
+                        |t| emit(SnapshotRecord::Team(t)),
+                    )?,
+                    team_members: export_pages(
+                        |(after_team, after_user)| {
+                            team_members::table
+                                .filter(
+                                    team_members::team_id
+                                        .gt(after_team)
+                                        .or(team_members::team_id
+                                            .eq(after_team)
+                                            .and(team_members::user_id.gt(after_user))),
+                                )
+                                .order((team_members::team_id, team_members::user_id))
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<TeamMember>(&conn)
+                        },
+                        |m| (m.team_id, m.user_id),
+                        |m| emit(SnapshotRecord::TeamMember(m)),
+                    )?,
+                    projects: export_pages(
+                        |after| {
+                            projects::table
+                                .filter(projects::id.gt(after))
+                                .order(projects::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<Project>(&conn)
+                        },
+                        |p| p.id,
+                        |p| emit(SnapshotRecord::Project(p)),
+                    )?,
+                    project_members: export_pages(
+                        |(after_user, after_project)| {
+                            project_members::table
+                                .filter(
+                                    project_members::user_id.gt(after_user).or(
+                                        project_members::user_id
+                                            .eq(after_user)
+                                            .and(project_members::project_id.gt(after_project)),
+                                    ),
+                                )
+                                .order((project_members::user_id, project_members::project_id))
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<ProjectMember>(&conn)
+                        },
+                        |m| (m.user_id, m.project_id),
+                        |m| emit(SnapshotRecord::ProjectMember(m)),
+                    )?,
+                    project_teams: export_pages(
+                        |(after_project, after_team)| {
+                            project_teams::table
+                                .filter(
+                                    project_teams::project_id.gt(after_project).or(
+                                        project_teams::project_id
+                                            .eq(after_project)
+                                            .and(project_teams::team_id.gt(after_team)),
+                                    ),
+                                )
+                                .order((project_teams::project_id, project_teams::team_id))
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<ProjectTeam>(&conn)
+                        },
+                        |g| (g.project_id, g.team_id),
+                        |g| emit(SnapshotRecord::ProjectTeam(g)),
+                    )?,
+                    tickets: export_pages(
+                        |after| {
+                            tickets::table
+                                .filter(tickets::id.gt(after))
+                                .order(tickets::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<Ticket>(&conn)
+                        },
+                        |t| t.id,
+                        |t| emit(SnapshotRecord::Ticket(t)),
+                    )?,
+                    ticket_watchers: export_pages(
+                        |after| {
+                            ticket_watchers::table
+                                .filter(ticket_watchers::id.gt(after))
+                                .order(ticket_watchers::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<TicketWatcher>(&conn)
+                        },
+                        |w| w.id,
+                        |w| emit(SnapshotRecord::TicketWatcher(w)),
+                    )?,
+                    ticket_history: export_pages(
+                        |after| {
+                            ticket_history::table
+                                .filter(ticket_history::id.gt(after))
+                                .order(ticket_history::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<TicketHistory>(&conn)
+                        },
+                        |h| h.id,
+                        |h| emit(SnapshotRecord::TicketHistory(h)),
+                    )?,
+                    external_refs: export_pages(
+                        |after| {
+                            external_refs::table
+                                .filter(external_refs::id.gt(after))
+                                .order(external_refs::id)
+                                .limit(EXPORT_PAGE_SIZE)
+                                .load::<ExternalRef>(&conn)
+                        },
+                        |r| r.id,
+                        |r| emit(SnapshotRecord::ExternalRef(r)),
+                    )?,
+                })
+            })
+            .map(|summary| {
+                tracing::debug!(?summary, "snapshot exported");
+                summary
+            })
+    }
+
+    /// Inserts records produced by [`Db::export_snapshot`] in single transaction. Rows get new ids,
+    /// references between them are rewritten to the new ids. Passwords are stored as they are, since they
+    /// are already hashed.
+    #[tracing::instrument(skip(self, records))]
+    pub fn import_snapshot<I>(&self, records: I) -> DbResult<SnapshotSummary>
+    where
+        I: IntoIterator<Item = Result<SnapshotRecord, String>>,
+    {
+        let conn = self.get_conn("import snapshot")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let mut summary = SnapshotSummary::default();
+            let mut user_ids = HashMap::new();
+            let mut team_ids = HashMap::new();
+            let mut project_ids = HashMap::new();
+            let mut ticket_ids = HashMap::new();
+            // merge target can be exported after the merged ticket, so the link is restored at the end
+            let mut merged = vec![];
+
+            for record in records {
+                match record.map_err(DbError::InvalidData)? {
+                    SnapshotRecord::User(u) => {
+                        let new_id = diesel::insert_into(users::table)
+                            .values((
+                                users::username.eq(u.username),
+                                users::password.eq(u.password),
+                                users::firstname.eq(u.firstname),
+                                users::lastname.eq(u.lastname),
+                                users::created.eq(u.created),
+                                users::role.eq(u.role),
+                                users::email.eq(u.email),
+                                users::email_verified.eq(u.email_verified),
+                                users::display_name.eq(u.display_name),
+                                users::timezone.eq(u.timezone),
+                                users::locale.eq(u.locale),
+                                users::active.eq(u.active),
+                            ))
+                            .returning(users::id)
+                            .get_result::<i32>(&conn)?;
+                        user_ids.insert(u.id, new_id);
+                        summary.users += 1;
+                    }
+                    SnapshotRecord::UserAvatar(a) => {
+                        diesel::insert_into(user_avatars::table)
+                            .values((
+                                user_avatars::user_id.eq(remap(&user_ids, "user", a.user_id)?),
+                                user_avatars::content_type.eq(a.content_type),
+                                user_avatars::data.eq(a.data),
+                                user_avatars::updated.eq(a.updated),
+                            ))
+                            .execute(&conn)?;
+                        summary.user_avatars += 1;
+                    }
+                    SnapshotRecord::UserIdentity(i) => {
+                        diesel::insert_into(user_identities::table)
+                            .values((
+                                user_identities::user_id.eq(remap(&user_ids, "user", i.user_id)?),
+                                user_identities::issuer.eq(i.issuer),
+                                user_identities::subject.eq(i.subject),
+                                user_identities::created.eq(i.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.user_identities += 1;
+                    }
+                    SnapshotRecord::Team(t) => {
+                        let new_id = diesel::insert_into(teams::table)
+                            .values((teams::name.eq(t.name), teams::created.eq(t.created)))
+                            .returning(teams::id)
+                            .get_result::<i32>(&conn)?;
+                        team_ids.insert(t.id, new_id);
+                        summary.teams += 1;
+                    }
+                    SnapshotRecord::TeamMember(m) => {
+                        diesel::insert_into(team_members::table)
+                            .values((
+                                team_members::team_id.eq(remap(&team_ids, "team", m.team_id)?),
+                                team_members::user_id.eq(remap(&user_ids, "user", m.user_id)?),
+                                team_members::created.eq(m.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.team_members += 1;
+                    }
+                    SnapshotRecord::Project(p) => {
+                        // default project is created by migrations, so project of the same name is reused
+                        let existing = projects::table
+                            .filter(projects::name.eq(&p.name))
+                            .select(projects::id)
+                            .first::<i32>(&conn)
+                            .optional()?;
+                        let new_id = match existing {
+                            Some(existing) => existing,
+                            None => diesel::insert_into(projects::table)
+                                .values((
+                                    projects::name.eq(p.name),
+                                    projects::created.eq(p.created),
+                                ))
+                                .returning(projects::id)
+                                .get_result::<i32>(&conn)?,
+                        };
+                        project_ids.insert(p.id, new_id);
+                        summary.projects += 1;
+                    }
+                    SnapshotRecord::ProjectMember(m) => {
+                        diesel::insert_into(project_members::table)
+                            .values((
+                                project_members::project_id.eq(remap(
+                                    &project_ids,
+                                    "project",
+                                    m.project_id,
+                                )?),
+                                project_members::user_id.eq(remap(&user_ids, "user", m.user_id)?),
+                                project_members::role.eq(m.role),
+                                project_members::created.eq(m.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.project_members += 1;
+                    }
+                    SnapshotRecord::ProjectTeam(g) => {
+                        diesel::insert_into(project_teams::table)
+                            .values((
+                                project_teams::project_id.eq(remap(
+                                    &project_ids,
+                                    "project",
+                                    g.project_id,
+                                )?),
+                                project_teams::team_id.eq(remap(&team_ids, "team", g.team_id)?),
+                                project_teams::role.eq(g.role),
+                                project_teams::created.eq(g.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.project_teams += 1;
+                    }
+                    SnapshotRecord::Ticket(t) => {
+                        let new_id = diesel::insert_into(tickets::table)
+                            .values((
+                                tickets::author_id.eq(remap(&user_ids, "user", t.author_id)?),
+                                tickets::description.eq(t.description),
+                                tickets::severity.eq(t.severity),
+                                tickets::status.eq(t.status),
+                                tickets::created.eq(t.created),
+                                tickets::resolution.eq(t.resolution),
+                                tickets::project_id.eq(remap(
+                                    &project_ids,
+                                    "project",
+                                    t.project_id,
+                                )?),
+                                tickets::assignee_id.eq(t
+                                    .assignee_id
+                                    .map(|assignee| remap(&user_ids, "user", assignee))
+                                    .transpose()?),
+                                tickets::assignee_team_id.eq(t
+                                    .assignee_team_id
+                                    .map(|team| remap(&team_ids, "team", team))
+                                    .transpose()?),
+                            ))
+                            .returning(tickets::id)
+                            .get_result::<i32>(&conn)?;
+                        ticket_ids.insert(t.id, new_id);
+                        if let Some(merged_into) = t.merged_into {
+                            merged.push((new_id, merged_into));
+                        }
+                        summary.tickets += 1;
+                    }
+                    SnapshotRecord::TicketWatcher(w) => {
+                        diesel::insert_into(ticket_watchers::table)
+                            .values((
+                                ticket_watchers::ticket_id.eq(remap(
+                                    &ticket_ids,
+                                    "ticket",
+                                    w.ticket_id,
+                                )?),
+                                ticket_watchers::user_id.eq(w
+                                    .user_id
+                                    .map(|user| remap(&user_ids, "user", user))
+                                    .transpose()?),
+                                ticket_watchers::team_id.eq(w
+                                    .team_id
+                                    .map(|team| remap(&team_ids, "team", team))
+                                    .transpose()?),
+                                ticket_watchers::created.eq(w.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.ticket_watchers += 1;
+                    }
+                    SnapshotRecord::TicketHistory(h) => {
+                        diesel::insert_into(ticket_history::table)
+                            .values((
+                                ticket_history::ticket_id.eq(remap(
+                                    &ticket_ids,
+                                    "ticket",
+                                    h.ticket_id,
+                                )?),
+                                ticket_history::old_status.eq(h.old_status),
+                                ticket_history::new_status.eq(h.new_status),
+                                ticket_history::created.eq(h.created),
+                                ticket_history::event.eq(h.event),
+                                ticket_history::related_ticket_id.eq(h
+                                    .related_ticket_id
+                                    .map(|related| remap(&ticket_ids, "ticket", related))
+                                    .transpose()?),
+                            ))
+                            .execute(&conn)?;
+                        summary.ticket_history += 1;
+                    }
+                    SnapshotRecord::ExternalRef(r) => {
+                        let local_id = match r.kind.as_str() {
+                            KIND_USER => remap(&user_ids, "user", r.local_id)?,
+                            KIND_TICKET => remap(&ticket_ids, "ticket", r.local_id)?,
+                            unknown => {
+                                return Err(DbError::InvalidData(format!(
+                                    "external reference of unknown kind '{}'",
+                                    unknown
+                                )))
+                            }
+                        };
+                        diesel::insert_into(external_refs::table)
+                            .values((
+                                external_refs::source.eq(r.source),
+                                external_refs::kind.eq(r.kind),
+                                external_refs::external_id.eq(r.external_id),
+                                external_refs::local_id.eq(local_id),
+                                external_refs::created.eq(r.created),
+                            ))
+                            .execute(&conn)?;
+                        summary.external_refs += 1;
+                    }
+                }
+            }
+
+            for (ticket_id, merged_into) in merged {
+                diesel::update(tickets::table.find(ticket_id))
+                    .set(tickets::merged_into.eq(remap(&ticket_ids, "ticket", merged_into)?))
+                    .execute(&conn)?;
+            }
+
+            Ok(summary)
+        })
+        .map(|summary| {
+            tracing::debug!(?summary, "snapshot imported");
+            summary
+        })
+    }
+}
+
+/// Emits rows loaded page by page, `load` gets key of the last row of previous page (default value at first).
+fn export_pages<T, K, L, I, E>(mut load: L, key_of: I, mut emit: E) -> DbResult<usize>
+where
+    K: Copy + Default,
+    L: FnMut(K) -> QueryResult<Vec<T>>,
+    I: Fn(&T) -> K,
+    E: FnMut(T) -> DbResult<()>,
+{
+    let mut exported = 0;
+    let mut after = K::default();
+    loop {
+        let page = load(after)?;
+        match page.last() {
+            Some(last) => after = key_of(last),
+            None => return Ok(exported),
+        }
+        for row in page {
+            emit(row)?;
+            exported += 1;
+        }
+    }
+}
+
+fn remap(ids: &HashMap<i32, i32>, what: &str, old_id: i32) -> DbResult<i32> {
+    ids.get(&old_id).copied().ok_or_else(|| {
+        DbError::InvalidData(format!(
+            "record references {} {} which is not part of the import",
+            what, old_id
+        ))
+    })
+}
@@ -0,0 +1,125 @@
+use crate::dbo::{ProjectTeam, Team, TeamMember};
+use crate::errors::{DbError, DbResult};
+use crate::schema::{project_teams, team_members, teams};
+use crate::Db;
+use diesel::prelude::*;
+
+impl Db {
+    #[tracing::instrument(skip(self))]
+    pub fn select_teams(&self) -> DbResult<Vec<Team>> {
+        teams::table
+            .order(teams::id)
+            .load::<Team>(&self.get_conn("select teams")?)
+            .map_err(|err| DbError::query_error("select teams", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_team(&self, team_id: i32) -> DbResult<Team> {
+        teams::table
+            .find(team_id)
+            .first::<Team>(&self.get_conn("select team")?)
+            .map_err(|err| DbError::query_error("select team", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn insert_team(&self, name: &str) -> DbResult<Team> {
+        diesel::insert_into(teams::table)
+            .values(teams::name.eq(name))
+            .get_result::<Team>(&self.get_conn("insert team")?)
+            .map_err(|err| DbError::insert_error("teams", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn update_team(&self, team_id: i32, name: &str) -> DbResult<Team> {
+        diesel::update(teams::table.find(team_id))
+            .set(teams::name.eq(name))
+            .get_result::<Team>(&self.get_conn("update team")?)
+            .map_err(|err| DbError::query_error("update team", err))
+    }
+
+    /// Tickets assigned to the team become unassigned, memberships and project grants are removed.
+    #[tracing::instrument(skip(self))]
+    pub fn delete_team(&self, team_id: i32) -> DbResult<usize> {
+        diesel::delete(teams::table.find(team_id))
+            .execute(&self.get_conn("delete team")?)
+            .and_then(|rows_affected| match rows_affected {
+                0 => Err(diesel::NotFound),
+                _ => Ok(rows_affected),
+            })
+            .map_err(|err| DbError::query_error("delete team", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_team_members(&self, team_id: i32) -> DbResult<Vec<TeamMember>> {
+        team_members::table
+            .filter(team_members::team_id.eq(team_id))
+            .order(team_members::user_id)
+            .load::<TeamMember>(&self.get_conn("select team members")?)
+            .map_err(|err| DbError::query_error("select team members", err))
+    }
+
+    /// Adding user who already is a member does nothing.
+    #[tracing::instrument(skip(self))]
+    pub fn insert_team_member(&self, team_id: i32, user_id: i32) -> DbResult<usize> {
+        diesel::insert_into(team_members::table)
+            .values((
+                team_members::team_id.eq(team_id),
+                team_members::user_id.eq(user_id),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&self.get_conn("insert team member")?)
+            .map_err(|err| DbError::insert_error("team_members", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn delete_team_member(&self, team_id: i32, user_id: i32) -> DbResult<usize> {
+        diesel::delete(team_members::table.find((team_id, user_id)))
+            .execute(&self.get_conn("delete team member")?)
+            .and_then(|rows_affected| match rows_affected {
+                0 => Err(diesel::NotFound),
+                _ => Ok(rows_affected),
+            })
+            .map_err(|err| DbError::query_error("delete team member", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_project_teams(&self, project_id: i32) -> DbResult<Vec<ProjectTeam>> {
+        project_teams::table
+            .filter(project_teams::project_id.eq(project_id))
+            .order(project_teams::team_id)
+            .load::<ProjectTeam>(&self.get_conn("select project teams")?)
+            .map_err(|err| DbError::query_error("select project teams", err))
+    }
+
+    /// Grants the role in the project to every member of the team, or changes the role already granted.
+    #[tracing::instrument(skip(self))]
+    pub fn upsert_project_team(
+        &self,
+        project_id: i32,
+        team_id: i32,
+        role: &str,
+    ) -> DbResult<ProjectTeam> {
+        diesel::insert_into(project_teams::table)
+            .values((
+                project_teams::project_id.eq(project_id),
+                project_teams::team_id.eq(team_id),
+                project_teams::role.eq(role),
+            ))
+            .on_conflict((project_teams::project_id, project_teams::team_id))
+            .do_update()
+            .set(project_teams::role.eq(role))
+            .get_result::<ProjectTeam>(&self.get_conn("upsert project team")?)
+            .map_err(|err| DbError::insert_error("project_teams", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn delete_project_team(&self, project_id: i32, team_id: i32) -> DbResult<usize> {
+        diesel::delete(project_teams::table.find((project_id, team_id)))
+            .execute(&self.get_conn("delete project team")?)
+            .and_then(|rows_affected| match rows_affected {
+                0 => Err(diesel::NotFound),
+                _ => Ok(rows_affected),
+            })
+            .map_err(|err| DbError::query_error("delete project team", err))
+    }
+}
@@ -0,0 +1,252 @@
+use crate::dbo::{PersonalAccessToken, RefreshToken, Rotation, User};
+use crate::errors::{DbError, DbResult};
+use crate::functions::{digest, encode};
+use crate::schema::{personal_access_tokens, refresh_tokens, revoked_tokens, sessions, users};
+use crate::Db;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+/// Seconds between two records of personal access token use.
+const PAT_LAST_USED_INTERVAL: i64 = 60;
+
+impl Db {
+    /// Stores hash of a newly issued refresh token.
+    #[tracing::instrument(skip(self, token))]
+    pub fn insert_refresh_token(
+        &self,
+        user_id: i32,
+        token: &str,
+        family: &str,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<RefreshToken> {
+        let conn = self.get_conn("insert refresh token")?;
+        insert_token(&conn, user_id, token, family, expires)
+            .map_err(|err| DbError::insert_error("refresh_tokens", err))
+    }
+
+    /// Replaces `token` with `new_token` of the same family, session of the family is marked as used.
+    /// Token which was already replaced or revoked revokes the whole family, so both the thief and the victim
+    /// have to log in again.
+    #[tracing::instrument(skip(self, token, new_token))]
+    pub fn rotate_refresh_token(
+        &self,
+        token: &str,
+        new_token: &str,
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<Rotation> {
+        let conn = self.get_conn("rotate refresh token")?;
+        conn.transaction::<_, DbError, _>(|| {
+            let current = refresh_tokens::table
+                .filter(refresh_tokens::token_hash.eq(hash_token(&conn, token)?))
+                .for_update()
+                .first::<RefreshToken>(&conn)
+                .optional()?
+                .ok_or_else(|| DbError::not_found("refresh token"))?;
+
+            if current.revoked {
+                tracing::warn!(
+                    user_id = current.user_id,
+                    family = %current.family,
+                    "reuse of rotated refresh token, revoking its family"
+                );
+                revoke_family(&conn, &current.family)?;
+                return Ok(Rotation::Reused);
+            }
+            if current.expires < chrono::Utc::now().naive_utc() {
+                return Ok(Rotation::Expired);
+            }
+
+            diesel::update(refresh_tokens::table.find(current.id))
+                .set(refresh_tokens::revoked.eq(true))
+                .execute(&conn)?;
+
+            let rotated =
+                insert_token(&conn, current.user_id, new_token, &current.family, expires)?;
+            // tokens issued before sessions were recorded have none
+            let session_id =
+                diesel::update(sessions::table.filter(sessions::family.eq(&current.family)))
+                    .set((
+                        sessions::expires.eq(expires),
+                        sessions::last_used.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .returning(sessions::id)
+                    .get_result::<i32>(&conn)
+                    .optional()?;
+            Ok(Rotation::Rotated(rotated, session_id))
+        })
+    }
+
+    /// Revokes refresh token of the user together with every token rotated from the same login.
+    #[tracing::instrument(skip(self, token))]
+    pub fn revoke_refresh_token(&self, user_id: i32, token: &str) -> DbResult<usize> {
+        let conn = self.get_conn("revoke refresh token")?;
+        let family = refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash_token(&conn, token)?))
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .select(refresh_tokens::family)
+            .first::<String>(&conn)
+            .map_err(|err| DbError::query_error("select refresh token", err))?;
+
+        revoke_family(&conn, &family)
+            .map_err(|err| DbError::query_error("revoke refresh token", err))
+    }
+
+    /// Revokes every refresh token of the user, e.g. when the credentials change.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_refresh_tokens_of_user(&self, user_id: i32) -> DbResult<usize> {
+        let conn = self.get_conn("revoke refresh tokens of user")?;
+        revoke_user_tokens(&conn, user_id)
+            .map_err(|err| DbError::query_error("revoke refresh tokens of user", err))
+    }
+
+    /// Puts access token on the revocation list. Entries of tokens which already expired are dropped
+    /// at the same time as they cannot pass validation anyway.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_token(&self, jti: &str, expires: chrono::NaiveDateTime) -> DbResult<()> {
+        let conn = self.get_conn("revoke token")?;
+
+        diesel::insert_into(revoked_tokens::table)
+            .values((
+                revoked_tokens::jti.eq(jti),
+                revoked_tokens::expires.eq(expires),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(|err| DbError::insert_error("revoked_tokens", err))?;
+
+        diesel::delete(
+            revoked_tokens::table
+                .filter(revoked_tokens::expires.lt(chrono::Utc::now().naive_utc())),
+        )
+        .execute(&conn)
+        .map(|_| ())
+        .map_err(|err| DbError::query_error("delete expired revoked tokens", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn is_token_revoked(&self, jti: &str) -> DbResult<bool> {
+        diesel::select(diesel::dsl::exists(revoked_tokens::table.find(jti)))
+            .get_result::<bool>(&self.get_conn("is token revoked")?)
+            .map_err(|err| DbError::query_error("is token revoked", err))
+    }
+
+    /// Stores hash of a newly created personal access token.
+    #[tracing::instrument(skip(self, token))]
+    pub fn insert_personal_access_token(
+        &self,
+        user_id: i32,
+        name: &str,
+        token: &str,
+        scopes: &[String],
+        expires: chrono::NaiveDateTime,
+    ) -> DbResult<PersonalAccessToken> {
+        let conn = self.get_conn("insert personal access token")?;
+        diesel::insert_into(personal_access_tokens::table)
+            .values((
+                personal_access_tokens::user_id.eq(user_id),
+                personal_access_tokens::name.eq(name),
+                personal_access_tokens::token_hash.eq(hash_token(&conn, token)?),
+                personal_access_tokens::scopes.eq(scopes),
+                personal_access_tokens::expires.eq(expires),
+            ))
+            .get_result::<PersonalAccessToken>(&conn)
+            .map_err(|err| DbError::insert_error("personal_access_tokens", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn select_personal_access_tokens(
+        &self,
+        user_id: i32,
+    ) -> DbResult<Vec<PersonalAccessToken>> {
+        personal_access_tokens::table
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .order(personal_access_tokens::id)
+            .load::<PersonalAccessToken>(&self.get_conn("select personal access tokens")?)
+            .map_err(|err| DbError::query_error("select personal access tokens", err))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn delete_personal_access_token(&self, user_id: i32, token_id: i32) -> DbResult<usize> {
+        diesel::delete(
+            personal_access_tokens::table
+                .find(token_id)
+                .filter(personal_access_tokens::user_id.eq(user_id)),
+        )
+        .execute(&self.get_conn("delete personal access token")?)
+        .and_then(|rows_affected| match rows_affected {
+            0 => Err(diesel::NotFound),
+            _ => Ok(rows_affected),
+        })
+        .map_err(|err| DbError::query_error("delete personal access token", err))
+    }
+
+    /// Finds unexpired personal access token together with its owner and marks it as used. Last use is
+    /// recorded at most once per [`PAT_LAST_USED_INTERVAL`], so busy clients do not write on every request.
+    #[tracing::instrument(skip(self, token))]
+    pub fn use_personal_access_token(&self, token: &str) -> DbResult<(PersonalAccessToken, User)> {
+        let conn = self.get_conn("use personal access token")?;
+        let now = chrono::Utc::now().naive_utc();
+        let (mut pat, user) = personal_access_tokens::table
+            .inner_join(users::table)
+            .filter(personal_access_tokens::token_hash.eq(encode(digest(token, "sha256"), "hex")))
+            .filter(personal_access_tokens::expires.gt(now))
+            .first::<(PersonalAccessToken, User)>(&conn)
+            .optional()
+            .map_err(|err| DbError::query_error("select personal access token", err))?
+            .ok_or_else(|| DbError::not_found("personal access token"))?;
+
+        let interval = chrono::Duration::seconds(PAT_LAST_USED_INTERVAL);
+        if pat
+            .last_used
+            .is_none_or(|last_used| now - last_used >= interval)
+        {
+            diesel::update(personal_access_tokens::table.find(pat.id))
+                .set(personal_access_tokens::last_used.eq(now))
+                .execute(&conn)
+                .map_err(|err| DbError::update_error("personal access token", err))?;
+            pat.last_used = Some(now);
+        }
+        Ok((pat, user))
+    }
+}
+
+pub(crate) fn hash_token(conn: &PgConnection, token: &str) -> QueryResult<String> {
+    diesel::select(encode(digest(token, "sha256"), "hex")).get_result::<String>(conn)
+}
+
+pub(crate) fn insert_token(
+    conn: &PgConnection,
+    user_id: i32,
+    token: &str,
+    family: &str,
+    expires: chrono::NaiveDateTime,
+) -> QueryResult<RefreshToken> {
+    diesel::insert_into(refresh_tokens::table)
+        .values((
+            refresh_tokens::user_id.eq(user_id),
+            refresh_tokens::token_hash.eq(hash_token(conn, token)?),
+            refresh_tokens::family.eq(family),
+            refresh_tokens::expires.eq(expires),
+        ))
+        .get_result::<RefreshToken>(conn)
+}
+
+pub(crate) fn revoke_user_tokens(conn: &PgConnection, user_id: i32) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .filter(refresh_tokens::revoked.eq(false)),
+    )
+    .set(refresh_tokens::revoked.eq(true))
+    .execute(conn)
+}
+
+pub(crate) fn revoke_family(conn: &PgConnection, family: &str) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::family.eq(family))
+            .filter(refresh_tokens::revoked.eq(false)),
+    )
+    .set(refresh_tokens::revoked.eq(true))
+    .execute(conn)
+}
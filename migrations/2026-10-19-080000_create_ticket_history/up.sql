@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS ticket_history
+(
+    id          SERIAL PRIMARY KEY,
+    ticket_id   integer REFERENCES tickets ON DELETE CASCADE NOT NULL,
+    old_status  smallint,
+    new_status  smallint    NOT NULL,
+    created     TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ticket_history_ticket_id_idx ON ticket_history (ticket_id, created);
+
+-- existing tickets have no recorded transitions, so we treat their current status as the one they were created with
+INSERT INTO ticket_history (ticket_id, old_status, new_status, created)
+SELECT id, NULL, status, created
+FROM tickets;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS external_refs
+(
+    id          SERIAL PRIMARY KEY,
+    source      VARCHAR     NOT NULL,
+    kind        VARCHAR     NOT NULL,
+    external_id VARCHAR     NOT NULL,
+    local_id    integer     NOT NULL,
+    created     TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (source, kind, external_id)
+);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tickets DROP COLUMN description_html
\ No newline at end of file
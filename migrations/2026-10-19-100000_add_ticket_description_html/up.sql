@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- rendered description, NULL until the ticket is read for the first time after its description changed
+ALTER TABLE tickets ADD COLUMN description_html VARCHAR;
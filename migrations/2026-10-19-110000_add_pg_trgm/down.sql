@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS tickets_description_trgm_idx;
+DROP EXTENSION IF EXISTS pg_trgm
\ No newline at end of file
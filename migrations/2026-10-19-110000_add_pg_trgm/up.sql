@@ -0,0 +1,4 @@
+-- Your SQL goes here
+CREATE EXTENSION pg_trgm;
+
+CREATE INDEX tickets_description_trgm_idx ON tickets USING gin (description gin_trgm_ops);
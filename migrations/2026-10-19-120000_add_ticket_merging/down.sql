@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE ticket_history DROP COLUMN related_ticket_id;
+ALTER TABLE ticket_history DROP COLUMN event;
+ALTER TABLE tickets DROP COLUMN merged_into;
+ALTER TABLE tickets DROP COLUMN resolution
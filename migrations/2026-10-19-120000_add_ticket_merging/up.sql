@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE tickets ADD COLUMN resolution VARCHAR;
+-- ticket this one was merged into as duplicate
+ALTER TABLE tickets ADD COLUMN merged_into integer REFERENCES tickets ON DELETE SET NULL;
+
+ALTER TABLE ticket_history ADD COLUMN event VARCHAR NOT NULL DEFAULT 'status';
+ALTER TABLE ticket_history ADD COLUMN related_ticket_id integer REFERENCES tickets ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'member'
+    CHECK (role IN ('admin', 'member', 'reporter', 'viewer'));
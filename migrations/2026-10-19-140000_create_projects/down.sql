@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE project_members;
+ALTER TABLE tickets DROP COLUMN project_id;
+DROP TABLE projects;
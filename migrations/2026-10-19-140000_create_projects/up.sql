@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS projects
+(
+    id      SERIAL PRIMARY KEY,
+    name    VARCHAR UNIQUE NOT NULL,
+    created TIMESTAMPTZ    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- tickets created before projects existed, or without explicit project, belong to the default project
+INSERT INTO projects (id, name) VALUES (1, 'Default');
+SELECT setval('projects_id_seq', 1);
+
+ALTER TABLE tickets ADD COLUMN project_id integer NOT NULL DEFAULT 1 REFERENCES projects;
+CREATE INDEX tickets_project_id_idx ON tickets (project_id);
+
+CREATE TABLE IF NOT EXISTS project_members
+(
+    project_id integer REFERENCES projects ON DELETE CASCADE NOT NULL,
+    user_id    integer REFERENCES users ON DELETE CASCADE    NOT NULL,
+    role       VARCHAR     NOT NULL CHECK (role IN ('admin', 'member', 'reporter', 'viewer')),
+    created    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (project_id, user_id)
+);
+
+CREATE INDEX project_members_user_id_idx ON project_members (user_id);
+
+-- existing users keep access to existing tickets
+INSERT INTO project_members (project_id, user_id, role)
+SELECT 1, id, role
+FROM users;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE ticket_watchers;
+ALTER TABLE tickets DROP COLUMN assignee_team_id;
+ALTER TABLE tickets DROP COLUMN assignee_id;
+DROP TABLE project_teams;
+DROP TABLE team_members;
+DROP TABLE teams;
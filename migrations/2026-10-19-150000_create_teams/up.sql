@@ -0,0 +1,44 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS teams
+(
+    id      SERIAL PRIMARY KEY,
+    name    VARCHAR UNIQUE NOT NULL,
+    created TIMESTAMPTZ    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS team_members
+(
+    team_id integer REFERENCES teams ON DELETE CASCADE NOT NULL,
+    user_id integer REFERENCES users ON DELETE CASCADE NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (team_id, user_id)
+);
+
+CREATE INDEX team_members_user_id_idx ON team_members (user_id);
+
+-- team members get the role in the project unless they have better role of their own in project_members
+CREATE TABLE IF NOT EXISTS project_teams
+(
+    project_id integer REFERENCES projects ON DELETE CASCADE NOT NULL,
+    team_id    integer REFERENCES teams ON DELETE CASCADE    NOT NULL,
+    role       VARCHAR     NOT NULL CHECK (role IN ('admin', 'member', 'reporter', 'viewer')),
+    created    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (project_id, team_id)
+);
+
+ALTER TABLE tickets ADD COLUMN assignee_id integer REFERENCES users ON DELETE SET NULL;
+ALTER TABLE tickets ADD COLUMN assignee_team_id integer REFERENCES teams ON DELETE SET NULL;
+CREATE INDEX tickets_assignee_team_id_idx ON tickets (assignee_team_id);
+
+-- watcher is either single user or whole team
+CREATE TABLE IF NOT EXISTS ticket_watchers
+(
+    id        SERIAL PRIMARY KEY,
+    ticket_id integer REFERENCES tickets ON DELETE CASCADE NOT NULL,
+    user_id   integer REFERENCES users ON DELETE CASCADE,
+    team_id   integer REFERENCES teams ON DELETE CASCADE,
+    created   TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((user_id IS NULL) <> (team_id IS NULL)),
+    UNIQUE (ticket_id, user_id),
+    UNIQUE (ticket_id, team_id)
+);
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- only SHA-256 of the refresh token is stored, tokens of one login share `family` so reuse of rotated
+-- token can revoke the whole chain
+CREATE TABLE refresh_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    family VARCHAR NOT NULL,
+    expires TIMESTAMP NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX refresh_tokens_user_id_idx ON refresh_tokens (user_id);
+CREATE INDEX refresh_tokens_family_idx ON refresh_tokens (family);
+
+-- access tokens revoked before they expired, rows can be dropped once `expires` has passed
+CREATE TABLE revoked_tokens (
+    jti VARCHAR PRIMARY KEY,
+    expires TIMESTAMP NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
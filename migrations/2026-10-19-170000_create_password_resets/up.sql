@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- password reset links are sent to this address, users without one can only have the password changed by themselves
+ALTER TABLE users ADD COLUMN email VARCHAR UNIQUE;
+
+-- only SHA-256 of the reset token is stored, token can be used once before `expires`
+CREATE TABLE password_resets (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    expires TIMESTAMP NOT NULL,
+    used TIMESTAMP,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX password_resets_user_id_idx ON password_resets (user_id);
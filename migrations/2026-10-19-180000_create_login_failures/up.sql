@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- failed basic auth logins per username (`kind` = 'user') and per client address (`kind` = 'ip'),
+-- kept in DB so every instance of the server sees the same counters
+CREATE TABLE login_failures (
+    kind VARCHAR NOT NULL,
+    key VARCHAR NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 1,
+    last_failure TIMESTAMP NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMP,
+    PRIMARY KEY (kind, key)
+);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- only SHA-256 of the token is stored, the token itself is shown just once when it is created
+CREATE TABLE personal_access_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    expires TIMESTAMP NOT NULL,
+    last_used TIMESTAMP,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX personal_access_tokens_user_id_idx ON personal_access_tokens (user_id);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- accounts at OpenID Connect providers linked to ticX users, identified by issuer and subject of the ID token
+CREATE TABLE user_identities (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    issuer VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX user_identities_user_id_idx ON user_identities (user_id);
+
+-- logins redirected to the provider which have not come back yet, only SHA-256 of the state is stored
+CREATE TABLE oidc_logins (
+    state_hash VARCHAR PRIMARY KEY,
+    nonce VARCHAR NOT NULL,
+    code_verifier VARCHAR NOT NULL,
+    expires TIMESTAMP NOT NULL
+);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE user_totp (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    -- base32 encoded, as it is shown to the authenticator app
+    secret VARCHAR NOT NULL,
+    -- NULL until the user proves the authenticator app works by submitting a code
+    confirmed TIMESTAMP,
+    -- time step of the last accepted code, so codes cannot be used twice
+    last_step BIGINT,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- only SHA-256 of the codes is stored, they are shown just once
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash VARCHAR NOT NULL,
+    used TIMESTAMP
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
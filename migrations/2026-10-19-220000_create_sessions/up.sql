@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- one row per login, refresh tokens rotated from the login share its `family` and access tokens carry its id
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    family VARCHAR NOT NULL UNIQUE,
+    user_agent VARCHAR,
+    ip VARCHAR,
+    -- expiration of the latest refresh token of the session
+    expires TIMESTAMP NOT NULL,
+    last_used TIMESTAMP NOT NULL DEFAULT NOW(),
+    revoked TIMESTAMP,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
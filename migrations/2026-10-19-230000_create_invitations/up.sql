@@ -0,0 +1,32 @@
+-- Your SQL goes here
+-- NULL until the user proves the address is theirs, users with unverified address cannot log in with password.
+-- Addresses set before are trusted, they were entered by admins or came from identity providers.
+ALTER TABLE users ADD COLUMN email_verified TIMESTAMP;
+UPDATE users SET email_verified = NOW() WHERE email IS NOT NULL;
+
+-- only SHA-256 of the token is stored, invitation is accepted at most once before `expires`
+CREATE TABLE invitations (
+    id SERIAL PRIMARY KEY,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    -- invited address, the invitation link is mailed there and the new user gets it as verified email
+    email VARCHAR,
+    role VARCHAR NOT NULL,
+    invited_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    expires TIMESTAMP NOT NULL,
+    accepted TIMESTAMP,
+    accepted_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- only SHA-256 of the token is stored, `email` is the address the link was sent to
+CREATE TABLE email_verifications (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email VARCHAR NOT NULL,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    expires TIMESTAMP NOT NULL,
+    used TIMESTAMP,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX email_verifications_user_id_idx ON email_verifications (user_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE user_avatars;
+ALTER TABLE users DROP COLUMN locale;
+ALTER TABLE users DROP COLUMN timezone;
+ALTER TABLE users DROP COLUMN display_name;
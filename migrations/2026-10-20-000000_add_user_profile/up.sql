@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- `display_name` is shown instead of first and last name when set, `timezone` is IANA name, `locale` BCP 47 tag
+ALTER TABLE users ADD COLUMN display_name VARCHAR;
+ALTER TABLE users ADD COLUMN timezone VARCHAR NOT NULL DEFAULT 'UTC';
+ALTER TABLE users ADD COLUMN locale VARCHAR NOT NULL DEFAULT 'en';
+
+-- uploaded avatars are kept apart so users are not loaded with their images, identicon is generated when missing
+CREATE TABLE user_avatars (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    content_type VARCHAR NOT NULL,
+    data BYTEA NOT NULL,
+    updated TIMESTAMP NOT NULL DEFAULT NOW()
+);
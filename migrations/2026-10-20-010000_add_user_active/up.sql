@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- deactivated users cannot log in, they are kept so tickets they wrote or worked on still show who it was
+ALTER TABLE users ADD COLUMN active BOOLEAN NOT NULL DEFAULT TRUE;
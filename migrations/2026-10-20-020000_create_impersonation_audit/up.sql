@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- writes made with impersonation tokens and the impersonations themselves, users are referenced without foreign
+-- keys so the trail outlives them
+CREATE TABLE impersonation_audit (
+    id SERIAL PRIMARY KEY,
+    actor_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    method VARCHAR NOT NULL,
+    path VARCHAR NOT NULL,
+    -- status of the response, NULL until the request is handled
+    status SMALLINT,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX impersonation_audit_user_id_idx ON impersonation_audit (user_id);
+CREATE INDEX impersonation_audit_actor_id_idx ON impersonation_audit (actor_id);
@@ -0,0 +1,471 @@
+//! Full database export and import, used to clone data between environments without `pg_dump` access.
+//!
+//! Export is JSON lines. First line is [`Line::Header`] carrying format version, every other line is single
+//! DB row tagged by its type, e.g. `{"type":"user","data":{...}}`.
+
+use crate::errors::{TicxError, TicxResult};
+use chrono::NaiveDateTime;
+use db::dbo::{
+    Avatar, ExternalRef, Project, ProjectMember, ProjectTeam, SnapshotRecord, SnapshotSummary,
+    Team, TeamMember, Ticket, TicketHistory, TicketWatcher, User, UserIdentity,
+};
+use db::Db;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+pub const FORMAT: &str = "ticx-export";
+/// Bumped whenever exported records change, import accepts only exports of the same version. 6 added email,
+/// its verification, profile fields and `active` to users, 7 added their avatars and linked identities.
+pub const VERSION: u32 = 7;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Line {
+    Header {
+        format: String,
+        version: u32,
+        created: NaiveDateTime,
+    },
+    User(UserRecord),
+    UserAvatar(UserAvatarRecord),
+    UserIdentity(UserIdentityRecord),
+    Team(TeamRecord),
+    TeamMember(TeamMemberRecord),
+    Project(ProjectRecord),
+    ProjectMember(ProjectMemberRecord),
+    ProjectTeam(ProjectTeamRecord),
+    Ticket(TicketRecord),
+    TicketWatcher(TicketWatcherRecord),
+    TicketHistory(TicketHistoryRecord),
+    ExternalRef(ExternalRefRecord),
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserRecord {
+    id: i32,
+    username: String,
+    /// password hash, never plain text
+    password: String,
+    firstname: String,
+    lastname: String,
+    created: NaiveDateTime,
+    role: String,
+    email: Option<String>,
+    email_verified: Option<NaiveDateTime>,
+    display_name: Option<String>,
+    timezone: String,
+    locale: String,
+    active: bool,
+}
+
+impl std::fmt::Debug for UserRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserRecord")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .field("password", &"*censored*")
+            .field("firstname", &self.firstname)
+            .field("lastname", &self.lastname)
+            .field("created", &self.created)
+            .field("role", &self.role)
+            .field("email", &self.email)
+            .field("email_verified", &self.email_verified)
+            .field("display_name", &self.display_name)
+            .field("timezone", &self.timezone)
+            .field("locale", &self.locale)
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserAvatarRecord {
+    user_id: i32,
+    content_type: String,
+    /// base64 encoded image
+    data: String,
+    updated: NaiveDateTime,
+}
+
+impl std::fmt::Debug for UserAvatarRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserAvatarRecord")
+            .field("user_id", &self.user_id)
+            .field("content_type", &self.content_type)
+            .field(
+                "data",
+                &format_args!("{} base64 characters", self.data.len()),
+            )
+            .field("updated", &self.updated)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserIdentityRecord {
+    id: i32,
+    user_id: i32,
+    issuer: String,
+    subject: String,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamRecord {
+    id: i32,
+    name: String,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamMemberRecord {
+    team_id: i32,
+    user_id: i32,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectRecord {
+    id: i32,
+    name: String,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectMemberRecord {
+    project_id: i32,
+    user_id: i32,
+    role: String,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectTeamRecord {
+    project_id: i32,
+    team_id: i32,
+    role: String,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketRecord {
+    id: i32,
+    project_id: i32,
+    author_id: i32,
+    description: String,
+    severity: i16,
+    status: i16,
+    created: NaiveDateTime,
+    resolution: Option<String>,
+    merged_into: Option<i32>,
+    assignee_id: Option<i32>,
+    assignee_team_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketWatcherRecord {
+    id: i32,
+    ticket_id: i32,
+    user_id: Option<i32>,
+    team_id: Option<i32>,
+    created: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketHistoryRecord {
+    id: i32,
+    ticket_id: i32,
+    old_status: Option<i16>,
+    new_status: i16,
+    created: NaiveDateTime,
+    event: String,
+    related_ticket_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExternalRefRecord {
+    id: i32,
+    source: String,
+    kind: String,
+    external_id: String,
+    local_id: i32,
+    created: NaiveDateTime,
+}
+
+impl From<SnapshotRecord> for Line {
+    fn from(record: SnapshotRecord) -> Self {
+        match record {
+            SnapshotRecord::User(u) => Line::User(UserRecord {
+                id: u.id,
+                username: u.username,
+                password: u.password,
+                firstname: u.firstname,
+                lastname: u.lastname,
+                created: u.created,
+                role: u.role,
+                email: u.email,
+                email_verified: u.email_verified,
+                display_name: u.display_name,
+                timezone: u.timezone,
+                locale: u.locale,
+                active: u.active,
+            }),
+            SnapshotRecord::UserAvatar(a) => Line::UserAvatar(UserAvatarRecord {
+                user_id: a.user_id,
+                content_type: a.content_type,
+                data: base64::encode(&a.data),
+                updated: a.updated,
+            }),
+            SnapshotRecord::UserIdentity(i) => Line::UserIdentity(UserIdentityRecord {
+                id: i.id,
+                user_id: i.user_id,
+                issuer: i.issuer,
+                subject: i.subject,
+                created: i.created,
+            }),
+            SnapshotRecord::Team(t) => Line::Team(TeamRecord {
+                id: t.id,
+                name: t.name,
+                created: t.created,
+            }),
+            SnapshotRecord::TeamMember(m) => Line::TeamMember(TeamMemberRecord {
+                team_id: m.team_id,
+                user_id: m.user_id,
+                created: m.created,
+            }),
+            SnapshotRecord::ProjectTeam(g) => Line::ProjectTeam(ProjectTeamRecord {
+                project_id: g.project_id,
+                team_id: g.team_id,
+                role: g.role,
+                created: g.created,
+            }),
+            SnapshotRecord::TicketWatcher(w) => Line::TicketWatcher(TicketWatcherRecord {
+                id: w.id,
+                ticket_id: w.ticket_id,
+                user_id: w.user_id,
+                team_id: w.team_id,
+                created: w.created,
+            }),
+            SnapshotRecord::Project(p) => Line::Project(ProjectRecord {
+                id: p.id,
+                name: p.name,
+                created: p.created,
+            }),
+            SnapshotRecord::ProjectMember(m) => Line::ProjectMember(ProjectMemberRecord {
+                project_id: m.project_id,
+                user_id: m.user_id,
+                role: m.role,
+                created: m.created,
+            }),
+            SnapshotRecord::Ticket(t) => Line::Ticket(TicketRecord {
+                id: t.id,
+                project_id: t.project_id,
+                author_id: t.author_id,
+                description: t.description,
+                severity: t.severity,
+                status: t.status,
+                created: t.created,
+                resolution: t.resolution,
+                merged_into: t.merged_into,
+                assignee_id: t.assignee_id,
+                assignee_team_id: t.assignee_team_id,
+            }),
+            SnapshotRecord::TicketHistory(h) => Line::TicketHistory(TicketHistoryRecord {
+                id: h.id,
+                ticket_id: h.ticket_id,
+                old_status: h.old_status,
+                new_status: h.new_status,
+                created: h.created,
+                event: h.event,
+                related_ticket_id: h.related_ticket_id,
+            }),
+            SnapshotRecord::ExternalRef(r) => Line::ExternalRef(ExternalRefRecord {
+                id: r.id,
+                source: r.source,
+                kind: r.kind,
+                external_id: r.external_id,
+                local_id: r.local_id,
+                created: r.created,
+            }),
+        }
+    }
+}
+
+impl Line {
+    fn into_record(self) -> Result<SnapshotRecord, String> {
+        Ok(match self {
+            Line::Header { .. } => return Err("unexpected second header".into()),
+            Line::User(u) => SnapshotRecord::User(User {
+                id: u.id,
+                username: u.username,
+                password: u.password,
+                firstname: u.firstname,
+                lastname: u.lastname,
+                created: u.created,
+                role: u.role,
+                email: u.email,
+                email_verified: u.email_verified,
+                display_name: u.display_name,
+                timezone: u.timezone,
+                locale: u.locale,
+                active: u.active,
+            }),
+            Line::UserAvatar(a) => SnapshotRecord::UserAvatar(Avatar {
+                user_id: a.user_id,
+                content_type: a.content_type,
+                data: base64::decode(&a.data)
+                    .map_err(|err| format!("avatar is not base64: {}", err))?,
+                updated: a.updated,
+            }),
+            Line::UserIdentity(i) => SnapshotRecord::UserIdentity(UserIdentity {
+                id: i.id,
+                user_id: i.user_id,
+                issuer: i.issuer,
+                subject: i.subject,
+                created: i.created,
+            }),
+            Line::Team(t) => SnapshotRecord::Team(Team {
+                id: t.id,
+                name: t.name,
+                created: t.created,
+            }),
+            Line::TeamMember(m) => SnapshotRecord::TeamMember(TeamMember {
+                team_id: m.team_id,
+                user_id: m.user_id,
+                created: m.created,
+            }),
+            Line::ProjectTeam(g) => SnapshotRecord::ProjectTeam(ProjectTeam {
+                project_id: g.project_id,
+                team_id: g.team_id,
+                role: g.role,
+                created: g.created,
+            }),
+            Line::TicketWatcher(w) => SnapshotRecord::TicketWatcher(TicketWatcher {
+                id: w.id,
+                ticket_id: w.ticket_id,
+                user_id: w.user_id,
+                team_id: w.team_id,
+                created: w.created,
+            }),
+            Line::Project(p) => SnapshotRecord::Project(Project {
+                id: p.id,
+                name: p.name,
+                created: p.created,
+            }),
+            Line::ProjectMember(m) => SnapshotRecord::ProjectMember(ProjectMember {
+                project_id: m.project_id,
+                user_id: m.user_id,
+                role: m.role,
+                created: m.created,
+            }),
+            Line::Ticket(t) => SnapshotRecord::Ticket(Ticket {
+                id: t.id,
+                project_id: t.project_id,
+                author_id: t.author_id,
+                description: t.description,
+                severity: t.severity,
+                status: t.status,
+                created: t.created,
+                // rendered description is just cache, it gets rendered again when needed
+                description_html: None,
+                resolution: t.resolution,
+                merged_into: t.merged_into,
+                assignee_id: t.assignee_id,
+                assignee_team_id: t.assignee_team_id,
+            }),
+            Line::TicketHistory(h) => SnapshotRecord::TicketHistory(TicketHistory {
+                id: h.id,
+                ticket_id: h.ticket_id,
+                old_status: h.old_status,
+                new_status: h.new_status,
+                created: h.created,
+                event: h.event,
+                related_ticket_id: h.related_ticket_id,
+            }),
+            Line::ExternalRef(r) => SnapshotRecord::ExternalRef(ExternalRef {
+                id: r.id,
+                source: r.source,
+                kind: r.kind,
+                external_id: r.external_id,
+                local_id: r.local_id,
+                created: r.created,
+            }),
+        })
+    }
+}
+
+/// Writes all ticX data to `out`, rows are streamed as they are read from DB.
+#[tracing::instrument(skip(db, out))]
+pub fn export<W: Write>(db: &Db, mut out: W) -> TicxResult<SnapshotSummary> {
+    let header = Line::Header {
+        format: FORMAT.into(),
+        version: VERSION,
+        created: chrono::Utc::now().naive_utc(),
+    };
+    write_line(&mut out, &header).map_err(|error| TicxError::GenericError {
+        what: "write export header",
+        error,
+    })?;
+
+    let summary = db.export_snapshot(|record| write_line(&mut out, &Line::from(record)))?;
+
+    out.flush().map_err(|err| TicxError::GenericError {
+        what: "flush export",
+        error: err.to_string(),
+    })?;
+
+    Ok(summary)
+}
+
+fn write_line<W: Write>(out: &mut W, line: &Line) -> Result<(), String> {
+    serde_json::to_writer(&mut *out, line)
+        .map_err(|err| err.to_string())
+        .and_then(|_| out.write_all(b"\n").map_err(|err| err.to_string()))
+}
+
+/// Reads export produced by [`export`] and inserts it into DB in single transaction.
+#[tracing::instrument(skip(db, input))]
+pub fn import<R: BufRead>(db: &Db, input: R) -> TicxResult<SnapshotSummary> {
+    let mut lines = input.lines().enumerate();
+
+    match lines.next() {
+        Some((_, Ok(first))) => match serde_json::from_str::<Line>(&first) {
+            Ok(Line::Header {
+                format, version, ..
+            }) if format == FORMAT && version == VERSION => {
+                tracing::debug!(%format, %version, "export header OK")
+            }
+            Ok(Line::Header {
+                format, version, ..
+            }) => {
+                return Err(TicxError::BadRequest(format!(
+                    "unsupported export '{}' version {}, expected '{}' version {}",
+                    format, version, FORMAT, VERSION
+                )))
+            }
+            _ => {
+                return Err(TicxError::BadRequest(
+                    "export does not start with header".into(),
+                ))
+            }
+        },
+        Some((_, Err(err))) => {
+            return Err(TicxError::GenericError {
+                what: "read export header",
+                error: err.to_string(),
+            })
+        }
+        None => return Err(TicxError::BadRequest("export is empty".into())),
+    }
+
+    let records = lines
+        .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|(idx, line)| {
+            line.map_err(|err| err.to_string())
+                .and_then(|line| serde_json::from_str::<Line>(&line).map_err(|err| err.to_string()))
+                .and_then(Line::into_record)
+                .map_err(|err| format!("line {}: {}", idx + 1, err))
+        });
+
+    Ok(db.import_snapshot(records)?)
+}
@@ -0,0 +1,44 @@
+use crate::importer::Source;
+use std::path::PathBuf;
+
+const USAGE: &str = "usage:
+    ticx [serve]                              start HTTP server
+    ticx import-issues <github|jira> <file>   import issues exported from other tracker
+    ticx export <file>                        export all data as JSON lines
+    ticx import <file>                        import data produced by `ticx export`";
+
+#[derive(Debug)]
+pub enum Command {
+    Serve,
+    ImportIssues { source: Source, path: PathBuf },
+    Export { path: PathBuf },
+    Import { path: PathBuf },
+}
+
+impl Command {
+    /// Parses command line arguments, without the binary name.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+        match args.next().as_deref() {
+            None | Some("serve") => Ok(Command::Serve),
+            Some("import-issues") => {
+                let source = args
+                    .next()
+                    .ok_or_else(|| USAGE.to_string())?
+                    .parse::<Source>()
+                    .map_err(|err| format!("{}\n{}", err, USAGE))?;
+                let path = args.next().ok_or_else(|| USAGE.to_string())?;
+                Ok(Command::ImportIssues {
+                    source,
+                    path: PathBuf::from(path),
+                })
+            }
+            Some("export") => Ok(Command::Export {
+                path: PathBuf::from(args.next().ok_or_else(|| USAGE.to_string())?),
+            }),
+            Some("import") => Ok(Command::Import {
+                path: PathBuf::from(args.next().ok_or_else(|| USAGE.to_string())?),
+            }),
+            Some(unknown) => Err(format!("unknown command '{}'\n{}", unknown, USAGE)),
+        }
+    }
+}
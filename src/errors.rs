@@ -1,5 +1,6 @@
 use actix_web::error::BlockingError;
-use actix_web::http::StatusCode;
+use actix_web::http::{header, StatusCode};
+use actix_web::HttpResponse;
 
 pub type TicxResult<T> = Result<T, TicxError>;
 
@@ -25,6 +26,14 @@ pub enum TicxError {
     GenericError { what: &'static str, error: String },
     #[error("requested data not found. error: {0}")]
     NotFound(String),
+    #[error("invalid request. Reason: {0}")]
+    BadRequest(String),
+    #[error("operation not permitted. Reason: {0}")]
+    Forbidden(String),
+    #[error("conflict with current state. Reason: {0}")]
+    Conflict(String),
+    #[error("too many failed login attempts, retry after {retry_after} seconds")]
+    TooManyRequests { retry_after: i64 },
 }
 
 // this shows error because it cannot identify std::fmt::Display being derived
@@ -35,9 +44,23 @@ impl actix_web::error::ResponseError for TicxError {
             Self::InvalidToken(_) => StatusCode::UNAUTHORIZED,
             Self::InvalidCredentials => StatusCode::NOT_FOUND,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::TooManyRequests { retry_after } = self {
+            response.set_header(header::RETRY_AFTER, retry_after.to_string());
+        }
+        response
+            .content_type("text/plain; charset=utf-8")
+            .body(self.to_string())
+    }
 }
 
 use db::errors::DbError;
@@ -50,6 +73,8 @@ impl From<DbError> for TicxError {
             | DbError::QueryExecuteError { .. } => Self::DbFail(db_error.to_string()),
             // DbError::NoConnectionAvailable(_) => (),
             DbError::NotFound(_) => TicxError::NotFound(db_error.to_string()),
+            DbError::InvalidData(_) => TicxError::BadRequest(db_error.to_string()),
+            DbError::Conflict(_) => TicxError::Conflict(db_error.to_string()),
             _ => Self::Unknown,
         }
     }
@@ -63,3 +88,12 @@ impl From<BlockingError<DbError>> for TicxError {
         }
     }
 }
+
+impl From<BlockingError<TicxError>> for TicxError {
+    fn from(b_err: BlockingError<TicxError>) -> Self {
+        match b_err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => TicxError::Unknown,
+        }
+    }
+}
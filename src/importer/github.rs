@@ -0,0 +1,105 @@
+use super::{
+    compose_description, invalid_format, severity_from_names, unusable_password, Comment, Source,
+};
+use crate::errors::TicxResult;
+use db::dbo::{status, ExternalBatch, ExternalTicket, ExternalUser};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Single issue as returned by `GET /repos/{owner}/{repo}/issues`. Comments are expected in `comment_data`,
+/// the way common backup tools store them, since `comments` only holds their count.
+#[derive(Debug, Deserialize)]
+struct Issue {
+    number: i64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    state_reason: Option<String>,
+    user: Account,
+    #[serde(default)]
+    labels: Vec<Label>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    comment_data: Vec<IssueComment>,
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Account {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssueComment {
+    user: Account,
+    body: Option<String>,
+    created_at: String,
+}
+
+pub(super) fn parse(raw: &[u8]) -> TicxResult<ExternalBatch> {
+    let issues: Vec<Issue> =
+        serde_json::from_slice(raw).map_err(|err| invalid_format(Source::GitHub, err))?;
+
+    let mut users = BTreeMap::new();
+    let mut tickets = vec![];
+
+    // GitHub lists pull requests as issues too, those have no place in bug tracker
+    for issue in issues.into_iter().filter(|i| i.pull_request.is_none()) {
+        let labels = issue
+            .labels
+            .into_iter()
+            .map(|l| l.name)
+            .collect::<Vec<String>>();
+
+        let comments = issue
+            .comment_data
+            .into_iter()
+            .map(|c| Comment {
+                author: c.user.login,
+                created: c.created_at,
+                body: c.body.unwrap_or_default(),
+            })
+            .collect::<Vec<Comment>>();
+
+        let status = match (issue.state.as_str(), issue.state_reason.as_deref()) {
+            ("open", _) => status::OPEN,
+            (_, Some("not_planned")) => status::CLOSED,
+            _ => status::RESOLVED,
+        };
+
+        users
+            .entry(issue.user.login.clone())
+            .or_insert_with(|| ExternalUser {
+                external_id: issue.user.login.clone(),
+                username: issue.user.login.clone(),
+                password: unusable_password(),
+                firstname: issue.user.login.clone(),
+                lastname: String::new(),
+            });
+
+        tickets.push(ExternalTicket {
+            external_id: issue.number.to_string(),
+            author_external_id: issue.user.login,
+            description: compose_description(
+                &issue.title,
+                issue.body.as_deref().unwrap_or_default(),
+                &labels,
+                &comments,
+            ),
+            severity: severity_from_names(labels.iter().map(String::as_str)),
+            status,
+            created: Some(issue.created_at.naive_utc()),
+            links: vec![],
+        });
+    }
+
+    Ok(ExternalBatch {
+        users: users.into_values().collect(),
+        tickets,
+    })
+}
@@ -0,0 +1,266 @@
+use super::{
+    compose_description, invalid_format, severity_from_names, split_name, unusable_password,
+    Comment, Source,
+};
+use crate::errors::TicxResult;
+use db::dbo::{status, ExternalBatch, ExternalLink, ExternalTicket, ExternalUser};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Jira exports timestamps as `2021-10-15T14:45:42.000+0000` which is not quite RFC 3339.
+const JIRA_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f%z";
+
+/// Output of Jira REST search (`/rest/api/2/search`) saved to a file.
+#[derive(Debug, Deserialize)]
+struct Export {
+    issues: Vec<Issue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Issue {
+    id: String,
+    key: String,
+    fields: Fields,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fields {
+    summary: String,
+    /// plain text in API v2, Atlassian Document Format object in v3
+    description: Option<serde_json::Value>,
+    status: Status,
+    priority: Option<Named>,
+    #[serde(default)]
+    labels: Vec<String>,
+    reporter: Option<Account>,
+    created: Option<String>,
+    comment: Option<Comments>,
+    #[serde(default)]
+    issuelinks: Vec<IssueLink>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    name: String,
+    status_category: Option<StatusCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusCategory {
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    account_id: Option<String>,
+    name: Option<String>,
+    email_address: Option<String>,
+    display_name: Option<String>,
+}
+
+impl Account {
+    /// Jira Cloud only has `accountId`, Jira Server only `name`.
+    fn external_id(&self) -> Option<String> {
+        self.account_id.clone().or_else(|| self.name.clone())
+    }
+
+    fn username(&self) -> Option<String> {
+        self.name
+            .clone()
+            .or_else(|| {
+                self.email_address
+                    .as_deref()
+                    .and_then(|email| email.split('@').next())
+                    .map(String::from)
+            })
+            .or_else(|| self.account_id.clone())
+    }
+
+    fn display_name(&self) -> String {
+        self.display_name
+            .clone()
+            .or_else(|| self.username())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Comments {
+    #[serde(default)]
+    comments: Vec<IssueComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssueComment {
+    author: Option<Account>,
+    body: Option<serde_json::Value>,
+    created: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IssueLink {
+    #[serde(rename = "type")]
+    link_type: LinkType,
+    outward_issue: Option<LinkedIssue>,
+    inward_issue: Option<LinkedIssue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinkType {
+    inward: String,
+    outward: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinkedIssue {
+    id: String,
+    key: String,
+}
+
+/// Unknown reporter (e.g. deleted account) still has to map to some user.
+const UNKNOWN_REPORTER: &str = "jira-unknown-reporter";
+
+pub(super) fn parse(raw: &[u8]) -> TicxResult<ExternalBatch> {
+    let export: Export =
+        serde_json::from_slice(raw).map_err(|err| invalid_format(Source::Jira, err))?;
+
+    let mut users = BTreeMap::new();
+    let mut tickets = vec![];
+
+    for issue in export.issues {
+        let fields = issue.fields;
+
+        let author_external_id = match fields.reporter.as_ref().and_then(Account::external_id) {
+            Some(external_id) => {
+                let reporter = fields.reporter.as_ref().unwrap(); // external id can only exist if reporter does
+                users
+                    .entry(external_id.clone())
+                    .or_insert_with(|| new_user(&external_id, reporter));
+                external_id
+            }
+            None => {
+                users
+                    .entry(UNKNOWN_REPORTER.to_string())
+                    .or_insert_with(|| ExternalUser {
+                        external_id: UNKNOWN_REPORTER.into(),
+                        username: UNKNOWN_REPORTER.into(),
+                        password: unusable_password(),
+                        firstname: "Unknown".into(),
+                        lastname: "Reporter".into(),
+                    });
+                UNKNOWN_REPORTER.to_string()
+            }
+        };
+
+        let comments = fields
+            .comment
+            .map(|c| c.comments)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| Comment {
+                author: c
+                    .author
+                    .as_ref()
+                    .map(Account::display_name)
+                    .unwrap_or_else(|| "unknown".into()),
+                created: c.created.unwrap_or_default(),
+                body: text(c.body),
+            })
+            .collect::<Vec<Comment>>();
+
+        let links = fields
+            .issuelinks
+            .into_iter()
+            .filter_map(|link| match (link.outward_issue, link.inward_issue) {
+                (Some(target), _) => Some((link.link_type.outward, target)),
+                (None, Some(target)) => Some((link.link_type.inward, target)),
+                (None, None) => None,
+            })
+            .map(|(relation, target)| ExternalLink {
+                relation,
+                target_external_id: target.id,
+                target_label: target.key,
+            })
+            .collect();
+
+        let status = match (
+            fields.status.name.to_lowercase().as_str(),
+            fields
+                .status
+                .status_category
+                .as_ref()
+                .map(|c| c.key.as_str()),
+        ) {
+            ("closed", _) => status::CLOSED,
+            (_, Some("done")) => status::RESOLVED,
+            (_, Some("indeterminate")) => status::IN_PROGRESS,
+            _ => status::OPEN,
+        };
+
+        let created = fields.created.as_deref().and_then(|created| {
+            chrono::DateTime::parse_from_str(created, JIRA_DATE_FORMAT)
+                .map_err(|err| tracing::warn!(%err, %created, key = %issue.key, "unparsable creation date"))
+                .ok()
+                .map(|c| c.naive_utc())
+        });
+
+        let severity = severity_from_names(
+            fields
+                .priority
+                .iter()
+                .map(|p| p.name.as_str())
+                .chain(fields.labels.iter().map(String::as_str)),
+        );
+
+        tickets.push(ExternalTicket {
+            external_id: issue.id,
+            author_external_id,
+            description: compose_description(
+                &format!("[{}] {}", issue.key, fields.summary),
+                &text(fields.description),
+                &fields.labels,
+                &comments,
+            ),
+            severity,
+            status,
+            created,
+            links,
+        });
+    }
+
+    Ok(ExternalBatch {
+        users: users.into_values().collect(),
+        tickets,
+    })
+}
+
+fn new_user(external_id: &str, account: &Account) -> ExternalUser {
+    let (firstname, lastname) = split_name(&account.display_name());
+    ExternalUser {
+        external_id: external_id.to_string(),
+        username: account
+            .username()
+            .unwrap_or_else(|| external_id.to_string()),
+        password: unusable_password(),
+        firstname,
+        lastname,
+    }
+}
+
+/// Text of v2 API string fields, v3 rich text documents are kept as their JSON so nothing is lost.
+fn text(value: Option<serde_json::Value>) -> String {
+    match value {
+        Some(serde_json::Value::String(s)) => s,
+        Some(serde_json::Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
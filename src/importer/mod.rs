@@ -0,0 +1,150 @@
+//! Imports issues exported from other trackers. Each source only parses its own format into
+//! [`db::dbo::ExternalBatch`], storing and de-duplicating of imported data is done by [`db::Db::import_external`].
+//!
+//! ticX has no comments, labels or links yet, so those are folded into ticket description to not lose them.
+
+mod github;
+mod jira;
+
+use crate::errors::{TicxError, TicxResult};
+use db::dbo::ImportSummary;
+use db::Db;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::path::Path;
+
+const DEFAULT_SEVERITY: i16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    GitHub,
+    Jira,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::GitHub => "github",
+            Source::Jira => "jira",
+        }
+    }
+}
+
+impl std::str::FromStr for Source {
+    type Err = TicxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(Source::GitHub),
+            "jira" => Ok(Source::Jira),
+            unknown => Err(TicxError::BadRequest(format!(
+                "unknown import source '{}', expected 'github' or 'jira'",
+                unknown
+            ))),
+        }
+    }
+}
+
+/// Reads export of given `source` from `path` and imports it. Blocking, use `web::block` when called from handlers.
+#[tracing::instrument(skip(db))]
+pub fn import_file(db: &Db, source: Source, path: &Path) -> TicxResult<ImportSummary> {
+    let raw = std::fs::read(path).map_err(|err| {
+        tracing::error!(%err, "failed to read import file");
+        TicxError::GenericError {
+            what: "read import file",
+            error: err.to_string(),
+        }
+    })?;
+
+    let batch = match source {
+        Source::GitHub => github::parse(&raw)?,
+        Source::Jira => jira::parse(&raw)?,
+    };
+
+    tracing::debug!(
+        users = batch.users.len(),
+        tickets = batch.tickets.len(),
+        "import file parsed"
+    );
+
+    let summary = db.import_external(source.as_str(), batch)?;
+    tracing::info!(?summary, "import finished");
+    Ok(summary)
+}
+
+/// Comment, kept only as a part of ticket description.
+struct Comment {
+    author: String,
+    created: String,
+    body: String,
+}
+
+fn compose_description(title: &str, body: &str, labels: &[String], comments: &[Comment]) -> String {
+    let mut description = title.trim().to_string();
+
+    if !body.trim().is_empty() {
+        description.push_str("\n\n");
+        description.push_str(body.trim());
+    }
+
+    if !labels.is_empty() {
+        description.push_str("\n\nLabels: ");
+        description.push_str(&labels.join(", "));
+    }
+
+    for comment in comments {
+        description.push_str(&format!(
+            "\n\n---\n{} wrote on {}:\n{}",
+            comment.author,
+            comment.created,
+            comment.body.trim()
+        ));
+    }
+
+    description
+}
+
+/// Maps priority or label names commonly used by other trackers to our severity.
+fn severity_from_name(name: &str) -> Option<i16> {
+    match name.trim().to_lowercase().as_str() {
+        "blocker" | "critical" | "highest" | "severity:critical" => Some(4),
+        "high" | "major" | "severity:high" => Some(3),
+        "medium" | "normal" | "severity:medium" => Some(2),
+        "low" | "minor" | "severity:low" => Some(1),
+        "lowest" | "trivial" => Some(0),
+        _ => None,
+    }
+}
+
+fn severity_from_names<'a, I: IntoIterator<Item = &'a str>>(names: I) -> i16 {
+    names
+        .into_iter()
+        .filter_map(severity_from_name)
+        .max()
+        .unwrap_or(DEFAULT_SEVERITY)
+}
+
+/// Imported users cannot log in until their password is changed, so the password is just a random string.
+fn unusable_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn split_name(display_name: &str) -> (String, String) {
+    match display_name.trim().split_once(' ') {
+        Some((first, last)) => (first.to_string(), last.trim().to_string()),
+        None => (display_name.trim().to_string(), String::new()),
+    }
+}
+
+fn invalid_format(source: Source, err: serde_json::Error) -> TicxError {
+    tracing::error!(%err, source = source.as_str(), "failed to parse import file");
+    TicxError::BadRequest(format!(
+        "file is not valid {} export: {}",
+        source.as_str(),
+        err
+    ))
+}
@@ -1,5 +1,9 @@
 mod app;
+mod backup;
+mod cli;
 mod errors;
+mod importer;
+mod markdown;
 mod metrics;
 mod server;
 mod tracer;
@@ -12,7 +16,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _guard = tracer::init();
     tracing::info!(version = env!("CARGO_PKG_VERSION"), "ticX App starting");
 
-    crate::app::TicXApp::run().await?;
+    let command = cli::Command::from_args(std::env::args().skip(1))?;
+    crate::app::TicXApp::run(command).await?;
     tracing::trace!("server shutdown");
 
     Ok(())
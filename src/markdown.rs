@@ -0,0 +1,210 @@
+//! Ticket descriptions are CommonMark. Rendered HTML is sanitized, fenced code blocks are highlighted
+//! (with CSS classes, the UI provides the theme) and `#123` / `@username` references are turned into links.
+
+use db::dbo::Ticket;
+use db::errors::DbResult;
+use db::Db;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+lazy_static::lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    // reference has to start a word, so URL fragments and e-mail addresses are left alone
+    static ref REFERENCE: Regex = Regex::new(
+        r"(?P<pre>^|[^\w/&@#])(?:#(?P<ticket>\d+)|@(?P<username>[A-Za-z0-9_][A-Za-z0-9_.\-]*[A-Za-z0-9_]|[A-Za-z0-9_]))"
+    )
+    .unwrap();
+}
+
+/// Usernames mentioned in text outside of code and links.
+pub fn mentions(markdown: &str) -> HashSet<String> {
+    let mut mentions = HashSet::new();
+    visit_text(markdown, |text| {
+        for captures in REFERENCE.captures_iter(text) {
+            if let Some(username) = captures.name("username") {
+                mentions.insert(username.as_str().to_string());
+            }
+        }
+    });
+    mentions
+}
+
+/// Renders markdown to sanitized HTML. Only mentions of users present in `users` (username -> id) are linked.
+pub fn render(markdown: &str, users: &HashMap<String, i32>) -> String {
+    let mut events = vec![];
+    let mut code_block: Option<(String, String)> = None;
+    let mut link_depth = 0;
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((language, String::new()));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((language, code)) = code_block.take() {
+                    events.push(Event::Html(highlight(&language, &code).into()));
+                }
+            }
+            Event::Text(text) if code_block.is_some() => {
+                if let Some((_, code)) = code_block.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::Start(Tag::Link(..)) => {
+                link_depth += 1;
+                events.push(event);
+            }
+            Event::End(Tag::Link(..)) => {
+                link_depth -= 1;
+                events.push(event);
+            }
+            Event::Text(text) if link_depth == 0 => autolink(text, users, &mut events),
+            other => events.push(other),
+        }
+    }
+
+    let mut html = String::with_capacity(markdown.len() * 2);
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+
+    ammonia::Builder::default()
+        .add_tag_attributes("pre", &["class"])
+        .add_tag_attributes("code", &["class"])
+        .add_tag_attributes("span", &["class"])
+        .add_allowed_classes("a", &["ticket-ref", "user-ref"])
+        .clean(&html)
+        .to_string()
+}
+
+/// Renders descriptions of tickets which have no cached HTML yet and stores the result.
+#[tracing::instrument(skip(db, tickets))]
+pub fn render_missing(db: &Db, tickets: &mut [Ticket]) -> DbResult<()> {
+    let mut missing = tickets
+        .iter_mut()
+        .filter(|t| t.description_html.is_none())
+        .collect::<Vec<&mut Ticket>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let usernames = missing
+        .iter()
+        .flat_map(|t| mentions(&t.description))
+        .collect::<HashSet<String>>();
+
+    let users = if usernames.is_empty() {
+        HashMap::new()
+    } else {
+        db.select_user_ids_by_username(usernames.into_iter().collect())?
+            .into_iter()
+            .collect()
+    };
+
+    for ticket in missing.iter_mut() {
+        let html = render(&ticket.description, &users);
+        tracing::trace!(ticket_id = ticket.id, "rendered ticket description");
+        db.cache_description_html(ticket.id, &ticket.description, &html)?;
+        ticket.description_html = Some(html);
+    }
+
+    Ok(())
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+fn visit_text<F: FnMut(&str)>(markdown: &str, mut visit: F) {
+    let mut in_code_block = false;
+    let mut link_depth = 0;
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Start(Tag::Link(..)) => link_depth += 1,
+            Event::End(Tag::Link(..)) => link_depth -= 1,
+            Event::Text(text) if !in_code_block && link_depth == 0 => visit(&text),
+            _ => (),
+        }
+    }
+}
+
+fn autolink<'a>(text: CowStr<'a>, users: &HashMap<String, i32>, events: &mut Vec<Event<'a>>) {
+    let mut last = 0;
+
+    for captures in REFERENCE.captures_iter(&text) {
+        let link = match (captures.name("ticket"), captures.name("username")) {
+            (Some(ticket), _) => format!(
+                r#"<a href="/api/ticket/{id}" class="ticket-ref">#{id}</a>"#,
+                id = ticket.as_str()
+            ),
+            (None, Some(username)) => match users.get(username.as_str()) {
+                Some(user_id) => format!(
+                    r#"<a href="/api/user/{}" class="user-ref">@{}</a>"#,
+                    user_id,
+                    username.as_str()
+                ),
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+
+        // `pre` is the character preceding the reference, it stays part of the plain text
+        let start = captures
+            .name("pre")
+            .map(|pre| pre.end())
+            .unwrap_or_default();
+        if start > last {
+            events.push(Event::Text(text[last..start].to_string().into()));
+        }
+        events.push(Event::Html(link.into()));
+        last = captures.get(0).map(|m| m.end()).unwrap_or(start);
+    }
+
+    match last {
+        0 => events.push(Event::Text(text)),
+        _ if last < text.len() => events.push(Event::Text(text[last..].to_string().into())),
+        _ => (),
+    }
+}
+
+fn highlight(language: &str, code: &str) -> String {
+    // language ends up in class attribute, so it must not contain anything unexpected
+    let language = language
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '+' || *c == '#')
+        .collect::<String>();
+
+    let syntax = SYNTAX_SET
+        .find_syntax_by_token(&language)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, ClassStyle::Spaced);
+
+    let highlighted = LinesWithEndings::from(code)
+        .try_for_each(|line| generator.parse_html_for_line_which_includes_newline(line))
+        .map(|_| generator.finalize())
+        .unwrap_or_else(|err| {
+            tracing::warn!(%err, %language, "failed to highlight code block");
+            let mut escaped = String::with_capacity(code.len());
+            let _ = pulldown_cmark::escape::escape_html(&mut escaped, code);
+            escaped
+        });
+
+    format!(
+        r#"<pre class="code"><code class="language-{}">{}</code></pre>"#,
+        language, highlighted
+    )
+}
@@ -1,13 +1,32 @@
-use prometheus::{
-    labels, opts, register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec,
-};
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
 
 pub const DB_TABLE_USERS: &str = "USERS";
 pub const DB_TABLE_TICKETS: &str = "TICKETS";
+pub const DB_TABLE_TICKET_HISTORY: &str = "TICKET_HISTORY";
+pub const DB_TABLE_PROJECTS: &str = "PROJECTS";
+pub const DB_TABLE_PROJECT_MEMBERS: &str = "PROJECT_MEMBERS";
+pub const DB_TABLE_TEAMS: &str = "TEAMS";
+pub const DB_TABLE_TEAM_MEMBERS: &str = "TEAM_MEMBERS";
+pub const DB_TABLE_PROJECT_TEAMS: &str = "PROJECT_TEAMS";
+pub const DB_TABLE_TICKET_WATCHERS: &str = "TICKET_WATCHERS";
+pub const DB_TABLE_REFRESH_TOKENS: &str = "REFRESH_TOKENS";
+pub const DB_TABLE_PASSWORD_RESETS: &str = "PASSWORD_RESETS";
+pub const DB_TABLE_LOGIN_FAILURES: &str = "LOGIN_FAILURES";
+pub const DB_TABLE_PERSONAL_ACCESS_TOKENS: &str = "PERSONAL_ACCESS_TOKENS";
+pub const DB_TABLE_OIDC_LOGINS: &str = "OIDC_LOGINS";
+pub const DB_TABLE_USER_IDENTITIES: &str = "USER_IDENTITIES";
+pub const DB_TABLE_USER_TOTP: &str = "USER_TOTP";
+pub const DB_TABLE_SESSIONS: &str = "SESSIONS";
+pub const DB_TABLE_INVITATIONS: &str = "INVITATIONS";
+pub const DB_TABLE_EMAIL_VERIFICATIONS: &str = "EMAIL_VERIFICATIONS";
+pub const DB_TABLE_USER_AVATARS: &str = "USER_AVATARS";
+pub const DB_TABLE_IMPERSONATION_AUDIT: &str = "IMPERSONATION_AUDIT";
 
 lazy_static::lazy_static! {
     pub static ref HTTP_REQUEST_COUNTER: IntCounterVec = register_int_counter_vec!("http_request_total", "counts number of received requests", &["method"]).unwrap();
     pub static ref HTTP_RESPONSE_COUNTER: IntCounterVec = register_int_counter_vec!("http_response_total", "counts number of responses sent", &["status_code"]).unwrap();
+    pub static ref LOGIN_LOCKOUT_COUNTER: IntCounterVec = register_int_counter_vec!("login_lockout_total", "counts logins locked after too many failures", &["kind"]).unwrap();
+    pub static ref LOGIN_LOCKED_REJECTION_COUNTER: IntCounterVec = register_int_counter_vec!("login_locked_rejection_total", "counts login attempts refused because of lockout", &["kind"]).unwrap();
     pub static ref HTTP_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!("http_request_duration_seconds", "measurement of how long it took to process request", &["handler"]).unwrap();
     pub static ref DB_QUERY_HISTOGRAM: HistogramVec = register_histogram_vec!("db_query_duration_seconds", "measurement how long it takes to process DB query", &["table", "query"]).unwrap();
 }
@@ -0,0 +1,102 @@
+//! Users of Apache htpasswd file, configured by environment:
+//!
+//! - `TICX_HTPASSWD_FILE` - path of the file, it is read once at start.
+//!
+//! Passwords hashed by bcrypt (`htpasswd -B`), SHA-1 (`htpasswd -s`) and crypt (`$1$`, `$5$`, `$6$`) are
+//! supported. Apache MD5 (`$apr1$`, default of `htpasswd -m`) is not, such users are skipped.
+
+use super::AuthProvider;
+use crate::errors::{TicxError, TicxResult};
+use crate::server::routes::auth::random_token;
+use db::dbo::{NewUser, User};
+use db::Db;
+use std::collections::HashMap;
+
+/// Issuer of identities linked to users of the file.
+const ISSUER: &str = "htpasswd";
+
+pub(crate) struct HtpasswdProvider {
+    /// username -> password hash
+    users: HashMap<String, String>,
+}
+
+impl HtpasswdProvider {
+    /// Parses content of htpasswd file, `user:hash` per line.
+    pub fn parse(content: &str) -> TicxResult<Self> {
+        let mut users = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (username, hash) = line
+                .split_once(':')
+                .ok_or_else(|| TicxError::GenericError {
+                    what: "read htpasswd file",
+                    error: format!("line {} is not 'user:hash'", number + 1),
+                })?;
+            if hash.starts_with("$apr1$") {
+                tracing::warn!(%username, "Apache MD5 password hash is not supported, user is skipped");
+                continue;
+            }
+            users.insert(username.to_string(), hash.to_string());
+        }
+        Ok(HtpasswdProvider { users })
+    }
+
+    pub fn from_env() -> TicxResult<Self> {
+        let path = dotenv::var("TICX_HTPASSWD_FILE").map_err(|_| TicxError::GenericError {
+            what: "read htpasswd file",
+            error: "TICX_HTPASSWD_FILE is required by htpasswd provider".into(),
+        })?;
+        let content = std::fs::read_to_string(&path).map_err(|err| TicxError::GenericError {
+            what: "read htpasswd file",
+            error: format!("'{}': {}", path, err),
+        })?;
+
+        let provider = HtpasswdProvider::parse(&content)?;
+        tracing::info!(%path, users = provider.users.len(), "htpasswd file loaded");
+        Ok(provider)
+    }
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    match hash.strip_prefix("{SHA}") {
+        Some(digest) => {
+            let computed = base64::encode(ring::digest::digest(
+                &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+                password.as_bytes(),
+            ));
+            ring::constant_time::verify_slices_are_equal(computed.as_bytes(), digest.as_bytes())
+                .is_ok()
+        }
+        None => pwhash::unix::verify(password, hash),
+    }
+}
+
+impl AuthProvider for HtpasswdProvider {
+    fn name(&self) -> &'static str {
+        "htpasswd"
+    }
+
+    fn authenticate(&self, db: &Db, username: &str, password: &str) -> TicxResult<Option<User>> {
+        match self.users.get(username) {
+            Some(hash) if verify(password, hash) => db
+                .link_identity(
+                    ISSUER,
+                    username,
+                    NewUser::new(
+                        username.to_string(),
+                        random_token(32),
+                        String::new(),
+                        String::new(),
+                    ),
+                    false,
+                )
+                .map(Some)
+                .map_err(TicxError::from),
+            _ => Ok(None),
+        }
+    }
+}
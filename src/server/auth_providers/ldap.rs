@@ -0,0 +1,191 @@
+//! Users of LDAP directory, who log in by binding as their own entry. Configured by environment:
+//!
+//! - `TICX_LDAP_URL` - e.g. `ldaps://ldap.example.org`.
+//! - `TICX_LDAP_USER_DN` - DN users bind as, `{username}` is replaced by the escaped username,
+//!   e.g. `uid={username},ou=people,dc=example,dc=org`.
+//! - `TICX_LDAP_TIMEOUT_SECONDS` - timeout of connecting and of each operation, 5 by default.
+//! - `TICX_LDAP_LINK_BY_EMAIL` - `true` or `1` links user logging in for the first time to existing ticX user
+//!   with the same verified email. Off by default, turn it on only when users cannot change `mail` of their
+//!   entries, they could take over ticX accounts otherwise.
+//!
+//! Names and email of created ticX users are taken from `givenName`, `sn` and `mail` of the entry. Email which
+//! is already taken by another ticX user is left out.
+
+use super::AuthProvider;
+use crate::errors::{TicxError, TicxResult};
+use crate::server::routes::auth::random_token;
+use db::dbo::{NewUser, User};
+use db::Db;
+use ldap3::{LdapConn, LdapConnSettings, Scope, SearchEntry};
+use std::time::Duration;
+
+/// Issuer of identities linked to entries of the directory, the DN is their subject.
+const ISSUER: &str = "ldap";
+const USERNAME_PLACEHOLDER: &str = "{username}";
+/// result code of bind with wrong DN or password, see RFC 4511
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Attributes of user entry.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LdapEntry {
+    pub given_name: Option<String>,
+    pub surname: Option<String>,
+    pub mail: Option<String>,
+}
+
+/// Directory users bind to, [`Ldap3Directory`] talks to LDAP server, tests use a fake.
+pub(crate) trait LdapDirectory: Send + Sync {
+    /// Binds as `dn` and reads its entry, `None` when the bind is refused.
+    fn bind(&self, dn: &str, password: &str) -> TicxResult<Option<LdapEntry>>;
+}
+
+pub(crate) struct Ldap3Directory {
+    url: String,
+    timeout: Duration,
+}
+
+impl Ldap3Directory {
+    pub fn new(url: &str, timeout: Duration) -> Self {
+        Ldap3Directory {
+            url: url.into(),
+            timeout,
+        }
+    }
+}
+
+impl LdapDirectory for Ldap3Directory {
+    #[tracing::instrument(skip(self, password))]
+    fn bind(&self, dn: &str, password: &str) -> TicxResult<Option<LdapEntry>> {
+        let mut conn = LdapConn::with_settings(
+            LdapConnSettings::new().set_conn_timeout(self.timeout),
+            &self.url,
+        )
+        .map_err(ldap_error)?;
+
+        let bind = conn
+            .with_timeout(self.timeout)
+            .simple_bind(dn, password)
+            .map_err(ldap_error)?;
+        if bind.rc == INVALID_CREDENTIALS {
+            return Ok(None);
+        }
+        bind.success().map_err(ldap_error)?;
+
+        let (entries, _) = conn
+            .with_timeout(self.timeout)
+            .search(
+                dn,
+                Scope::Base,
+                "(objectClass=*)",
+                vec!["givenName", "sn", "mail"],
+            )
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+        if let Err(err) = conn.unbind() {
+            tracing::debug!(%err, "failed to unbind from LDAP");
+        }
+
+        let attributes = entries
+            .into_iter()
+            .next()
+            .map(|entry| SearchEntry::construct(entry).attrs)
+            .unwrap_or_default();
+        let attribute = |name: &str| attributes.get(name).and_then(|v| v.first()).cloned();
+        Ok(Some(LdapEntry {
+            given_name: attribute("givenName"),
+            surname: attribute("sn"),
+            mail: attribute("mail"),
+        }))
+    }
+}
+
+pub(crate) struct LdapProvider {
+    directory: Box<dyn LdapDirectory>,
+    user_dn: String,
+    link_by_email: bool,
+}
+
+impl LdapProvider {
+    pub fn new(directory: Box<dyn LdapDirectory>, user_dn: &str) -> TicxResult<Self> {
+        if !user_dn.contains(USERNAME_PLACEHOLDER) {
+            return Err(ldap_error(format!(
+                "user DN '{}' has no {} placeholder",
+                user_dn, USERNAME_PLACEHOLDER
+            )));
+        }
+        Ok(LdapProvider {
+            directory,
+            user_dn: user_dn.into(),
+            link_by_email: false,
+        })
+    }
+
+    /// Links new identities to existing users by email of their entry.
+    pub fn with_link_by_email(mut self, link_by_email: bool) -> Self {
+        self.link_by_email = link_by_email;
+        self
+    }
+
+    pub fn from_env() -> TicxResult<Self> {
+        let url = dotenv::var("TICX_LDAP_URL")
+            .map_err(|_| ldap_error("TICX_LDAP_URL is required by ldap provider"))?;
+        let user_dn = dotenv::var("TICX_LDAP_USER_DN")
+            .map_err(|_| ldap_error("TICX_LDAP_USER_DN is required by ldap provider"))?;
+        let timeout = dotenv::var("TICX_LDAP_TIMEOUT_SECONDS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(5);
+        let link_by_email = dotenv::var("TICX_LDAP_LINK_BY_EMAIL")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        LdapProvider::new(
+            Box::new(Ldap3Directory::new(&url, Duration::from_secs(timeout))),
+            &user_dn,
+        )
+        .map(|provider| provider.with_link_by_email(link_by_email))
+    }
+}
+
+impl AuthProvider for LdapProvider {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate(&self, db: &Db, username: &str, password: &str) -> TicxResult<Option<User>> {
+        // bind with empty password is anonymous and succeeds for any DN
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let dn = self
+            .user_dn
+            .replace(USERNAME_PLACEHOLDER, &ldap3::dn_escape(username));
+        let entry = match self.directory.bind(&dn, password)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        db.link_identity(
+            ISSUER,
+            &dn,
+            NewUser::new(
+                username.to_string(),
+                random_token(32),
+                entry.given_name.unwrap_or_default(),
+                entry.surname.unwrap_or_default(),
+            )
+            .with_email(entry.mail),
+            self.link_by_email,
+        )
+        .map(Some)
+        .map_err(TicxError::from)
+    }
+}
+
+fn ldap_error<E: std::fmt::Display>(err: E) -> TicxError {
+    TicxError::GenericError {
+        what: "authenticate against LDAP",
+        error: err.to_string(),
+    }
+}
@@ -0,0 +1,111 @@
+//! Providers checking username and password of basic auth login, configured by environment:
+//!
+//! - `TICX_AUTH_PROVIDERS` - comma separated providers tried in the given order until one of them accepts
+//!   the credentials, `db` by default. Providers are `db` (passwords of ticX users), `ldap` and `htpasswd`,
+//!   see their modules for configuration.
+//!
+//! Users of `ldap` and `htpasswd` are linked to ticX users the same way as OpenID Connect identities, ticX user
+//! is created on their first login.
+
+pub(crate) mod htpasswd;
+pub(crate) mod ldap;
+
+use crate::errors::{TicxError, TicxResult};
+use db::dbo::User;
+use db::Db;
+
+pub(crate) trait AuthProvider: Send + Sync {
+    /// Name the provider is configured by, e.g. `ldap`.
+    fn name(&self) -> &'static str;
+
+    /// User the credentials belong to, `None` when the provider does not know the username or the password
+    /// does not match.
+    fn authenticate(&self, db: &Db, username: &str, password: &str) -> TicxResult<Option<User>>;
+}
+
+/// Passwords of ticX users stored in the DB.
+pub(crate) struct DbProvider;
+
+impl AuthProvider for DbProvider {
+    fn name(&self) -> &'static str {
+        "db"
+    }
+
+    fn authenticate(&self, db: &Db, username: &str, password: &str) -> TicxResult<Option<User>> {
+        match db.check_credentials(username, password) {
+            Ok(user) => Ok(Some(user)),
+            Err(db::errors::DbError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Ordered chain of providers.
+pub(crate) struct AuthProviders(Vec<Box<dyn AuthProvider>>);
+
+impl AuthProviders {
+    pub fn new(providers: Vec<Box<dyn AuthProvider>>) -> Self {
+        AuthProviders(providers)
+    }
+
+    pub fn from_env() -> TicxResult<Self> {
+        let names = dotenv::var("TICX_AUTH_PROVIDERS").unwrap_or_else(|_| "db".into());
+        let providers = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| -> TicxResult<Box<dyn AuthProvider>> {
+                match name {
+                    "db" => Ok(Box::new(DbProvider)),
+                    "ldap" => Ok(Box::new(ldap::LdapProvider::from_env()?)),
+                    "htpasswd" => Ok(Box::new(htpasswd::HtpasswdProvider::from_env()?)),
+                    other => Err(TicxError::GenericError {
+                        what: "configure auth providers",
+                        error: format!(
+                            "unknown provider '{}' in TICX_AUTH_PROVIDERS, expected 'db', 'ldap' or 'htpasswd'",
+                            other
+                        ),
+                    }),
+                }
+            })
+            .collect::<TicxResult<Vec<_>>>()?;
+
+        tracing::info!(providers = %names, "auth providers configured");
+        Ok(AuthProviders::new(providers))
+    }
+
+    /// User accepted by the first provider which knows the credentials. Provider which fails does not stop
+    /// the others, its error is returned only when none of them accepts the credentials, so outage of
+    /// a directory is not counted as failed login.
+    #[tracing::instrument(skip(self, db, password))]
+    pub fn authenticate(
+        &self,
+        db: &Db,
+        username: &str,
+        password: &str,
+    ) -> TicxResult<Option<User>> {
+        let mut failure = None;
+        for provider in &self.0 {
+            match provider.authenticate(db, username, password) {
+                Ok(Some(user)) => {
+                    tracing::debug!(
+                        provider = provider.name(),
+                        user_id = user.id,
+                        "credentials accepted"
+                    );
+                    return Ok(Some(user));
+                }
+                Ok(None) => tracing::trace!(provider = provider.name(), "credentials not accepted"),
+                Err(err) => {
+                    tracing::error!(provider = provider.name(), %err, "auth provider failed");
+                    failure = Some(err);
+                }
+            }
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+}
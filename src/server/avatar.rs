@@ -0,0 +1,60 @@
+//! Avatars of users, either uploaded image or identicon generated from the user id.
+
+use crate::errors::{TicxError, TicxResult};
+
+/// the same as default limit of request payload, larger uploads are refused before they reach handler
+pub(crate) const MAX_AVATAR_BYTES: usize = 256 * 1024;
+const IDENTICON_CELLS: usize = 5;
+const IDENTICON_SIZE: usize = 250;
+
+/// Content type of uploaded image, detected from its data and not taken from the request, so only images
+/// browsers cannot run scripts from are served back.
+pub(crate) fn detect_image_type(data: &[u8]) -> TicxResult<&'static str> {
+    if data.len() > MAX_AVATAR_BYTES {
+        return Err(TicxError::BadRequest(format!(
+            "avatar cannot be larger than {} KiB",
+            MAX_AVATAR_BYTES / 1024
+        )));
+    }
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Ok("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Ok("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Ok("image/gif")
+    } else if data.len() > 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Ok("image/webp")
+    } else {
+        Err(TicxError::BadRequest(
+            "avatar has to be PNG, JPEG, GIF or WebP image".into(),
+        ))
+    }
+}
+
+/// Symmetric 5x5 pattern of one color derived from SHA-256 of the user id, as SVG.
+pub(crate) fn identicon(user_id: i32) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, user_id.to_string().as_bytes());
+    let digest = digest.as_ref();
+
+    let hue = u16::from_be_bytes([digest[0], digest[1]]) % 360;
+    // left half and the middle column, the right half mirrors the left one
+    let columns = IDENTICON_CELLS.div_ceil(2);
+    let cells = (0..IDENTICON_CELLS)
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .filter(|(row, column)| digest[2 + row * columns + column] % 2 == 0)
+        .flat_map(|(row, column)| {
+            let mirrored = IDENTICON_CELLS - 1 - column;
+            std::iter::once((row, column)).chain((mirrored != column).then_some((row, mirrored)))
+        })
+        .map(|(row, column)| format!(r#"<rect x="{}" y="{}" width="1" height="1"/>"#, column, row))
+        .collect::<String>();
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="-0.5 -0.5 {view} {view}" shape-rendering="crispEdges"><rect x="-0.5" y="-0.5" width="{view}" height="{view}" fill="#f0f0f0"/><g fill="hsl({hue}, 55%, 50%)">{cells}</g></svg>"##,
+        size = IDENTICON_SIZE,
+        view = IDENTICON_CELLS + 1,
+        hue = hue,
+        cells = cells,
+    )
+}
@@ -0,0 +1,448 @@
+//! Keys tokens are signed and verified with, configured by environment:
+//!
+//! - `TICX_JWT_ALGORITHM` - algorithm of the signing key, `HS512` by default. HMAC (`HS*`), RSA (`RS*`, `PS*`),
+//!   ECDSA (`ES256`, `ES384`) and EdDSA with Ed25519 keys are supported.
+//! - `TICX_JWT_KID` - id of the signing key put into `kid` header of issued tokens, `default` by default.
+//! - `TICX_JWT_SECRET` - secret of HMAC algorithms. When not set, random secret is generated on start, so tokens
+//!   do not survive restart and are not accepted by other instances.
+//! - `TICX_JWT_PRIVATE_KEY` and `TICX_JWT_PUBLIC_KEY` - PEM encoded key pair of RSA, ECDSA and EdDSA algorithms.
+//! - `TICX_JWT_VERIFY_KEYS` - comma separated `kid:algorithm:path` of keys which are no longer used for signing
+//!   but tokens signed by them are still accepted, so keys can be rotated without logging everybody out.
+//!
+//! Secret and PEM keys can be read from file instead, by setting the variable with `_FILE` suffix to its path.
+//! Public keys of asymmetric algorithms are published as JWKS.
+
+use crate::errors::{TicxError, TicxResult};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use simple_asn1::ASN1Block;
+use std::str::FromStr;
+
+/// Length of the secret generated when none is configured, matches output of SHA-512 used by HS512.
+const GENERATED_SECRET_BYTES: usize = 64;
+const DEFAULT_KID: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyFamily {
+    Hmac,
+    Rsa,
+    Ec,
+    Ed,
+}
+
+fn family(algorithm: Algorithm) -> KeyFamily {
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => KeyFamily::Hmac,
+        Algorithm::ES256 | Algorithm::ES384 => KeyFamily::Ec,
+        Algorithm::EdDSA => KeyFamily::Ed,
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => KeyFamily::Rsa,
+    }
+}
+
+/// Public key in JWK format, see RFC 7517.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    // optional in keys published by others
+    #[serde(default)]
+    pub kid: String,
+    #[serde(default)]
+    pub alg: String,
+    #[serde(default, rename = "use")]
+    pub usage: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+impl Jwk {
+    /// Key verifying tokens signed by the owner of this public key with `algorithm`, e.g. ID tokens of OpenID
+    /// Connect providers.
+    pub fn decoding_key(&self, algorithm: Algorithm) -> TicxResult<DecodingKey> {
+        let invalid = |what: &str| TicxError::InvalidToken(format!("key '{}' {}", self.kid, what));
+        if !self.alg.is_empty() && self.alg != format!("{:?}", algorithm) {
+            return Err(invalid(&format!("is not {:?} key", algorithm)));
+        }
+
+        match (family(algorithm), self.kty.as_str()) {
+            (KeyFamily::Rsa, "RSA") => match (&self.n, &self.e) {
+                (Some(n), Some(e)) => DecodingKey::from_rsa_components(n, e)
+                    .map_err(|err| invalid(&format!("has invalid modulus or exponent: {}", err))),
+                _ => Err(invalid("has no modulus or exponent")),
+            },
+            (KeyFamily::Ec, "EC") => {
+                let crv = match algorithm {
+                    Algorithm::ES384 => "P-384",
+                    _ => "P-256",
+                };
+                if self.crv.as_deref() != Some(crv) {
+                    return Err(invalid(&format!("is not {} key", crv)));
+                }
+                let coordinate = |c: &Option<String>| {
+                    c.as_ref()
+                        .and_then(|c| base64::decode_config(c, base64::URL_SAFE_NO_PAD).ok())
+                        .ok_or_else(|| invalid("has invalid coordinates"))
+                };
+                // ring takes uncompressed point (0x04 | x | y) as the public key
+                let mut point = vec![0x04];
+                point.extend(coordinate(&self.x)?);
+                point.extend(coordinate(&self.y)?);
+                Ok(DecodingKey::from_ec_der(&point))
+            }
+            (KeyFamily::Ed, "OKP") => match (self.crv.as_deref(), &self.x) {
+                (Some("Ed25519"), Some(x)) => DecodingKey::from_ed_components(x)
+                    .map_err(|err| invalid(&format!("has invalid public key: {}", err))),
+                _ => Err(invalid("is not Ed25519 key")),
+            },
+            _ => Err(invalid(&format!(
+                "cannot verify {:?} signatures",
+                algorithm
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+struct VerificationKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: DecodingKey,
+    /// `None` for HMAC secrets which must not be published
+    jwk: Option<Jwk>,
+}
+
+pub(crate) struct KeySet {
+    signing: SigningKey,
+    verification: Vec<VerificationKey>,
+}
+
+impl KeySet {
+    pub fn from_env() -> TicxResult<Self> {
+        let algorithm = match dotenv::var("TICX_JWT_ALGORITHM") {
+            Ok(algorithm) => parse_algorithm(&algorithm)?,
+            Err(_) => Algorithm::HS512,
+        };
+        let kid = dotenv::var("TICX_JWT_KID").unwrap_or_else(|_| DEFAULT_KID.into());
+
+        let mut keys = match family(algorithm) {
+            KeyFamily::Hmac => {
+                let secret = env_key("TICX_JWT_SECRET")?.unwrap_or_else(|| {
+                    tracing::warn!(
+                        "TICX_JWT_SECRET is not set, tokens are signed with random secret valid until restart"
+                    );
+                    (0..GENERATED_SECRET_BYTES)
+                        .map(|_| rand::random::<u8>())
+                        .collect()
+                });
+                KeySet::hmac(&kid, algorithm, &secret)?
+            }
+            KeyFamily::Rsa | KeyFamily::Ec | KeyFamily::Ed => {
+                let private = env_key("TICX_JWT_PRIVATE_KEY")?.ok_or_else(|| {
+                    key_error("TICX_JWT_PRIVATE_KEY is required by asymmetric algorithms")
+                })?;
+                let public = env_key("TICX_JWT_PUBLIC_KEY")?.ok_or_else(|| {
+                    key_error("TICX_JWT_PUBLIC_KEY is required by asymmetric algorithms")
+                })?;
+                KeySet::asymmetric(&kid, algorithm, &private, &public)?
+            }
+        };
+
+        if let Ok(verify_keys) = dotenv::var("TICX_JWT_VERIFY_KEYS") {
+            for entry in verify_keys
+                .split(',')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+            {
+                let mut parts = entry.splitn(3, ':');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(kid), Some(algorithm), Some(path)) => {
+                        let key = std::fs::read(path).map_err(|err| {
+                            key_error(format!("failed to read key file '{}': {}", path, err))
+                        })?;
+                        keys =
+                            keys.with_verification_key(kid, parse_algorithm(algorithm)?, &key)?;
+                    }
+                    _ => {
+                        return Err(key_error(format!(
+                            "expected 'kid:algorithm:path' in TICX_JWT_VERIFY_KEYS, got '{}'",
+                            entry
+                        )))
+                    }
+                }
+            }
+        }
+
+        tracing::info!(
+            kid = %keys.signing.kid,
+            algorithm = ?keys.signing.algorithm,
+            verification_keys = keys.verification.len(),
+            "JWT keys loaded"
+        );
+        Ok(keys)
+    }
+
+    pub fn hmac(kid: &str, algorithm: Algorithm, secret: &[u8]) -> TicxResult<Self> {
+        if family(algorithm) != KeyFamily::Hmac {
+            return Err(key_error(format!("{:?} is not HMAC algorithm", algorithm)));
+        }
+
+        Ok(KeySet {
+            signing: SigningKey {
+                kid: kid.to_string(),
+                algorithm,
+                key: EncodingKey::from_secret(secret),
+            },
+            verification: vec![verification_key(kid, algorithm, secret)?],
+        })
+    }
+
+    /// Key set signing with PEM encoded `private` key, whose public counterpart is `public`.
+    pub fn asymmetric(
+        kid: &str,
+        algorithm: Algorithm,
+        private: &[u8],
+        public: &[u8],
+    ) -> TicxResult<Self> {
+        let key = match family(algorithm) {
+            KeyFamily::Rsa => EncodingKey::from_rsa_pem(private),
+            KeyFamily::Ec => EncodingKey::from_ec_pem(private),
+            KeyFamily::Ed => EncodingKey::from_ed_pem(private),
+            KeyFamily::Hmac => {
+                return Err(key_error(format!(
+                    "{:?} is not asymmetric algorithm",
+                    algorithm
+                )))
+            }
+        }
+        .map_err(|err| key_error(format!("invalid private key: {}", err)))?;
+
+        Ok(KeySet {
+            signing: SigningKey {
+                kid: kid.to_string(),
+                algorithm,
+                key,
+            },
+            verification: vec![verification_key(kid, algorithm, public)?],
+        })
+    }
+
+    /// Accepts also tokens signed by `key` (secret or PEM encoded public key) with given `kid`.
+    pub fn with_verification_key(
+        mut self,
+        kid: &str,
+        algorithm: Algorithm,
+        key: &[u8],
+    ) -> TicxResult<Self> {
+        if self.verification.iter().any(|k| k.kid == kid) {
+            return Err(key_error(format!("duplicate key id '{}'", kid)));
+        }
+
+        self.verification
+            .push(verification_key(kid, algorithm, key)?);
+        Ok(self)
+    }
+
+    /// Signs `claims` with the signing key, its id is put into `kid` header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> TicxResult<String> {
+        let header = Header {
+            kid: Some(self.signing.kid.clone()),
+            ..Header::new(self.signing.algorithm)
+        };
+
+        jsonwebtoken::encode(&header, claims, &self.signing.key).map_err(|err| {
+            tracing::trace!(%err, "failed to encode JWT token");
+            TicxError::GenericError {
+                what: "encode JWT token",
+                error: err.to_string(),
+            }
+        })
+    }
+
+    /// Verifies token with key matching its `kid` header. Algorithms of `validation` are replaced by the
+    /// algorithm of the key, so token cannot pick weaker one.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> TicxResult<T> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|err| TicxError::InvalidToken(err.to_string()))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| TicxError::InvalidToken("token has no key id".into()))?;
+        let key = self
+            .verification
+            .iter()
+            .find(|k| k.kid == kid)
+            .ok_or_else(|| TicxError::InvalidToken(format!("unknown key id '{}'", kid)))?;
+
+        validation.algorithms = vec![key.algorithm];
+        jsonwebtoken::decode::<T>(token, &key.key, &validation)
+            .map(|data| data.claims)
+            .map_err(|err| TicxError::InvalidToken(err.to_string()))
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: self
+                .verification
+                .iter()
+                .filter_map(|k| k.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn parse_algorithm(algorithm: &str) -> TicxResult<Algorithm> {
+    Algorithm::from_str(algorithm)
+        .map_err(|err| key_error(format!("invalid algorithm '{}': {}", algorithm, err)))
+}
+
+/// Value of variable `name`, or content of the file `<name>_FILE` points to.
+fn env_key(name: &str) -> TicxResult<Option<Vec<u8>>> {
+    if let Ok(value) = dotenv::var(name) {
+        return Ok(Some(value.into_bytes()));
+    }
+
+    match dotenv::var(format!("{}_FILE", name)) {
+        Ok(path) => std::fs::read(&path)
+            .map(Some)
+            .map_err(|err| key_error(format!("failed to read {} from '{}': {}", name, path, err))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn key_error<E: std::fmt::Display>(err: E) -> TicxError {
+    TicxError::GenericError {
+        what: "load JWT keys",
+        error: err.to_string(),
+    }
+}
+
+fn verification_key(kid: &str, algorithm: Algorithm, key: &[u8]) -> TicxResult<VerificationKey> {
+    let decoding_key = match family(algorithm) {
+        KeyFamily::Hmac => Ok(DecodingKey::from_secret(key)),
+        KeyFamily::Rsa => DecodingKey::from_rsa_pem(key),
+        KeyFamily::Ec => DecodingKey::from_ec_pem(key),
+        KeyFamily::Ed => DecodingKey::from_ed_pem(key),
+    }
+    .map_err(|err| key_error(format!("invalid key '{}': {}", kid, err)))?;
+
+    let jwk = match family(algorithm) {
+        KeyFamily::Hmac => None,
+        KeyFamily::Rsa | KeyFamily::Ec | KeyFamily::Ed => Some(public_jwk(kid, algorithm, key)?),
+    };
+
+    Ok(VerificationKey {
+        kid: kid.to_string(),
+        algorithm,
+        key: decoding_key,
+        jwk,
+    })
+}
+
+fn public_jwk(kid: &str, algorithm: Algorithm, public_pem: &[u8]) -> TicxResult<Jwk> {
+    let invalid = |what: &str| key_error(format!("invalid public key '{}': {}", kid, what));
+    let pem = pem::parse(public_pem).map_err(|err| invalid(&err.to_string()))?;
+    let der = |bytes: &[u8]| simple_asn1::from_der(bytes).map_err(|err| invalid(&err.to_string()));
+
+    let mut jwk = Jwk {
+        kty: String::new(),
+        kid: kid.to_string(),
+        alg: format!("{:?}", algorithm),
+        usage: "sig".into(),
+        n: None,
+        e: None,
+        crv: None,
+        x: None,
+        y: None,
+    };
+
+    match family(algorithm) {
+        KeyFamily::Rsa => {
+            // PKCS#1 `RSA PUBLIC KEY` is the bare key, `PUBLIC KEY` wraps it in SubjectPublicKeyInfo
+            let rsa_key = match pem.tag.as_str() {
+                "RSA PUBLIC KEY" => pem.contents.clone(),
+                _ => subject_public_key(&der(&pem.contents)?)
+                    .ok_or_else(|| invalid("not a public key"))?,
+            };
+            match der(&rsa_key)?.as_slice() {
+                [ASN1Block::Sequence(_, items)] => match items.as_slice() {
+                    [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                        jwk.kty = "RSA".into();
+                        jwk.n = Some(base64url(&n.to_bytes_be().1));
+                        jwk.e = Some(base64url(&e.to_bytes_be().1));
+                    }
+                    _ => return Err(invalid("not a RSA public key")),
+                },
+                _ => return Err(invalid("not a RSA public key")),
+            }
+        }
+        KeyFamily::Ec => {
+            let (crv, size) = match algorithm {
+                Algorithm::ES384 => ("P-384", 48),
+                _ => ("P-256", 32),
+            };
+            let point = subject_public_key(&der(&pem.contents)?)
+                .ok_or_else(|| invalid("not a public key"))?;
+            // only uncompressed points (0x04 | x | y) are used in practice
+            if point.len() != 1 + 2 * size || point[0] != 0x04 {
+                return Err(invalid(&format!("not an uncompressed {} point", crv)));
+            }
+            jwk.kty = "EC".into();
+            jwk.crv = Some(crv.into());
+            jwk.x = Some(base64url(&point[1..=size]));
+            jwk.y = Some(base64url(&point[size + 1..]));
+        }
+        KeyFamily::Ed => {
+            let key = subject_public_key(&der(&pem.contents)?)
+                .ok_or_else(|| invalid("not a public key"))?;
+            if key.len() != 32 {
+                return Err(invalid("not an Ed25519 public key"));
+            }
+            jwk.kty = "OKP".into();
+            jwk.crv = Some("Ed25519".into());
+            jwk.x = Some(base64url(&key));
+        }
+        KeyFamily::Hmac => return Err(invalid("HMAC secret cannot be published")),
+    }
+
+    Ok(jwk)
+}
+
+/// Key bits of DER encoded SubjectPublicKeyInfo.
+fn subject_public_key(blocks: &[ASN1Block]) -> Option<Vec<u8>> {
+    match blocks {
+        [ASN1Block::Sequence(_, items)] => match items.as_slice() {
+            [ASN1Block::Sequence(..), ASN1Block::BitString(_, _, key)] => Some(key.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
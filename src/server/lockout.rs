@@ -0,0 +1,145 @@
+//! Brute-force protection of basic auth login. Failed logins are counted per username and per client address,
+//! once there are too many of them in a row the login is locked for exponentially growing time.
+//! Configured by environment:
+//!
+//! - `TICX_LOGIN_MAX_FAILURES` - failures of one username before it gets locked, 5 by default.
+//! - `TICX_LOGIN_MAX_FAILURES_PER_IP` - failures from one address before it gets locked, 20 by default. `0` turns
+//!   counting per address off, which is needed behind a reverse proxy, all clients share its address there.
+//! - `TICX_LOGIN_LOCK_SECONDS` - first lock, doubled with every further failure, 30 by default.
+//! - `TICX_LOGIN_MAX_LOCK_SECONDS` - longest lock, 1 hour by default.
+//!
+//! Failures are forgotten after successful login or once there was none for a day.
+
+use crate::errors::{TicxError, TicxResult};
+use crate::metrics::*;
+use db::dbo::login_subject;
+use db::Db;
+
+const FAILURE_WINDOW: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LoginLockout {
+    pub max_failures: i32,
+    /// `0` when failures are not counted per address
+    pub max_failures_per_ip: i32,
+    pub lock_seconds: i64,
+    pub max_lock_seconds: i64,
+}
+
+impl Default for LoginLockout {
+    fn default() -> Self {
+        LoginLockout {
+            max_failures: 5,
+            max_failures_per_ip: 20,
+            lock_seconds: 30,
+            max_lock_seconds: 60 * 60,
+        }
+    }
+}
+
+impl LoginLockout {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            dotenv::var(name)
+                .ok()
+                .and_then(|v| v.parse::<T>().ok())
+                .unwrap_or(default)
+        }
+
+        let defaults = LoginLockout::default();
+        LoginLockout {
+            max_failures: var("TICX_LOGIN_MAX_FAILURES", defaults.max_failures),
+            max_failures_per_ip: var(
+                "TICX_LOGIN_MAX_FAILURES_PER_IP",
+                defaults.max_failures_per_ip,
+            ),
+            lock_seconds: var("TICX_LOGIN_LOCK_SECONDS", defaults.lock_seconds),
+            max_lock_seconds: var("TICX_LOGIN_MAX_LOCK_SECONDS", defaults.max_lock_seconds),
+        }
+    }
+
+    /// How long to lock after `failures` in a row, `None` while there are not too many of them.
+    fn lock_duration(&self, failures: i32, max_failures: i32) -> Option<i64> {
+        if failures < max_failures {
+            return None;
+        }
+        let doublings = (failures - max_failures).min(32) as u32;
+        Some(
+            self.lock_seconds
+                .saturating_mul(2i64.saturating_pow(doublings))
+                .min(self.max_lock_seconds),
+        )
+    }
+
+    fn subjects<'a>(
+        &self,
+        username: &'a str,
+        ip: Option<&'a str>,
+    ) -> Vec<(&'static str, &'a str, i32)> {
+        let mut subjects = vec![(login_subject::USER, username, self.max_failures)];
+        match ip {
+            Some(ip) if self.max_failures_per_ip > 0 => {
+                subjects.push((login_subject::IP, ip, self.max_failures_per_ip))
+            }
+            _ => (),
+        }
+        subjects
+    }
+
+    /// Refuses the login with `TooManyRequests` while the username or the address is locked.
+    #[tracing::instrument(skip(self, db))]
+    pub fn check(&self, db: &Db, username: &str, ip: Option<&str>) -> TicxResult<()> {
+        let timer = DB_QUERY_HISTOGRAM
+            .with_label_values(&[DB_TABLE_LOGIN_FAILURES, "SELECT"])
+            .start_timer();
+
+        let now = chrono::Utc::now().naive_utc();
+        for (kind, key, _) in self.subjects(username, ip) {
+            if let Some(until) = db.login_locked_until(kind, key)? {
+                timer.observe_duration();
+                tracing::warn!(%kind, %until, "login refused, too many failed attempts");
+                LOGIN_LOCKED_REJECTION_COUNTER
+                    .with_label_values(&[kind])
+                    .inc();
+                return Err(TicxError::TooManyRequests {
+                    // rounded up, client retrying right after would be refused again
+                    retry_after: (until - now).num_seconds() + 1,
+                });
+            }
+        }
+
+        timer.observe_duration();
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, db))]
+    pub fn record_failure(&self, db: &Db, username: &str, ip: Option<&str>) -> TicxResult<()> {
+        let timer = DB_QUERY_HISTOGRAM
+            .with_label_values(&[DB_TABLE_LOGIN_FAILURES, "UPSERT"])
+            .start_timer();
+
+        for (kind, key, max_failures) in self.subjects(username, ip) {
+            let failures = db.record_login_failure(kind, key, FAILURE_WINDOW)?;
+            if let Some(seconds) = self.lock_duration(failures, max_failures) {
+                tracing::warn!(%kind, %failures, %seconds, "locking login after too many failed attempts");
+                db.lock_login(
+                    kind,
+                    key,
+                    chrono::Utc::now().naive_utc() + chrono::Duration::seconds(seconds),
+                )?;
+                LOGIN_LOCKOUT_COUNTER.with_label_values(&[kind]).inc();
+            }
+        }
+
+        timer.observe_duration();
+        Ok(())
+    }
+
+    /// Failures of the address are kept, otherwise one valid account would let anybody guess passwords
+    /// of others from the same address.
+    #[tracing::instrument(skip(self, db))]
+    pub fn record_success(&self, db: &Db, username: &str) -> TicxResult<()> {
+        db.clear_login_failures(login_subject::USER, username)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Outgoing mail, configured by environment:
+//!
+//! - `TICX_MAIL_TRANSPORT` - `log` (default) only logs mails, so the server can be run locally without any setup,
+//!   `sendmail` pipes them to local MTA.
+//! - `TICX_MAIL_FROM` - sender address, `ticx@localhost` by default.
+//! - `TICX_SENDMAIL_COMMAND` - sendmail compatible binary, `/usr/sbin/sendmail` by default.
+//! - `TICX_PUBLIC_URL` - base of links put into mails, `http://localhost:8080` by default.
+
+use crate::errors::{TicxError, TicxResult};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug)]
+pub(crate) struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub(crate) trait MailTransport: Send + Sync {
+    fn send(&self, from: &str, mail: &Mail) -> TicxResult<()>;
+}
+
+/// Mails are not delivered, only logged. Meant for development, mails may contain secrets like reset links.
+pub(crate) struct LogTransport;
+
+impl MailTransport for LogTransport {
+    fn send(&self, from: &str, mail: &Mail) -> TicxResult<()> {
+        tracing::info!(%from, to = %mail.to, subject = %mail.subject, body = %mail.body, "mail not delivered, log transport is used");
+        Ok(())
+    }
+}
+
+/// Delivers mails through `sendmail -t`, recipient is taken from the `To` header.
+pub(crate) struct SendmailTransport {
+    command: String,
+}
+
+impl MailTransport for SendmailTransport {
+    #[tracing::instrument(skip(self, mail), fields(to = %mail.to))]
+    fn send(&self, from: &str, mail: &Mail) -> TicxResult<()> {
+        let mut child = Command::new(&self.command)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(mail_error)?;
+
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            from, mail.to, mail.subject, mail.body
+        );
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| mail_error("stdin of sendmail is not available"))?
+            .write_all(message.as_bytes())
+            .map_err(mail_error)?;
+
+        let status = child.wait().map_err(mail_error)?;
+        if !status.success() {
+            return Err(mail_error(format!("sendmail exited with {}", status)));
+        }
+        tracing::debug!("mail handed over to sendmail");
+        Ok(())
+    }
+}
+
+pub(crate) struct Mailer {
+    transport: Box<dyn MailTransport>,
+    from: String,
+    public_url: String,
+}
+
+impl Mailer {
+    pub fn new(transport: Box<dyn MailTransport>, from: &str, public_url: &str) -> Self {
+        Mailer {
+            transport,
+            from: from.into(),
+            public_url: public_url.trim_end_matches('/').into(),
+        }
+    }
+
+    pub fn from_env() -> TicxResult<Self> {
+        let from = dotenv::var("TICX_MAIL_FROM").unwrap_or_else(|_| "ticx@localhost".into());
+        let public_url =
+            dotenv::var("TICX_PUBLIC_URL").unwrap_or_else(|_| "http://localhost:8080".into());
+
+        let transport: Box<dyn MailTransport> = match dotenv::var("TICX_MAIL_TRANSPORT")
+            .unwrap_or_else(|_| "log".into())
+            .as_str()
+        {
+            "log" => {
+                tracing::warn!("TICX_MAIL_TRANSPORT is 'log', mails are only logged");
+                Box::new(LogTransport)
+            }
+            "sendmail" => Box::new(SendmailTransport {
+                command: dotenv::var("TICX_SENDMAIL_COMMAND")
+                    .unwrap_or_else(|_| "/usr/sbin/sendmail".into()),
+            }),
+            other => {
+                return Err(mail_error(format!(
+                    "unknown TICX_MAIL_TRANSPORT '{}', expected 'log' or 'sendmail'",
+                    other
+                )))
+            }
+        };
+
+        Ok(Mailer::new(transport, &from, &public_url))
+    }
+
+    /// Absolute URL of `path` for links in mails.
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url, path)
+    }
+
+    pub fn send(&self, mail: Mail) -> TicxResult<()> {
+        // header injection, recipient and subject end up in headers of the message
+        if mail.to.contains(['\r', '\n']) || mail.subject.contains(['\r', '\n']) {
+            return Err(TicxError::BadRequest(
+                "mail recipient and subject cannot contain line breaks".into(),
+            ));
+        }
+        self.transport.send(&self.from, &mail)
+    }
+}
+
+fn mail_error<T: ToString>(err: T) -> TicxError {
+    TicxError::GenericError {
+        what: "send mail",
+        error: err.to_string(),
+    }
+}
@@ -0,0 +1,191 @@
+//! Two-factor authentication with TOTP (RFC 6238) authenticator apps and single-use recovery codes.
+//! Configured by environment:
+//!
+//! - `TICX_MFA_REQUIRED_FOR_ADMINS` - `true` when admins cannot log in without the second factor, admin who
+//!   has not enrolled yet has to do so during login. `false` by default.
+
+use super::routes::auth::random_token;
+use crate::errors::{TicxError, TicxResult};
+use db::Db;
+use ring::hmac;
+use serde::Serialize;
+
+const ISSUER: &str = "TicX";
+const SECRET_BYTES: usize = 20;
+const DIGITS: usize = 6;
+const STEP_SECONDS: i64 = 30;
+/// steps before and after the current one which are accepted too, clocks of phones drift
+const ALLOWED_SKEW: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MfaPolicy {
+    pub required_for_admins: bool,
+}
+
+impl MfaPolicy {
+    pub fn from_env() -> Self {
+        MfaPolicy {
+            required_for_admins: dotenv::var("TICX_MFA_REQUIRED_FOR_ADMINS")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Time based one-time password generator sharing secret with authenticator app of the user.
+pub(crate) struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    pub fn generate() -> Self {
+        Totp {
+            secret: (0..SECRET_BYTES).map(|_| rand::random::<u8>()).collect(),
+        }
+    }
+
+    pub fn from_secret(secret: &str) -> TicxResult<Self> {
+        base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)
+            .map(|secret| Totp { secret })
+            .ok_or_else(|| TicxError::GenericError {
+                what: "decode TOTP secret",
+                error: "secret is not base32".into(),
+            })
+    }
+
+    /// Base32 encoded secret, as authenticator apps expect it.
+    pub fn secret(&self) -> String {
+        base32::encode(base32::Alphabet::RFC4648 { padding: false }, &self.secret)
+    }
+
+    /// Code valid at unix `time`, as authenticator app shows it.
+    #[cfg(test)]
+    pub fn code_at(&self, time: i64) -> String {
+        self.code(time.div_euclid(STEP_SECONDS))
+    }
+
+    fn code(&self, step: i64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.secret);
+        let digest = hmac::sign(&key, &step.to_be_bytes());
+        let digest = digest.as_ref();
+        // dynamic truncation, see RFC 4226 section 5.3
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS as u32),
+            width = DIGITS
+        )
+    }
+
+    /// Time step `code` belongs to when it is valid at unix `time`.
+    pub fn verify(&self, code: &str, time: i64) -> Option<i64> {
+        let current = time.div_euclid(STEP_SECONDS);
+        (current - ALLOWED_SKEW..=current + ALLOWED_SKEW).find(|step| {
+            ring::constant_time::verify_slices_are_equal(
+                self.code(*step).as_bytes(),
+                code.as_bytes(),
+            )
+            .is_ok()
+        })
+    }
+
+    /// `otpauth://` URI authenticator apps are configured with, usually scanned as QR code.
+    pub fn uri(&self, account: &str) -> String {
+        let label = format!("{}:{}", ISSUER, account);
+        format!(
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            url::form_urlencoded::byte_serialize(label.as_bytes()).collect::<String>(),
+            self.secret(),
+            ISSUER,
+            DIGITS,
+            STEP_SECONDS
+        )
+    }
+}
+
+/// What the user needs to set up authenticator app, shown only until the enrollment is confirmed.
+#[derive(Debug, Serialize, serde::Deserialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// the URI as QR code
+    pub qr_code_svg: String,
+}
+
+/// Starts enrollment of the user with a new secret, replacing enrollment which was not confirmed yet.
+pub(crate) fn enroll(db: &Db, user: &db::dbo::User) -> TicxResult<Enrollment> {
+    let totp = Totp::generate();
+    db.start_totp_enrollment(user.id, &totp.secret())?;
+
+    let otpauth_uri = totp.uri(&user.username);
+    let qr_code_svg = qrcode::QrCode::new(otpauth_uri.as_bytes())
+        .map_err(|err| TicxError::GenericError {
+            what: "render QR code",
+            error: err.to_string(),
+        })?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(Enrollment {
+        secret: totp.secret(),
+        otpauth_uri,
+        qr_code_svg,
+    })
+}
+
+#[derive(Debug)]
+pub(crate) enum Verified {
+    Code,
+    RecoveryCode,
+    /// first code confirmed the enrollment, recovery codes are shown to the user just this once
+    Enrolled(Vec<String>),
+}
+
+/// Checks TOTP code or recovery code of the user. Code which is wrong, expired or was already used is
+/// reported as invalid token.
+pub(crate) fn verify_code(db: &Db, user_id: i32, code: &str) -> TicxResult<Verified> {
+    let invalid = || TicxError::InvalidToken("invalid two-factor authentication code".into());
+    let enrollment = db
+        .select_totp(user_id)?
+        .ok_or_else(|| TicxError::BadRequest("two-factor authentication is not enabled".into()))?;
+    let code = code.trim().to_lowercase();
+
+    let totp = Totp::from_secret(&enrollment.secret)?;
+    match (
+        totp.verify(&code, chrono::Utc::now().timestamp()),
+        enrollment.confirmed,
+    ) {
+        (Some(step), None) => {
+            let codes = (0..RECOVERY_CODES)
+                .map(|_| {
+                    let code = random_token(5);
+                    format!("{}-{}", &code[..5], &code[5..])
+                })
+                .collect::<Vec<_>>();
+            match db.confirm_totp(user_id, step, &codes)? {
+                true => Ok(Verified::Enrolled(codes)),
+                false => Err(invalid()),
+            }
+        }
+        (Some(step), Some(_)) => match db.accept_totp_step(user_id, step)? {
+            true => Ok(Verified::Code),
+            false => Err(invalid()),
+        },
+        (None, Some(_)) => match db.use_recovery_code(user_id, &code)? {
+            true => {
+                tracing::warn!(user_id, "recovery code used");
+                Ok(Verified::RecoveryCode)
+            }
+            false => Err(invalid()),
+        },
+        (None, None) => Err(invalid()),
+    }
+}
@@ -1,16 +1,17 @@
+use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::{
     pin::Pin,
+    rc::Rc,
     sync::Arc,
     task::{Context, Poll},
 };
 
 use crate::errors::{TicxError, TicxResult};
-use actix_web::http::StatusCode;
 use actix_web::{
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    dev::{MessageBody, Service, ServiceRequest, ServiceResponse, Transform},
     http::HeaderValue,
-    Error,
+    web, Error, HttpMessage,
 };
 use db::Db;
 use futures::{
@@ -18,12 +19,6 @@ use futures::{
     Future,
 };
 
-macro_rules! pin_svc_call {
-    ($me:ident, $req:ident) => {
-        Box::pin($me.service.call($req))
-    };
-}
-
 macro_rules! tracing_span {
     ($level:ident, $name:expr, $guard:ident) => {
         let span = tracing::span!(tracing::Level::$level, stringify!($name));
@@ -37,11 +32,13 @@ fn box_error<B>(e: TicxError) -> Pin<Box<dyn Future<Output = Result<ServiceRespo
 
 pub(super) struct BasicAuthMiddleware {
     pub(super) db: Arc<Db>,
+    pub(super) providers: Arc<super::auth_providers::AuthProviders>,
+    pub(super) lockout: super::lockout::LoginLockout,
 }
 
 impl<S, B> Transform<S> for BasicAuthMiddleware
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Request = S::Request;
@@ -53,29 +50,34 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(BasicAuthService {
-            service,
+            service: Rc::new(RefCell::new(service)),
             db: self.db.clone(),
+            providers: self.providers.clone(),
+            lockout: self.lockout,
         })
     }
 }
 
 pub(super) struct BasicAuthService<S> {
-    service: S,
+    // shared with the future of authentication, which calls it once the credentials are checked
+    service: Rc<RefCell<S>>,
     db: Arc<Db>,
+    providers: Arc<super::auth_providers::AuthProviders>,
+    lockout: super::lockout::LoginLockout,
 }
 
 impl<S, B> Service for BasicAuthService<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
     type Error = Error;
-    type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>; // maybe we could turn thins into Ready<..> ?
+    type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        self.service.borrow_mut().poll_ready(cx)
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
@@ -87,7 +89,7 @@ where
             .headers()
             .get(actix_web::http::header::AUTHORIZATION)
             .ok_or(TicxError::MissingAuthHeader)
-            .and_then(|header_value| super::routes::auth::Credentials::try_from(header_value))
+            .and_then(super::routes::auth::Credentials::try_from)
         {
             Ok(credentials) => {
                 tracing::trace!(?credentials, "basic auth credentials parsed");
@@ -99,37 +101,81 @@ where
             }
         };
 
-        tracing::trace!(
-            username = credentials.username(),
-            "checking username & password"
-        );
-
-        if let Err(_) = self
-            .db
-            .check_credentials(credentials.username(), credentials.password())
-            .map_err(|err| {
-                tracing::error!(%err, "no user found for provided credentials");
-                TicxError::InvalidCredentials
-            })
-            .and_then(|user| {
-                tracing::trace!(?user, "credentials match for user");
-                Ok(user)
-            })
-        {
-            return box_error(TicxError::InvalidCredentials);
-        }
+        // peer address and not `X-Forwarded-For`, which anybody could set to get around the lockout
+        let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+        drop(guard);
+
+        let service = self.service.clone();
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        let lockout = self.lockout;
+        // providers hash passwords or ask LDAP server, neither of which may block the worker
+        Box::pin(async move {
+            let user = web::block(move || authenticate(&db, &providers, lockout, &credentials, ip))
+                .await
+                .map_err(TicxError::from)?;
+            req.extensions_mut()
+                .insert(super::routes::auth::Authenticated { user_id: user.id });
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
 
-        pin_svc_call!(self, req)
+/// Checks basic auth `credentials` with the providers, failures count towards the lockout of the username.
+#[tracing::instrument(skip(db, providers, lockout))]
+fn authenticate(
+    db: &Db,
+    providers: &super::auth_providers::AuthProviders,
+    lockout: super::lockout::LoginLockout,
+    credentials: &super::routes::auth::Credentials,
+    ip: Option<String>,
+) -> TicxResult<db::dbo::User> {
+    lockout.check(db, credentials.username(), ip.as_deref())?;
+
+    tracing::trace!(
+        username = credentials.username(),
+        "checking username & password"
+    );
+
+    match providers.authenticate(db, credentials.username(), credentials.password())? {
+        Some(user) if !user.active => {
+            tracing::warn!(user_id = user.id, "login refused, user is deactivated");
+            Err(TicxError::Forbidden("user is deactivated".into()))
+        }
+        Some(user) if user.email_unverified() => {
+            tracing::warn!(user_id = user.id, "login refused, email is not verified");
+            Err(TicxError::Forbidden(
+                "email has to be verified before logging in".into(),
+            ))
+        }
+        Some(user) => {
+            tracing::trace!(?user, "credentials match for user");
+            if let Err(err) = lockout.record_success(db, credentials.username()) {
+                tracing::error!(%err, "failed to clear failed logins");
+            }
+            Ok(user)
+        }
+        None => {
+            tracing::error!("no user found for provided credentials");
+            if let Err(err) = lockout.record_failure(db, credentials.username(), ip.as_deref()) {
+                tracing::error!(%err, "failed to record failed login");
+            }
+            Err(TicxError::InvalidCredentials)
+        }
     }
 }
 
+/// Validates JWT access tokens and personal access tokens, claims of either are stored in request extensions.
 pub(super) struct JWTValidationMiddleware {
-    pub secret: Arc<super::routes::auth::Secret>,
+    pub db: Arc<Db>,
+    pub keys: Arc<super::keys::KeySet>,
+    pub revocations: Arc<super::revocation::RevocationList>,
 }
 
 impl<S, B> Transform<S> for JWTValidationMiddleware
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Request = ServiceRequest;
@@ -141,20 +187,25 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(JWTValidationService {
-            service,
-            secret: self.secret.clone(),
+            service: Rc::new(RefCell::new(service)),
+            db: self.db.clone(),
+            keys: self.keys.clone(),
+            revocations: self.revocations.clone(),
         })
     }
 }
 
 pub(super) struct JWTValidationService<S> {
-    secret: Arc<super::routes::auth::Secret>,
-    service: S,
+    db: Arc<Db>,
+    keys: Arc<super::keys::KeySet>,
+    revocations: Arc<super::revocation::RevocationList>,
+    // shared with the future of personal access token lookup, which calls it once the DB answers
+    service: Rc<RefCell<S>>,
 }
 
-impl<'a, S, B> Service for JWTValidationService<S>
+impl<S, B> Service for JWTValidationService<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Request = ServiceRequest;
@@ -163,36 +214,19 @@ where
     type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
 
     fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(ctx)
+        self.service.borrow_mut().poll_ready(ctx)
     }
 
     fn call(&mut self, req: Self::Request) -> Self::Future {
         tracing_span!(TRACE, JWTValidationService, guard);
 
-        #[allow(unused)]
-        #[derive(serde::Deserialize, Debug)]
-        struct Claims {
-            iss: String,
-            aud: String,
-            sub: String,
-            iat: i64,
-            exp: i64,
-            nbf: i64,
-            jti: String,
-        }
-
         let raw_token: String = match req
             .headers()
             .get(actix_web::http::header::AUTHORIZATION)
-            .and_then(|header_value| {
-                tracing::debug!("AUTHORIZATION header found");
-                Some(header_value)
-            })
+            .inspect(|_| tracing::debug!("AUTHORIZATION header found"))
             .ok_or(TicxError::MissingAuthHeader)
-            .map_err(|err| {
-                tracing::error!("AUTHORIZATION header not found");
-                err
-            }) {
+            .inspect_err(|_| tracing::error!("AUTHORIZATION header not found"))
+        {
             Ok(v) => match parse_token(v) {
                 Ok(t) => t,
                 Err(e) => return box_error(e),
@@ -200,32 +234,260 @@ where
             Err(e) => return box_error(e),
         };
 
-        if let Err(err) = jsonwebtoken::decode::<Claims>(
+        if raw_token.starts_with(super::routes::auth::PERSONAL_ACCESS_TOKEN_PREFIX) {
+            drop(guard);
+            let db = self.db.clone();
+            let service = self.service.clone();
+            // unlike JWT, every request with personal access token needs the DB, so it is queried on the
+            // thread pool instead of blocking the worker
+            return Box::pin(async move {
+                let claims = web::block(move || match db.use_personal_access_token(&raw_token) {
+                    Ok((token, owner)) => super::routes::auth::Claims::personal(&token, &owner),
+                    Err(db::errors::DbError::NotFound(_)) => Err(TicxError::InvalidToken(
+                        "unknown or expired personal access token".into(),
+                    )),
+                    Err(e) => Err(e.into()),
+                })
+                .await
+                .map_err(TicxError::from);
+                match claims {
+                    Ok(claims) => {
+                        tracing::trace!(sub = %claims.sub, jti = %claims.jti, "personal access token OK");
+                        req.extensions_mut().insert(claims);
+                    }
+                    Err(e) => {
+                        tracing::error!(err = %e, "personal access token rejected");
+                        return Err(e.into());
+                    }
+                }
+                let fut = service.borrow_mut().call(req);
+                fut.await
+            });
+        }
+
+        let claims = match self.keys.decode::<super::routes::auth::Claims>(
             raw_token.as_str(),
-            &jsonwebtoken::DecodingKey::from_secret(self.secret.0.as_bytes()),
-            &jsonwebtoken::Validation {
-                leeway: 10,
-                validate_exp: true,
-                validate_nbf: true,
-                aud: Some({
-                    let mut set = std::collections::HashSet::new();
-                    set.insert(super::routes::auth::AUD.to_owned());
-                    set
-                }),
-                iss: Some(super::routes::auth::ISS.into()),
-                sub: None,
-                algorithms: vec![jsonwebtoken::Algorithm::HS512],
-            },
+            super::routes::auth::validation(super::routes::auth::AUD),
         ) {
-            tracing::error!(%raw_token, %err, "failed to decode JWT");
-            return box_error(TicxError::InvalidCredentials);
+            Ok(claims) => claims,
+            Err(err) => {
+                tracing::error!(%raw_token, %err, "failed to decode JWT");
+                return box_error(TicxError::InvalidCredentials);
+            }
+        };
+
+        drop(guard);
+        let service = self.service.clone();
+        let db = self.db.clone();
+        if let Some(revocation) = self.revocations.check_cached(&claims.jti, claims.sid) {
+            return match refuse_revoked(revocation, &claims) {
+                Ok(()) => Self::call_validated(service, db, req, claims),
+                Err(e) => box_error(e),
+            };
         }
 
-        tracing::trace!("JTW validation OK");
+        // revocations are stored in the DB, which is queried on the thread pool not to block the worker
+        let revocations = self.revocations.clone();
+        Box::pin(async move {
+            let (jti, session_id, exp) = (claims.jti.clone(), claims.sid, claims.exp);
+            let revocation = web::block(move || revocations.check(&jti, session_id, exp))
+                .await
+                .map_err(TicxError::from)?;
+            refuse_revoked(revocation, &claims)?;
+            Self::call_validated(service, db, req, claims).await
+        })
+    }
+}
 
-        drop(guard);
+fn refuse_revoked(
+    revocation: super::revocation::Revocation,
+    claims: &super::routes::auth::Claims,
+) -> TicxResult<()> {
+    use super::revocation::Revocation;
+    match revocation {
+        Revocation::None => Ok(()),
+        Revocation::Token => {
+            tracing::warn!(sub = %claims.sub, jti = %claims.jti, "revoked JWT used");
+            Err(TicxError::InvalidToken("token has been revoked".into()))
+        }
+        Revocation::Session => {
+            tracing::warn!(sub = %claims.sub, session_id = ?claims.sid, "JWT of revoked session used");
+            Err(TicxError::InvalidToken("session has been revoked".into()))
+        }
+    }
+}
+
+impl<S, B> JWTValidationService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    /// Passes request with valid, not revoked `claims` on.
+    fn call_validated(
+        service: Rc<RefCell<S>>,
+        db: Arc<Db>,
+        req: ServiceRequest,
+        claims: super::routes::auth::Claims,
+    ) -> Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>> {
+        tracing::trace!(sub = %claims.sub, role = ?claims.role, "JTW validation OK");
+        let impersonation = match (claims.actor_id(), claims.user_id()) {
+            (Ok(Some(actor_id)), Ok(user_id)) => Some((actor_id, user_id)),
+            (Ok(None), _) => None,
+            (Err(e), _) | (_, Err(e)) => return box_error(e),
+        };
+        req.extensions_mut().insert(claims);
 
-        pin_svc_call!(self, req)
+        match impersonation {
+            Some((actor_id, user_id)) if !req.method().is_safe() => {
+                Self::call_audited(service, db, req, actor_id, user_id)
+            }
+            Some((actor_id, user_id)) => {
+                tracing::info!(actor_id, user_id, method = %req.method(), path = req.path(), "impersonated read");
+                Box::pin(service.borrow_mut().call(req))
+            }
+            None => Box::pin(service.borrow_mut().call(req)),
+        }
+    }
+
+    /// Writes made with impersonation token are recorded with both identities before they are handled, request
+    /// which cannot be recorded is refused. Status of the response is added once it is known.
+    fn call_audited(
+        service: Rc<RefCell<S>>,
+        db: Arc<Db>,
+        req: ServiceRequest,
+        actor_id: i32,
+        user_id: i32,
+    ) -> Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>> {
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        tracing::info!(actor_id, user_id, %method, %path, "impersonated write");
+        let record_id = match db.record_impersonation(actor_id, user_id, &method, &path) {
+            Ok(id) => id,
+            Err(e) => return box_error(e.into()),
+        };
+
+        let fut = service.borrow_mut().call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            if let Err(err) =
+                web::block(move || db.complete_impersonation(record_id, status.as_u16() as i16))
+                    .await
+            {
+                tracing::error!(%err, record_id, "failed to record status of impersonated write");
+            }
+            res
+        })
+    }
+}
+
+/// Renders timestamps of responses in timezone the client asks for, see [`super::timezone`]. It has to be
+/// wrapped by [`JWTValidationMiddleware`], timezone of the user's profile is looked up by the claims.
+pub(super) struct TimezoneMiddleware {
+    pub db: Arc<Db>,
+}
+
+impl<S, B> Transform<S> for TimezoneMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TimezoneService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TimezoneService {
+            service: Rc::new(RefCell::new(service)),
+            db: self.db.clone(),
+        })
+    }
+}
+
+pub(super) struct TimezoneService<S> {
+    // shared with the future looking up timezone of the user, which calls it once the DB answers
+    service: Rc<RefCell<S>>,
+    db: Arc<Db>,
+}
+
+/// Timezone `X-Timezone` header asks for.
+enum RequestedTimezone {
+    Named(chrono_tz::Tz),
+    /// timezone of the profile of the user with this id
+    OfUser(i32),
+}
+
+fn requested_timezone(req: &ServiceRequest) -> TicxResult<Option<RequestedTimezone>> {
+    let name = match req.headers().get(super::timezone::TIMEZONE_HEADER) {
+        Some(value) => value.to_str().map_err(|err| TicxError::InvalidHeader {
+            header: super::timezone::TIMEZONE_HEADER,
+            value: "failed to decode".into(),
+            error: err.to_string(),
+        })?,
+        None => return Ok(None),
+    };
+
+    if name != super::timezone::USER_TIMEZONE {
+        return super::timezone::parse(name).map(|tz| Some(RequestedTimezone::Named(tz)));
+    }
+    let user_id = req
+        .extensions()
+        .get::<super::routes::auth::Claims>()
+        .ok_or(TicxError::MissingAuthHeader)?
+        .user_id()?;
+    Ok(Some(RequestedTimezone::OfUser(user_id)))
+}
+
+impl<S, B> Service for TimezoneService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let requested = match requested_timezone(&req) {
+            Ok(Some(requested)) => requested,
+            Ok(None) => return Box::pin(self.service.borrow_mut().call(req)),
+            Err(e) => return box_error(e),
+        };
+
+        let service = self.service.clone();
+        let db = self.db.clone();
+        Box::pin(async move {
+            let tz = match requested {
+                RequestedTimezone::Named(tz) => tz,
+                RequestedTimezone::OfUser(user_id) => {
+                    let user = web::block(move || db.select_user(user_id))
+                        .await
+                        .map_err(TicxError::from)?;
+                    super::timezone::parse(&user.timezone)?
+                }
+            };
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = super::timezone::InTimezone::new(tz, fut).await?;
+            res.headers_mut().insert(
+                actix_web::http::header::HeaderName::from_static("x-timezone"),
+                HeaderValue::from_str(tz.name()).expect("timezone name is valid header value"),
+            );
+            Ok(res)
+        })
     }
 }
 
@@ -250,9 +512,8 @@ fn parse_token(header_value: &HeaderValue) -> TicxResult<String> {
                 error: "failed to retrieve token from header value".into(),
             }
         })
-        .map_err(|err| {
+        .inspect_err(|_| {
             tracing::error!("AUTHORIZATION token in invalid format. Expected 'Bearer {{JWT}}'");
-            err
         })
         .map(|(_bearer, raw_token)| {
             tracing::trace!("successfully parsed JWT");
@@ -301,13 +562,13 @@ fn parse_token(header_value: &HeaderValue) -> TicxResult<String> {
 //
 //     fn call(&mut self, req: Self::Request) -> Self::Future {
 //         crate::metrics::REQUESTS_COUNTER.inc();
-//         pin_svc_call!(self, req)
+//         Box::pin(self.service.call(req))
 //     }
 // }
 
 pub(super) struct HttpMetricsCounterMiddlware;
 
-impl<'a, S, B> Transform<S> for HttpMetricsCounterMiddlware
+impl<S, B> Transform<S> for HttpMetricsCounterMiddlware
 where
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
@@ -330,7 +591,7 @@ pub(super) struct HttpMetricsCounterService<S> {
     service: S,
 }
 
-impl<'a, S, B> Service for HttpMetricsCounterService<S>
+impl<S, B> Service for HttpMetricsCounterService<S>
 where
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
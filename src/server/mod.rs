@@ -2,30 +2,71 @@ use actix_web::middleware::Logger;
 use actix_web_opentelemetry::RequestTracing;
 use std::sync::Arc;
 
+mod auth_providers;
+mod avatar;
+mod keys;
+mod lockout;
+mod mail;
+mod mfa;
 mod middlewares;
+mod oidc;
+mod permissions;
+mod registration;
+mod revocation;
 mod routes;
+mod timezone;
 
 #[tracing::instrument(skip(db))]
 pub async fn start(db: Arc<db::Db>) -> Result<(), Box<dyn std::error::Error>> {
     let addr = "127.0.0.1:8080";
-    let secret = Arc::new(routes::auth::Secret(String::from("my_super_jwt_secret")));
+    let keys = Arc::new(keys::KeySet::from_env()?);
+    let duplicate_detection = Arc::new(routes::ticket::DuplicateDetection::from_env());
+    let revocations = Arc::new(revocation::RevocationList::new(db.clone()));
+    let mailer = Arc::new(mail::Mailer::from_env()?);
+    let lockout = lockout::LoginLockout::from_env();
+    let auth_providers = Arc::new(auth_providers::AuthProviders::from_env()?);
+    let oidc = oidc::OidcProvider::from_env()?.map(Arc::new);
+    let mfa_policy = mfa::MfaPolicy::from_env();
+    let registration = Arc::new(registration::RegistrationPolicy::from_env());
     tracing::trace!(?addr, "starting server");
     actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .data(db.clone())
-            .data(secret.clone())
+            .data(keys.clone())
+            .data(duplicate_detection.clone())
+            .data(revocations.clone())
+            .data(mailer.clone())
+            .data(mfa_policy)
+            .data(registration.clone())
             .service(
                 actix_web::Scope::new("/api")
                     .service(routes::index)
                     .service(routes::user_routes())
+                    .service(routes::me_routes())
                     .service(routes::ticket_routes())
+                    .service(routes::team_routes())
+                    .service(routes::report_routes())
+                    .service(routes::admin_routes())
+                    .wrap(middlewares::TimezoneMiddleware { db: db.clone() })
                     .wrap(middlewares::JWTValidationMiddleware {
-                        secret: secret.clone(),
+                        db: db.clone(),
+                        keys: keys.clone(),
+                        revocations: revocations.clone(),
                     }),
             )
-            .service(
-                routes::auth_routes().wrap(middlewares::BasicAuthMiddleware { db: db.clone() }),
-            )
+            .service(routes::auth_routes(
+                db.clone(),
+                auth_providers.clone(),
+                lockout,
+                middlewares::JWTValidationMiddleware {
+                    db: db.clone(),
+                    keys: keys.clone(),
+                    revocations: revocations.clone(),
+                },
+                oidc.clone(),
+                mfa_policy,
+            ))
+            .service(routes::auth::jwks)
             .service(actix_web::Scope::new("prom").service(routes::metrics_routes()))
             .wrap(Logger::default())
             .wrap(RequestTracing::new())
@@ -0,0 +1,297 @@
+//! OpenID Connect login, authorization code flow with PKCE. Configured by environment:
+//!
+//! - `TICX_OIDC_ISSUER` - issuer of the provider, the rest of its configuration is discovered from
+//!   `<issuer>/.well-known/openid-configuration`. OpenID Connect login is disabled when not set.
+//! - `TICX_OIDC_CLIENT_ID` - id ticX is registered with at the provider.
+//! - `TICX_OIDC_CLIENT_SECRET` - secret of confidential client, public client relies only on PKCE.
+//! - `TICX_OIDC_REDIRECT_URL` - where the provider redirects back to, `<TICX_PUBLIC_URL>/auth/oidc/callback`
+//!   by default.
+//! - `TICX_OIDC_SCOPES` - `openid email profile` by default.
+//!
+//! Provider is called with blocking HTTP client, methods of [`OidcProvider`] have to be run in the thread pool.
+
+use super::keys::Jwks;
+use super::routes::auth::random_token;
+use crate::errors::{TicxError, TicxResult};
+use jsonwebtoken::Validation;
+use serde::Deserialize;
+use std::sync::RwLock;
+use std::time::Duration;
+
+const DEFAULT_SCOPES: &str = "openid email profile";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// tolerated clock difference between ticX and the provider
+const LEEWAY: u64 = 60;
+
+/// Part of provider configuration ticX needs, see OpenID Connect Discovery 1.0.
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims of ID token users are linked by and created from.
+#[derive(Debug, Deserialize)]
+pub(crate) struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub given_name: Option<String>,
+    #[serde(default)]
+    pub family_name: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// User created on the first login. Email is taken only when the provider verified it, existing user with
+    /// the same email gets linked instead. Password is random, the user logs in only through the provider
+    /// until it is reset.
+    pub fn new_user(&self) -> db::dbo::NewUser {
+        let email = self.email.clone().filter(|_| self.email_verified);
+        let username = self
+            .preferred_username
+            .clone()
+            .or_else(|| email.clone())
+            .unwrap_or_else(|| self.sub.clone());
+
+        db::dbo::NewUser::new(
+            username,
+            random_token(32),
+            self.given_name.clone().unwrap_or_default(),
+            self.family_name.clone().unwrap_or_default(),
+        )
+        .with_email(email)
+    }
+}
+
+pub(crate) struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    agent: ureq::Agent,
+    /// discovered on the first login
+    metadata: RwLock<Option<ProviderMetadata>>,
+}
+
+impl OidcProvider {
+    pub fn new(
+        issuer: &str,
+        client_id: &str,
+        client_secret: Option<&str>,
+        redirect_url: &str,
+    ) -> Self {
+        OidcProvider {
+            issuer: issuer.trim_end_matches('/').into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.map(String::from),
+            redirect_url: redirect_url.into(),
+            scopes: DEFAULT_SCOPES.into(),
+            agent: ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build(),
+            metadata: RwLock::new(None),
+        }
+    }
+
+    pub fn from_env() -> TicxResult<Option<Self>> {
+        let issuer = match dotenv::var("TICX_OIDC_ISSUER") {
+            Ok(issuer) => issuer,
+            Err(_) => return Ok(None),
+        };
+        let client_id = dotenv::var("TICX_OIDC_CLIENT_ID")
+            .map_err(|_| oidc_error("TICX_OIDC_CLIENT_ID is required by OpenID Connect login"))?;
+        let client_secret = dotenv::var("TICX_OIDC_CLIENT_SECRET").ok();
+        let redirect_url = dotenv::var("TICX_OIDC_REDIRECT_URL").unwrap_or_else(|_| {
+            format!(
+                "{}/auth/oidc/callback",
+                dotenv::var("TICX_PUBLIC_URL")
+                    .unwrap_or_else(|_| "http://localhost:8080".into())
+                    .trim_end_matches('/')
+            )
+        });
+
+        let mut provider =
+            OidcProvider::new(&issuer, &client_id, client_secret.as_deref(), &redirect_url);
+        if let Ok(scopes) = dotenv::var("TICX_OIDC_SCOPES") {
+            provider.scopes = scopes;
+        }
+        tracing::info!(issuer = %provider.issuer, redirect_url = %provider.redirect_url, "OpenID Connect login enabled");
+        Ok(Some(provider))
+    }
+
+    fn metadata(&self) -> TicxResult<ProviderMetadata> {
+        if let Some(metadata) = self
+            .metadata
+            .read()
+            .map_err(|_| TicxError::Unknown)?
+            .as_ref()
+        {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let metadata = self
+            .agent
+            .get(&url)
+            .call()
+            .map_err(oidc_error)?
+            .into_json::<ProviderMetadata>()
+            .map_err(oidc_error)?;
+        // provider must not be impersonated by configuration of another issuer
+        if metadata.issuer.trim_end_matches('/') != self.issuer {
+            return Err(oidc_error(format!(
+                "provider configuration is of issuer '{}', expected '{}'",
+                metadata.issuer, self.issuer
+            )));
+        }
+
+        tracing::debug!(?metadata, "discovered OpenID Connect provider");
+        *self.metadata.write().map_err(|_| TicxError::Unknown)? = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Where to send the user to log in at the provider.
+    pub fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> TicxResult<String> {
+        let metadata = self.metadata()?;
+        url::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &code_challenge(code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(String::from)
+        .map_err(oidc_error)
+    }
+
+    /// Exchanges authorization code for ID token and returns its verified claims.
+    #[tracing::instrument(skip_all)]
+    pub fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> TicxResult<IdTokenClaims> {
+        let metadata = self.metadata()?;
+
+        let mut request = self.agent.post(&metadata.token_endpoint);
+        if let Some(secret) = &self.client_secret {
+            // client_secret_basic, both parts are form encoded first
+            let credentials = format!(
+                "{}:{}",
+                url::form_urlencoded::byte_serialize(self.client_id.as_bytes()).collect::<String>(),
+                url::form_urlencoded::byte_serialize(secret.as_bytes()).collect::<String>()
+            );
+            request = request.set(
+                "Authorization",
+                &format!("Basic {}", base64::encode(credentials)),
+            );
+        }
+        let response = request
+            .send_form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+                ("client_id", &self.client_id),
+                ("code_verifier", code_verifier),
+            ])
+            .map_err(|err| match err {
+                ureq::Error::Status(status, response) if status < 500 => {
+                    TicxError::InvalidToken(format!(
+                        "authorization code was refused by identity provider with {}: {}",
+                        status,
+                        response.into_string().unwrap_or_default()
+                    ))
+                }
+                err => oidc_error(err),
+            })?
+            .into_json::<TokenResponse>()
+            .map_err(oidc_error)?;
+
+        let claims = self.verify_id_token(&metadata, &response.id_token)?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(TicxError::InvalidToken(
+                "nonce of ID token does not match the login".into(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Keys are fetched every time, logins are rare and keys of the provider rotate.
+    fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> TicxResult<IdTokenClaims> {
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|err| TicxError::InvalidToken(err.to_string()))?;
+        let jwks = self
+            .agent
+            .get(&metadata.jwks_uri)
+            .call()
+            .map_err(oidc_error)?
+            .into_json::<Jwks>()
+            .map_err(oidc_error)?;
+
+        let jwk = match &header.kid {
+            Some(kid) => jwks.keys.iter().find(|k| &k.kid == kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| {
+            TicxError::InvalidToken(format!(
+                "ID token is signed by unknown key {:?}",
+                header.kid
+            ))
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = LEEWAY;
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &jwk.decoding_key(header.alg)?, &validation)
+            .map(|data| data.claims)
+            .map_err(|err| TicxError::InvalidToken(format!("invalid ID token: {}", err)))
+    }
+}
+
+/// PKCE `S256` challenge of `code_verifier`, see RFC 7636.
+pub(crate) fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        ring::digest::digest(&ring::digest::SHA256, code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn oidc_error<E: std::fmt::Display>(err: E) -> TicxError {
+    TicxError::GenericError {
+        what: "OpenID Connect login",
+        error: err.to_string(),
+    }
+}
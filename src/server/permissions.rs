@@ -0,0 +1,351 @@
+//! Role based access control. Role of the user is carried in the JWT, handlers declare permission they need
+//! by taking [`Authorized`] guard as an argument, e.g. `_auth: Authorized<require::TicketDelete>`.
+//!
+//! Tickets belong to projects and users get roles per project in `project_members`. Handlers working with
+//! tickets take [`ProjectAccess`] instead, which resolves projects where the user's role grants the permission.
+//! Global admins have access to every project.
+//!
+//! Personal access tokens are further limited to their scopes, one scope per [`Permission`], e.g. `ticket:read`.
+//! Impersonation tokens never grant [`Permission::Admin`], whatever the role of the impersonated user is.
+
+use super::routes::auth::Claims;
+use crate::errors::{TicxError, TicxResult};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use db::dbo::{role, ProjectScope};
+use db::Db;
+use futures::future::{err, ok, ready, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Member,
+    Reporter,
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    TicketRead,
+    TicketCreate,
+    TicketWrite,
+    TicketDelete,
+    UserRead,
+    UserWrite,
+    UserDelete,
+    ReportRead,
+    Admin,
+}
+
+const VIEWER_PERMISSIONS: &[Permission] = &[
+    Permission::TicketRead,
+    Permission::UserRead,
+    Permission::ReportRead,
+];
+
+const REPORTER_PERMISSIONS: &[Permission] = &[
+    Permission::TicketRead,
+    Permission::UserRead,
+    Permission::ReportRead,
+    Permission::TicketCreate,
+];
+
+const MEMBER_PERMISSIONS: &[Permission] = &[
+    Permission::TicketRead,
+    Permission::UserRead,
+    Permission::ReportRead,
+    Permission::TicketCreate,
+    Permission::TicketWrite,
+];
+
+const ALL_PERMISSIONS: &[Permission] = &[
+    Permission::TicketRead,
+    Permission::TicketCreate,
+    Permission::TicketWrite,
+    Permission::TicketDelete,
+    Permission::UserRead,
+    Permission::UserWrite,
+    Permission::UserDelete,
+    Permission::ReportRead,
+    Permission::Admin,
+];
+
+impl Permission {
+    /// Name of the scope of personal access tokens granting the permission.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Permission::TicketRead => "ticket:read",
+            Permission::TicketCreate => "ticket:create",
+            Permission::TicketWrite => "ticket:write",
+            Permission::TicketDelete => "ticket:delete",
+            Permission::UserRead => "user:read",
+            Permission::UserWrite => "user:write",
+            Permission::UserDelete => "user:delete",
+            Permission::ReportRead => "report:read",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Permission {
+    type Err = TicxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_PERMISSIONS
+            .iter()
+            .find(|p| p.scope() == s)
+            .copied()
+            .ok_or_else(|| {
+                TicxError::BadRequest(format!(
+                    "unknown scope '{}', expected one of {}",
+                    s,
+                    ALL_PERMISSIONS
+                        .iter()
+                        .map(|p| format!("'{}'", p.scope()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })
+    }
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => role::ADMIN,
+            Role::Member => role::MEMBER,
+            Role::Reporter => role::REPORTER,
+            Role::Viewer => role::VIEWER,
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Member => MEMBER_PERMISSIONS.contains(&permission),
+            Role::Reporter => REPORTER_PERMISSIONS.contains(&permission),
+            Role::Viewer => VIEWER_PERMISSIONS.contains(&permission),
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = TicxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            role::ADMIN => Ok(Role::Admin),
+            role::MEMBER => Ok(Role::Member),
+            role::REPORTER => Ok(Role::Reporter),
+            role::VIEWER => Ok(Role::Viewer),
+            unknown => Err(TicxError::BadRequest(format!(
+                "unknown role '{}', expected one of 'admin', 'member', 'reporter' or 'viewer'",
+                unknown
+            ))),
+        }
+    }
+}
+
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required_permissions {
+    ($($name:ident),+) => {
+        /// Marker types used as the parameter of [`Authorized`].
+        pub mod require {
+            $(
+                #[derive(Debug)]
+                pub struct $name;
+
+                impl super::RequiredPermission for $name {
+                    const PERMISSION: super::Permission = super::Permission::$name;
+                }
+            )+
+        }
+    };
+}
+
+required_permissions!(
+    TicketRead,
+    TicketCreate,
+    TicketWrite,
+    TicketDelete,
+    UserRead,
+    UserWrite,
+    UserDelete,
+    ReportRead,
+    Admin
+);
+
+/// Guard which lets the request through only if role of the caller grants permission `P`.
+/// Requires claims validated by `JWTValidationMiddleware`.
+pub struct Authorized<P> {
+    pub user_id: String,
+    pub role: Role,
+    _permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> std::fmt::Debug for Authorized<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authorized")
+            .field("user_id", &self.user_id)
+            .field("role", &self.role)
+            .field("permission", &P::PERMISSION)
+            .finish()
+    }
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = TicxError;
+    type Future = Ready<TicxResult<Authorized<P>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let claims = match validated_claims(req) {
+            Ok(claims) => claims,
+            Err(e) => return err(e),
+        };
+
+        if !claims.role.has(P::PERMISSION) {
+            tracing::warn!(sub = %claims.sub, role = ?claims.role, permission = ?P::PERMISSION, "permission denied");
+            return err(TicxError::Forbidden(format!(
+                "role '{}' does not grant {:?}",
+                claims.role.as_str(),
+                P::PERMISSION
+            )));
+        }
+        if let Err(e) = check_scope(&claims, P::PERMISSION) {
+            return err(e);
+        }
+        if let Err(e) = check_impersonation(&claims, P::PERMISSION) {
+            return err(e);
+        }
+
+        ok(Authorized {
+            user_id: claims.sub,
+            role: claims.role,
+            _permission: PhantomData,
+        })
+    }
+}
+
+/// Guard resolving projects in which role of the caller grants permission `P`. Pass [`ProjectAccess::scope`]
+/// to `Db`, tickets outside of it are reported as not found.
+pub struct ProjectAccess<P> {
+    pub user_id: i32,
+    pub scope: ProjectScope,
+    _permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> std::fmt::Debug for ProjectAccess<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProjectAccess")
+            .field("user_id", &self.user_id)
+            .field("scope", &self.scope)
+            .field("permission", &P::PERMISSION)
+            .finish()
+    }
+}
+
+impl<P: RequiredPermission + 'static> FromRequest for ProjectAccess<P> {
+    type Error = TicxError;
+    type Future = LocalBoxFuture<'static, TicxResult<ProjectAccess<P>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let claims = match validated_claims(req) {
+            Ok(claims) => claims,
+            Err(e) => return Box::pin(ready(Err(e))),
+        };
+        let user_id = match claims.user_id() {
+            Ok(user_id) => user_id,
+            Err(e) => return Box::pin(ready(Err(e))),
+        };
+        if let Err(e) = check_scope(&claims, P::PERMISSION) {
+            return Box::pin(ready(Err(e)));
+        }
+        if let Err(e) = check_impersonation(&claims, P::PERMISSION) {
+            return Box::pin(ready(Err(e)));
+        }
+
+        if claims.role == Role::Admin {
+            return Box::pin(ready(Ok(ProjectAccess {
+                user_id,
+                scope: ProjectScope::All,
+                _permission: PhantomData,
+            })));
+        }
+
+        let db = match req.app_data::<web::Data<Arc<Db>>>() {
+            Some(db) => db.get_ref().clone(),
+            None => {
+                tracing::error!("DB is not part of application data");
+                return Box::pin(ready(Err(TicxError::Unknown)));
+            }
+        };
+
+        Box::pin(async move {
+            let memberships = web::block(move || db.select_memberships(user_id)).await?;
+
+            // project can be listed more than once when access is granted also through teams
+            let mut project_ids = memberships
+                .into_iter()
+                .filter(|m| match m.role.parse::<Role>() {
+                    Ok(role) => role.has(P::PERMISSION),
+                    Err(_) => {
+                        tracing::error!(project_id = m.project_id, role = %m.role, "unknown project role");
+                        false
+                    }
+                })
+                .map(|m| m.project_id)
+                .collect::<Vec<i32>>();
+            project_ids.sort_unstable();
+            project_ids.dedup();
+
+            tracing::trace!(user_id, ?project_ids, permission = ?P::PERMISSION, "resolved project access");
+            Ok(ProjectAccess {
+                user_id,
+                scope: ProjectScope::Only(project_ids),
+                _permission: PhantomData,
+            })
+        })
+    }
+}
+
+/// Requests made with personal access token need the scope of the permission besides the role granting it.
+fn check_scope(claims: &Claims, permission: Permission) -> TicxResult<()> {
+    match &claims.scopes {
+        Some(scopes) if !scopes.contains(&permission) => {
+            tracing::warn!(sub = %claims.sub, ?scopes, ?permission, "scope of personal access token missing");
+            Err(TicxError::Forbidden(format!(
+                "personal access token does not have scope '{}'",
+                permission.scope()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Admin acting as another user sees what the user sees, administration is done with their own token.
+fn check_impersonation(claims: &Claims, permission: Permission) -> TicxResult<()> {
+    match &claims.act {
+        Some(act) if permission == Permission::Admin => {
+            tracing::warn!(sub = %claims.sub, actor = %act.sub, "impersonation token used for administration");
+            Err(TicxError::Forbidden(
+                "impersonation token cannot be used for administration".into(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validated_claims(req: &HttpRequest) -> TicxResult<Claims> {
+    req.extensions().get::<Claims>().cloned().ok_or_else(|| {
+        tracing::error!("no validated claims found, route is not behind JWT validation");
+        TicxError::InvalidToken("no validated claims".into())
+    })
+}
@@ -0,0 +1,102 @@
+//! Users who join on their own, either with invitation link issued by admin or by open self-registration.
+//! Configured by environment:
+//!
+//! - `TICX_REGISTRATION_DOMAINS` - comma separated email domains users can register with on their own,
+//!   e.g. `example.org,example.com`. Self-registration is off when it is not set, invitations work regardless.
+//!
+//! Self-registered users have to verify their email before they can log in with password.
+
+use crate::errors::{TicxError, TicxResult};
+use crate::server::mail::{Mail, Mailer};
+use crate::server::routes::auth::random_token;
+use db::Db;
+
+const EMAIL_VERIFICATION_LIFETIME: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RegistrationPolicy {
+    allowed_domains: Vec<String>,
+}
+
+impl RegistrationPolicy {
+    pub fn new(allowed_domains: &[&str]) -> Self {
+        RegistrationPolicy {
+            allowed_domains: allowed_domains
+                .iter()
+                .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let domains = dotenv::var("TICX_REGISTRATION_DOMAINS").unwrap_or_default();
+        let policy = RegistrationPolicy::new(&domains.split(',').collect::<Vec<_>>());
+        match policy.enabled() {
+            true => tracing::info!(%domains, "self-registration enabled"),
+            false => tracing::info!("self-registration disabled"),
+        }
+        policy
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.allowed_domains.is_empty()
+    }
+
+    /// Refuses registration with email outside of allowed domains, or any when registration is off.
+    pub fn check(&self, email: &str) -> TicxResult<()> {
+        if !self.enabled() {
+            return Err(TicxError::Forbidden(
+                "self-registration is disabled, ask an admin for an invitation".into(),
+            ));
+        }
+
+        let domain = email_domain(email)?;
+        if !self.allowed_domains.contains(&domain) {
+            return Err(TicxError::Forbidden(format!(
+                "registration with email of '{}' is not allowed",
+                domain
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Lowercase domain of `email`, address without local part or domain is refused.
+pub(crate) fn email_domain(email: &str) -> TicxResult<String> {
+    email
+        .rsplit_once('@')
+        .map(|(local, domain)| (local, domain.to_lowercase()))
+        .filter(|(local, domain)| !local.is_empty() && !domain.is_empty())
+        .map(|(_, domain)| domain)
+        .ok_or_else(|| TicxError::BadRequest(format!("'{}' is not an email address", email)))
+}
+
+/// Mails link which verifies `email` of the user, links sent before stop working.
+pub(crate) fn send_verification(
+    db: &Db,
+    mailer: &Mailer,
+    user: &db::dbo::User,
+    email: &str,
+) -> TicxResult<()> {
+    let token = random_token(32);
+    db.insert_email_verification(
+        user.id,
+        email,
+        &token,
+        chrono::Utc::now().naive_utc() + chrono::Duration::seconds(EMAIL_VERIFICATION_LIFETIME),
+    )?;
+
+    mailer.send(Mail {
+        to: email.to_string(),
+        subject: "Verify your TicX email".into(),
+        body: format!(
+            "Hi {},\n\nto verify the email of your TicX account '{}' open {}\n\
+            The link is valid for {} hours. If you did not ask for it, just ignore this mail.",
+            user.firstname,
+            user.username,
+            mailer.link(&format!("/verify-email?token={}", token)),
+            EMAIL_VERIFICATION_LIFETIME / 60 / 60,
+        ),
+    })
+}
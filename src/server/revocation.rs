@@ -0,0 +1,169 @@
+//! Access tokens revoked before they expired, e.g. by logout, and revoked sessions whose tokens are refused
+//! as a whole. `JWTValidationMiddleware` consults the list for every request, so results are cached: revoked
+//! tokens until they expire, tokens which are not revoked only for [`NOT_REVOKED_TTL`] as they can be revoked
+//! by another instance of the server meanwhile.
+
+use crate::errors::TicxResult;
+use db::Db;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const NOT_REVOKED_TTL: Duration = Duration::from_secs(30);
+/// Expired entries are dropped once the cache grows over this size.
+const CACHE_PRUNE_SIZE: usize = 10_000;
+
+#[derive(Debug)]
+struct Cached {
+    revoked: bool,
+    until: Instant,
+}
+
+/// Why a token is refused, if it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Revocation {
+    None,
+    Token,
+    Session,
+}
+
+pub(crate) struct RevocationList {
+    db: Arc<Db>,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl RevocationList {
+    pub fn new(db: Arc<Db>) -> Self {
+        RevocationList {
+            db,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Revocation of token with given `jti` and its session answered from the cache alone, `None` when the DB
+    /// has to be asked by [`RevocationList::check`].
+    pub fn check_cached(&self, jti: &str, session_id: Option<i32>) -> Option<Revocation> {
+        let now = Instant::now();
+        let cache = self.cache();
+        let cached = |key: &str| cache.get(key).filter(|c| c.until > now).map(|c| c.revoked);
+        if cached(jti)? {
+            return Some(Revocation::Token);
+        }
+        match session_id {
+            Some(session_id) if cached(&session_key(session_id))? => Some(Revocation::Session),
+            _ => Some(Revocation::None),
+        }
+    }
+
+    /// Revocation of token with given `jti` and its session, the token expires at `exp` (seconds since epoch).
+    /// Queries the DB on cache miss, so it is to be called on the thread pool.
+    pub fn check(&self, jti: &str, session_id: Option<i32>, exp: i64) -> TicxResult<Revocation> {
+        if self.is_revoked(jti, exp)? {
+            return Ok(Revocation::Token);
+        }
+        match session_id {
+            Some(session_id) if self.is_session_revoked(session_id, exp)? => {
+                Ok(Revocation::Session)
+            }
+            _ => Ok(Revocation::None),
+        }
+    }
+
+    /// Whether token with given `jti`, expiring at `exp` (seconds since epoch), was revoked.
+    #[tracing::instrument(skip(self))]
+    pub fn is_revoked(&self, jti: &str, exp: i64) -> TicxResult<bool> {
+        let now = Instant::now();
+        if let Some(cached) = self.cache().get(jti).filter(|c| c.until > now) {
+            tracing::trace!(revoked = cached.revoked, "revocation cache hit");
+            return Ok(cached.revoked);
+        }
+
+        let revoked = self.db.is_token_revoked(jti)?;
+        let until = match revoked {
+            true => until_expired(exp),
+            false => now + NOT_REVOKED_TTL,
+        };
+        self.remember(jti, revoked, until);
+        Ok(revoked)
+    }
+
+    /// Revokes token with given `jti` which expires at `exp` (seconds since epoch).
+    #[tracing::instrument(skip(self))]
+    pub fn revoke(&self, jti: &str, exp: i64) -> TicxResult<()> {
+        let expires = chrono::NaiveDateTime::from_timestamp(exp, 0);
+        self.db.revoke_token(jti, expires)?;
+        self.remember(jti, true, until_expired(exp));
+        Ok(())
+    }
+
+    /// Whether session of token expiring at `exp` was revoked, the session is marked as used otherwise.
+    /// Last use is thus recorded at most once per [`NOT_REVOKED_TTL`].
+    #[tracing::instrument(skip(self))]
+    fn is_session_revoked(&self, session_id: i32, exp: i64) -> TicxResult<bool> {
+        let key = session_key(session_id);
+        let now = Instant::now();
+        if let Some(cached) = self.cache().get(&key).filter(|c| c.until > now) {
+            tracing::trace!(revoked = cached.revoked, "session revocation cache hit");
+            return Ok(cached.revoked);
+        }
+
+        let revoked = !self.db.use_session(session_id)?;
+        let until = match revoked {
+            true => until_expired(exp),
+            false => now + NOT_REVOKED_TTL,
+        };
+        self.remember(&key, revoked, until);
+        Ok(revoked)
+    }
+
+    /// Revokes session of the user, access tokens of the session are refused from now on.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_session(&self, user_id: i32, session_id: i32) -> TicxResult<()> {
+        self.db.revoke_session(user_id, session_id)?;
+        self.remember_revoked_session(session_id);
+        Ok(())
+    }
+
+    /// Revokes all sessions of the user, returns how many of them there were.
+    #[tracing::instrument(skip(self))]
+    pub fn revoke_sessions_of_user(&self, user_id: i32) -> TicxResult<usize> {
+        let sessions = self.db.revoke_sessions_of_user(user_id)?;
+        for session in &sessions {
+            self.remember_revoked_session(session.id);
+        }
+        Ok(sessions.len())
+    }
+
+    fn remember_revoked_session(&self, session_id: i32) {
+        // no access token of the session lives longer than this
+        let exp =
+            chrono::Utc::now().timestamp() + crate::server::routes::auth::ACCESS_TOKEN_LIFETIME;
+        self.remember(&session_key(session_id), true, until_expired(exp));
+    }
+
+    fn remember(&self, jti: &str, revoked: bool, until: Instant) {
+        let mut cache = self.cache();
+        if cache.len() >= CACHE_PRUNE_SIZE {
+            let now = Instant::now();
+            cache.retain(|_, cached| cached.until > now);
+        }
+        cache.insert(jti.to_string(), Cached { revoked, until });
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, Cached>> {
+        // cache holds no invariants which a panicking thread could break
+        self.cache
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Sessions share the cache with token ids, which are hex and so cannot collide with these keys.
+fn session_key(session_id: i32) -> String {
+    format!("session:{}", session_id)
+}
+
+fn until_expired(exp: i64) -> Instant {
+    let remaining = (exp - chrono::Utc::now().timestamp()).max(0) as u64;
+    Instant::now() + Duration::from_secs(remaining)
+}
@@ -0,0 +1,786 @@
+use super::auth::CurrentUser;
+use crate::errors::{TicxError, TicxResult};
+use crate::importer::{self, Source};
+use crate::metrics::*;
+use crate::server::keys::KeySet;
+use crate::server::mail::{Mail, Mailer};
+use crate::server::permissions::{require, Authorized, Role};
+use crate::server::revocation::RevocationList;
+use crate::server::timezone;
+use actix_web::web::Json;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use db::dbo::ProjectScope;
+use db::Db;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const DEFAULT_INVITATION_LIFETIME_DAYS: i64 = 7;
+const MAX_INVITATION_LIFETIME_DAYS: i64 = 30;
+const DEFAULT_IMPERSONATION_LIMIT: i64 = 100;
+const MAX_IMPERSONATION_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRequest {
+    /// path to the export file on the server
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleChange {
+    role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    users_created: usize,
+    users_linked: usize,
+    tickets_created: usize,
+    tickets_updated: usize,
+}
+
+impl From<db::dbo::ImportSummary> for ImportReport {
+    fn from(s: db::dbo::ImportSummary) -> Self {
+        ImportReport {
+            users_created: s.users_created,
+            users_linked: s.users_linked,
+            tickets_created: s.tickets_created,
+            tickets_updated: s.tickets_updated,
+        }
+    }
+}
+
+#[post("/import/{source}")]
+#[tracing::instrument(skip(db))]
+pub async fn import_issues(
+    _auth: Authorized<require::Admin>,
+    source: web::Path<String>,
+    json: Json<ImportRequest>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<ImportReport>> {
+    tracing::trace!("requested import of issues from other tracker");
+    let source = source.into_inner().parse::<Source>()?;
+    let path = json.into_inner().path;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "IMPORT"])
+        .start_timer();
+
+    let result = web::block(move || importer::import_file(&db, source, &path))
+        .await
+        .map(|summary| Json(summary.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[put("/user/{id}/role")]
+#[tracing::instrument(skip(db))]
+pub async fn set_user_role(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    json: Json<RoleChange>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<super::user::User>> {
+    tracing::trace!("requested change of user role");
+    let role = json.into_inner().role;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || db.update_user_role(id.into_inner(), role.as_str()))
+        .await
+        .map(|user| Json(user.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Lifts lockout of the user caused by too many failed logins.
+#[delete("/user/{id}/lockout")]
+#[tracing::instrument(skip(db))]
+pub async fn unlock_user(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested unlock of user login");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_LOGIN_FAILURES, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || {
+        let user = db.select_user(id.into_inner())?;
+        db.clear_login_failures(db::dbo::login_subject::USER, &user.username)
+    })
+    .await
+    .map(|_| HttpResponse::Ok().finish())
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokedSessions {
+    pub revoked: usize,
+}
+
+/// Logs the user out everywhere, all sessions and refresh tokens of the user are revoked.
+#[delete("/user/{id}/sessions")]
+#[tracing::instrument(skip(db, revocations))]
+pub async fn revoke_user_sessions(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+    revocations: web::Data<Arc<RevocationList>>,
+) -> TicxResult<Json<RevokedSessions>> {
+    let revocations = revocations.get_ref().clone();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_SESSIONS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<usize> {
+        let user = db.select_user(id.into_inner())?;
+        let revoked = revocations.revoke_sessions_of_user(user.id)?;
+        tracing::info!(user_id = user.id, revoked, "all sessions of user revoked");
+        Ok(revoked)
+    })
+    .await
+    .map(|revoked| Json(RevokedSessions { revoked }))
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deactivation {
+    pub user: super::user::User,
+    pub revoked_sessions: usize,
+    /// tickets assigned to the user which are not resolved yet, they should be reassigned
+    pub open_assignments: Vec<super::ticket::Ticket>,
+}
+
+/// Deactivates user who should not have access anymore, e.g. colleague who left. The user cannot log in, all
+/// their sessions are revoked and personal access tokens deleted. Tickets and history keep referring to them.
+#[post("/user/{id}/deactivate")]
+#[tracing::instrument(skip(db, revocations))]
+pub async fn deactivate_user(
+    _auth: Authorized<require::Admin>,
+    current_user: CurrentUser,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+    revocations: web::Data<Arc<RevocationList>>,
+) -> TicxResult<Json<Deactivation>> {
+    let user_id = id.into_inner();
+    if user_id == current_user.id {
+        return Err(TicxError::BadRequest(
+            "admins cannot deactivate themselves".into(),
+        ));
+    }
+    let revocations = revocations.get_ref().clone();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Deactivation> {
+        let user = db.deactivate_user(user_id)?;
+        let revoked_sessions = revocations.revoke_sessions_of_user(user_id)?;
+        let open_assignments = db.select_open_assignments(user_id)?;
+        tracing::info!(
+            user_id,
+            revoked_sessions,
+            open_assignments = open_assignments.len(),
+            "user deactivated"
+        );
+        Ok(Deactivation {
+            user: user.into(),
+            revoked_sessions,
+            open_assignments: open_assignments.into_iter().map(Into::into).collect(),
+        })
+    })
+    .await
+    .map(Json)
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Lets deactivated user log in again, they need to log in anew and create new personal access tokens.
+#[post("/user/{id}/reactivate")]
+#[tracing::instrument(skip(db))]
+pub async fn reactivate_user(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<super::user::User>> {
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || db.reactivate_user(id.into_inner()))
+        .await
+        .map(|user| {
+            tracing::info!(user_id = user.id, "user reactivated");
+            Json(user.into())
+        })
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+fn default_invitation_lifetime_days() -> i64 {
+    DEFAULT_INVITATION_LIFETIME_DAYS
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewInvitation {
+    /// the link is mailed there and the invited user gets it as verified email
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    role: Option<Role>,
+    #[serde(default = "default_invitation_lifetime_days")]
+    expires_in_days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invitation {
+    pub id: i32,
+    pub email: Option<String>,
+    pub role: String,
+    pub invited_by: Option<i32>,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub expires: chrono::NaiveDateTime,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub created: chrono::NaiveDateTime,
+    /// link the user accepts the invitation with, returned only when it is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl From<db::dbo::Invitation> for Invitation {
+    fn from(i: db::dbo::Invitation) -> Self {
+        Invitation {
+            id: i.id,
+            email: i.email,
+            role: i.role,
+            invited_by: i.invited_by,
+            expires: i.expires,
+            created: i.created,
+            link: None,
+        }
+    }
+}
+
+/// Issues invitation link with given role, the response is the only time the link is shown. When email is
+/// given, the link is mailed there too.
+#[post("/invitation")]
+#[tracing::instrument(skip(db, mailer))]
+pub async fn create_invitation(
+    _auth: Authorized<require::Admin>,
+    current_user: CurrentUser,
+    json: web::Json<NewInvitation>,
+    db: web::Data<Arc<Db>>,
+    mailer: web::Data<Arc<Mailer>>,
+) -> TicxResult<HttpResponse> {
+    let invited_by = current_user.id;
+    let invitation = json.into_inner();
+    if !(1..=MAX_INVITATION_LIFETIME_DAYS).contains(&invitation.expires_in_days) {
+        return Err(TicxError::BadRequest(format!(
+            "invitation has to expire in 1 to {} days",
+            MAX_INVITATION_LIFETIME_DAYS
+        )));
+    }
+    let email = invitation.email.map(|e| e.trim().to_string());
+    let role = invitation.role.unwrap_or(Role::Member);
+    let expires =
+        chrono::Utc::now().naive_utc() + chrono::Duration::days(invitation.expires_in_days);
+    let token = super::auth::random_token(32);
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_INVITATIONS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Invitation> {
+        let created =
+            db.insert_invitation(&token, email.as_deref(), role.as_str(), invited_by, expires)?;
+        let link = mailer.link(&format!("/invitation?token={}", token));
+        if let Some(email) = email {
+            mailer.send(Mail {
+                to: email,
+                subject: "Invitation to TicX".into(),
+                body: format!(
+                    "Hi,\n\nyou are invited to TicX. To create your account open {}\n\
+                    The link is valid for {} days.",
+                    link, invitation.expires_in_days,
+                ),
+            })?;
+        }
+        tracing::info!(invitation_id = created.id, "invitation created");
+        Ok(Invitation {
+            link: Some(link),
+            ..created.into()
+        })
+    })
+    .await
+    .map(|invitation| HttpResponse::Created().json(invitation))
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Invitations which were neither accepted nor expired.
+#[get("/invitation")]
+#[tracing::instrument(skip(db))]
+pub async fn list_invitations(
+    _auth: Authorized<require::Admin>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<Invitation>>> {
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_INVITATIONS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_open_invitations())
+        .await
+        .map(|invitations| Json(invitations.into_iter().map(Invitation::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Withdraws invitation which was not accepted yet.
+#[delete("/invitation/{id}")]
+#[tracing::instrument(skip(db))]
+pub async fn delete_invitation(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_INVITATIONS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_invitation(id.into_inner()))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Impersonation {
+    pub access_token: String,
+    pub token_type: String,
+    /// lifetime of the access token in seconds, it cannot be refreshed
+    pub expires_in: i64,
+    pub user: super::user::User,
+}
+
+/// Issues short-lived access token letting the admin see what the user sees, e.g. when the user cannot find
+/// a ticket. The token carries the admin in `act` claim, writes made with it are recorded in the impersonation
+/// audit with both identities and it is refused for administration. Admins and deactivated users cannot be
+/// impersonated.
+#[post("/impersonate/{user_id}")]
+#[tracing::instrument(skip(db, keys))]
+pub async fn impersonate(
+    _auth: Authorized<require::Admin>,
+    current_user: CurrentUser,
+    user_id: web::Path<i32>,
+    req: HttpRequest,
+    db: web::Data<Arc<Db>>,
+    keys: web::Data<Arc<KeySet>>,
+) -> TicxResult<Json<Impersonation>> {
+    let user_id = user_id.into_inner();
+    let actor_id = current_user.id;
+    if user_id == actor_id {
+        return Err(TicxError::BadRequest(
+            "admins cannot impersonate themselves".into(),
+        ));
+    }
+    let path = req.path().to_owned();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_IMPERSONATION_AUDIT, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Impersonation> {
+        let user = db.select_user(user_id)?;
+        if user.role.parse::<Role>()? == Role::Admin {
+            return Err(TicxError::Forbidden("admins cannot be impersonated".into()));
+        }
+        if !user.active {
+            return Err(TicxError::BadRequest(
+                "deactivated user cannot be impersonated".into(),
+            ));
+        }
+
+        let record_id = db.record_impersonation(actor_id, user_id, "POST", &path)?;
+        let access_token = super::auth::issue_impersonation_token(
+            &keys,
+            &user,
+            actor_id,
+            current_user.session_id,
+        )?;
+        db.complete_impersonation(record_id, 200)?;
+        tracing::warn!(actor_id, user_id, "impersonation started");
+
+        Ok(Impersonation {
+            access_token,
+            token_type: "Bearer".into(),
+            expires_in: super::auth::IMPERSONATION_TOKEN_LIFETIME,
+            user: user.into(),
+        })
+    })
+    .await
+    .map(Json)
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+fn default_impersonation_limit() -> i64 {
+    DEFAULT_IMPERSONATION_LIMIT
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImpersonationQuery {
+    /// only impersonations of this user
+    #[serde(default)]
+    user_id: Option<i32>,
+    #[serde(default = "default_impersonation_limit")]
+    limit: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationRecord {
+    pub id: i32,
+    /// admin who acted as the user
+    pub actor_id: i32,
+    pub user_id: i32,
+    pub method: String,
+    pub path: String,
+    /// status of the response, missing when the request did not finish
+    pub status: Option<i16>,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub created: chrono::NaiveDateTime,
+}
+
+impl From<db::dbo::ImpersonationRecord> for ImpersonationRecord {
+    fn from(r: db::dbo::ImpersonationRecord) -> Self {
+        ImpersonationRecord {
+            id: r.id,
+            actor_id: r.actor_id,
+            user_id: r.user_id,
+            method: r.method,
+            path: r.path,
+            status: r.status,
+            created: r.created,
+        }
+    }
+}
+
+/// Audit trail of impersonations, the latest first. Lists the impersonations themselves and writes made
+/// with impersonation tokens.
+#[get("/impersonation")]
+#[tracing::instrument(skip(db))]
+pub async fn list_impersonations(
+    _auth: Authorized<require::Admin>,
+    query: web::Query<ImpersonationQuery>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<ImpersonationRecord>>> {
+    let query = query.into_inner();
+    if !(1..=MAX_IMPERSONATION_LIMIT).contains(&query.limit) {
+        return Err(TicxError::BadRequest(format!(
+            "limit has to be between 1 and {}",
+            MAX_IMPERSONATION_LIMIT
+        )));
+    }
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_IMPERSONATION_AUDIT, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_impersonations(query.user_id, query.limit))
+        .await
+        .map(|records| Json(records.into_iter().map(ImpersonationRecord::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewProject {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Project {
+    id: i32,
+    name: String,
+    #[serde(serialize_with = "timezone::serialize")]
+    created: chrono::NaiveDateTime,
+}
+
+impl From<db::dbo::Project> for Project {
+    fn from(p: db::dbo::Project) -> Self {
+        Project {
+            id: p.id,
+            name: p.name,
+            created: p.created,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectMember {
+    project_id: i32,
+    user_id: i32,
+    role: String,
+}
+
+impl From<db::dbo::ProjectMember> for ProjectMember {
+    fn from(m: db::dbo::ProjectMember) -> Self {
+        ProjectMember {
+            project_id: m.project_id,
+            user_id: m.user_id,
+            role: m.role,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectTeam {
+    project_id: i32,
+    team_id: i32,
+    role: String,
+}
+
+impl From<db::dbo::ProjectTeam> for ProjectTeam {
+    fn from(t: db::dbo::ProjectTeam) -> Self {
+        ProjectTeam {
+            project_id: t.project_id,
+            team_id: t.team_id,
+            role: t.role,
+        }
+    }
+}
+
+#[get("/project")]
+#[tracing::instrument(skip(db))]
+pub async fn list_projects(
+    _auth: Authorized<require::Admin>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<Project>>> {
+    tracing::trace!("requested all projects");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECTS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_projects(&ProjectScope::All))
+        .await
+        .map(|p| Json(p.into_iter().map(Project::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[post("/project")]
+#[tracing::instrument(skip(db))]
+pub async fn create_project(
+    _auth: Authorized<require::Admin>,
+    json: Json<NewProject>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested to create new project");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECTS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.insert_project(&json.into_inner().name))
+        .await
+        .map(|p| HttpResponse::Created().json(Project::from(p)))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[get("/project/{id}/member")]
+#[tracing::instrument(skip(db))]
+pub async fn project_members(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<ProjectMember>>> {
+    tracing::trace!("requested project members");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_MEMBERS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_project_members(id.into_inner()))
+        .await
+        .map(|m| Json(m.into_iter().map(ProjectMember::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Adds the user to the project with given role, or changes the role of existing member.
+#[put("/project/{id}/member/{user_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn set_project_member(
+    _auth: Authorized<require::Admin>,
+    path: web::Path<(i32, i32)>,
+    json: Json<RoleChange>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<ProjectMember>> {
+    tracing::trace!("requested change of project membership");
+    let (project_id, user_id) = path.into_inner();
+    let role = json.into_inner().role;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_MEMBERS, "UPSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.upsert_project_member(project_id, user_id, role.as_str()))
+        .await
+        .map(|m| Json(m.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[delete("/project/{id}/member/{user_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn remove_project_member(
+    _auth: Authorized<require::Admin>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested removal of project member");
+    let (project_id, user_id) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_MEMBERS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_project_member(project_id, user_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[get("/project/{id}/team")]
+#[tracing::instrument(skip(db))]
+pub async fn project_teams(
+    _auth: Authorized<require::Admin>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<ProjectTeam>>> {
+    tracing::trace!("requested project teams");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_TEAMS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_project_teams(id.into_inner()))
+        .await
+        .map(|t| Json(t.into_iter().map(ProjectTeam::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Grants the role in the project to all members of the team, or changes the role already granted.
+#[put("/project/{id}/team/{team_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn set_project_team(
+    _auth: Authorized<require::Admin>,
+    path: web::Path<(i32, i32)>,
+    json: Json<RoleChange>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<ProjectTeam>> {
+    tracing::trace!("requested change of project team grant");
+    let (project_id, team_id) = path.into_inner();
+    let role = json.into_inner().role;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_TEAMS, "UPSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.upsert_project_team(project_id, team_id, role.as_str()))
+        .await
+        .map(|t| Json(t.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[delete("/project/{id}/team/{team_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn remove_project_team(
+    _auth: Authorized<require::Admin>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested removal of project team grant");
+    let (project_id, team_id) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PROJECT_TEAMS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_project_team(project_id, team_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
@@ -1,21 +1,57 @@
 use crate::errors::{TicxError, TicxResult};
 use crate::metrics::*;
+use crate::server::keys::{Jwks, KeySet};
+use crate::server::lockout::LoginLockout;
+use crate::server::mail::{Mail, Mailer};
+use crate::server::mfa::{self, Enrollment, MfaPolicy, Verified};
+use crate::server::oidc::OidcProvider;
+use crate::server::permissions::{Permission, Role};
+use crate::server::revocation::RevocationList;
 use actix_web::{
     dev::Payload,
     get,
     http::HeaderValue,
-    web::{block, Data},
-    FromRequest, HttpRequest,
+    post,
+    web::{block, Data, Json, Query, ReqData},
+    FromRequest, HttpRequest, HttpResponse,
 };
+use db::dbo::Rotation;
 use db::Db;
 use futures::future::{err, ok, Ready};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::fmt::Formatter;
 use std::{convert::TryFrom, str::FromStr, sync::Arc};
 
-pub(crate) const ISS: &'static str = "TicX server";
-pub(crate) const AUD: &'static str = "TicX user";
+pub(crate) const ISS: &str = "TicX server";
+pub(crate) const AUD: &str = "TicX user";
+/// Audience of tokens which only let the user finish login with the second factor, API refuses them.
+const MFA_AUD: &str = "TicX mfa";
 
-pub(crate) struct Secret(pub String);
+/// Validation of tokens issued by this server for `audience`. Algorithm is taken from the key matching `kid`
+/// of the token, see [`KeySet::decode`].
+pub(crate) fn validation(audience: &str) -> jsonwebtoken::Validation {
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.leeway = 10;
+    validation.validate_nbf = true;
+    validation.set_audience(&[audience]);
+    validation.set_issuer(&[ISS]);
+    validation
+}
+
+/// Access tokens are short-lived, clients get a new one with refresh token.
+pub(crate) const ACCESS_TOKEN_LIFETIME: i64 = 15 * 60;
+const REFRESH_TOKEN_LIFETIME: i64 = 30 * 24 * 60 * 60;
+const PASSWORD_RESET_LIFETIME: i64 = 60 * 60;
+/// how long the user has to log in at OpenID Connect provider
+const OIDC_LOGIN_LIFETIME: i64 = 10 * 60;
+/// how long the user has to enter the second factor after the password was accepted
+const MFA_TOKEN_LIFETIME: i64 = 5 * 60;
+/// Impersonation tokens cannot be refreshed, support looks around and gets a new one if needed.
+pub(crate) const IMPERSONATION_TOKEN_LIFETIME: i64 = 10 * 60;
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Personal access tokens are told apart from JWTs by this prefix.
+pub(crate) const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "ticx_pat_";
 
 pub(crate) struct Credentials(http_auth_basic::Credentials);
 impl Credentials {
@@ -73,7 +109,7 @@ impl FromRequest for Credentials {
             .headers()
             .get(actix_web::http::header::AUTHORIZATION)
             .ok_or(TicxError::MissingAuthHeader)
-            .and_then(|header_value| Credentials::try_from(header_value))
+            .and_then(Credentials::try_from)
         {
             Ok(creds) => ok(creds),
             Err(e) => err(e),
@@ -81,67 +117,893 @@ impl FromRequest for Credentials {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Claims {
-    iss: &'static str,
-    aud: &'static str,
-    sub: String,
-    iat: i64,
-    exp: i64,
-    nbf: i64,
-    jti: String,
+/// Claims of tokens issued by [`login`]. Once validated by `JWTValidationMiddleware` they are stored
+/// in request extensions.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Claims {
+    pub iss: String,
+    pub aud: String,
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub nbf: i64,
+    pub jti: String,
+    pub role: Role,
+    /// Session the token was issued for, tokens of revoked session are refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i32>,
+    /// Admin acting as the subject, set only for tokens issued by `admin::impersonate` (RFC 8693 `act` claim).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// Permissions the request is limited to, set only for personal access tokens which are not JWTs.
+    #[serde(skip)]
+    pub scopes: Option<Vec<Permission>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Actor {
+    pub sub: String,
 }
 
 impl Claims {
-    fn new(sub: String) -> Self {
+    fn new(sub: String, role: Role, sid: Option<i32>) -> Self {
         let timestamp = chrono::Local::now().timestamp();
-        let exp = timestamp + (7 * 24 * 60 * 60);
+        let exp = timestamp + ACCESS_TOKEN_LIFETIME;
 
         Claims {
-            iss: ISS,
-            aud: AUD,
+            iss: ISS.into(),
+            aud: AUD.into(),
             sub,
             iat: timestamp,
             exp,
             nbf: timestamp,
-            jti: "Some constant random ID".to_string(),
+            jti: random_token(16),
+            role,
+            sid,
+            act: None,
+            scopes: None,
         }
     }
+
+    /// Claims of short-lived token letting admin `actor_id` act as the user, it belongs to the admin's session
+    /// so it is revoked with it.
+    fn impersonation(sub: String, role: Role, actor_id: i32, sid: Option<i32>) -> Self {
+        let timestamp = chrono::Local::now().timestamp();
+        Claims {
+            exp: timestamp + IMPERSONATION_TOKEN_LIFETIME,
+            act: Some(Actor {
+                sub: actor_id.to_string(),
+            }),
+            ..Claims::new(sub, role, sid)
+        }
+    }
+
+    /// Claims of token which can only be exchanged for access token once second factor is verified.
+    fn mfa_pending(sub: String, role: Role) -> Self {
+        let timestamp = chrono::Local::now().timestamp();
+        Claims {
+            aud: MFA_AUD.into(),
+            exp: timestamp + MFA_TOKEN_LIFETIME,
+            ..Claims::new(sub, role, None)
+        }
+    }
+
+    /// Claims of a request authorized by personal access token, role is the current role of its owner.
+    pub fn personal(
+        token: &db::dbo::PersonalAccessToken,
+        owner: &db::dbo::User,
+    ) -> TicxResult<Self> {
+        let scopes = token
+            .scopes
+            .iter()
+            .map(|s| s.parse::<Permission>())
+            .collect::<TicxResult<Vec<_>>>()?;
+
+        Ok(Claims {
+            iss: ISS.into(),
+            aud: AUD.into(),
+            sub: owner.id().to_string(),
+            iat: token.created.timestamp(),
+            exp: token.expires.timestamp(),
+            nbf: token.created.timestamp(),
+            jti: format!("pat-{}", token.id),
+            role: owner.role.parse::<Role>()?,
+            sid: None,
+            act: None,
+            scopes: Some(scopes),
+        })
+    }
+
+    pub fn user_id(&self) -> TicxResult<i32> {
+        self.sub
+            .parse::<i32>()
+            .map_err(|e| TicxError::InvalidToken(format!("subject is not user id: {}", e)))
+    }
+
+    /// Admin impersonating the subject, `None` for tokens of the user themselves.
+    pub fn actor_id(&self) -> TicxResult<Option<i32>> {
+        self.act
+            .as_ref()
+            .map(|act| {
+                act.sub
+                    .parse::<i32>()
+                    .map_err(|e| TicxError::InvalidToken(format!("actor is not user id: {}", e)))
+            })
+            .transpose()
+    }
 }
 
-#[get("/login")]
-#[tracing::instrument(skip(db, secret))]
+/// Refuses deactivated user, basic auth login refuses them in `BasicAuthMiddleware` already.
+fn ensure_active(user: &db::dbo::User) -> TicxResult<()> {
+    if !user.active {
+        tracing::warn!(user_id = user.id, "login of deactivated user refused");
+        return Err(TicxError::Forbidden("user is deactivated".into()));
+    }
+    Ok(())
+}
+
+/// Hex encoded random bytes, used for token ids and as refresh tokens.
+pub(crate) fn random_token(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+/// New passwords are checked only for length, anything else is up to the user.
+pub(crate) fn validate_password(password: &str) -> TicxResult<()> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(TicxError::BadRequest(format!(
+            "password has to be at least {} characters long",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn refresh_token_expiration() -> chrono::NaiveDateTime {
+    chrono::Utc::now().naive_utc() + chrono::Duration::seconds(REFRESH_TOKEN_LIFETIME)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    /// lifetime of the access token in seconds
+    pub expires_in: i64,
+}
+
+impl TokenPair {
+    fn new(access_token: String, refresh_token: String) -> Self {
+        TokenPair {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: ACCESS_TOKEN_LIFETIME,
+        }
+    }
+}
+
+/// Mints JWT for given user and session, role is taken from the user as it is stored in the DB.
+pub(crate) fn issue_token(
+    keys: &KeySet,
+    user: &db::dbo::User,
+    session_id: Option<i32>,
+) -> TicxResult<String> {
+    let role = user.role.parse::<Role>()?;
+    keys.encode(&Claims::new(user.id().to_string(), role, session_id))
+}
+
+/// Mints JWT letting admin `actor_id` act as `user`, see [`Claims::impersonation`].
+pub(crate) fn issue_impersonation_token(
+    keys: &KeySet,
+    user: &db::dbo::User,
+    actor_id: i32,
+    session_id: Option<i32>,
+) -> TicxResult<String> {
+    let role = user.role.parse::<Role>()?;
+    keys.encode(&Claims::impersonation(
+        user.id().to_string(),
+        role,
+        actor_id,
+        session_id,
+    ))
+}
+
+/// Where the login comes from, recorded with its session.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Client {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Client {
+    pub fn of(req: &HttpRequest) -> Self {
+        Client {
+            user_agent: req
+                .headers()
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            // peer address for the same reason as the lockout uses it, forwarded headers can be forged
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+/// Starts session of the user with `refresh_token` as its first refresh token.
+fn start_session(
+    db: &Db,
+    user_id: i32,
+    refresh_token: &str,
+    client: &Client,
+) -> db::errors::DbResult<db::dbo::Session> {
+    db.start_session(
+        user_id,
+        refresh_token,
+        &random_token(16),
+        client.user_agent.as_deref(),
+        client.ip.as_deref(),
+        refresh_token_expiration(),
+    )
+}
+
+/// Public keys tokens can be verified with, HMAC secrets are never published.
+#[get("/.well-known/jwks.json")]
+#[tracing::instrument(skip(keys))]
+pub(crate) async fn jwks(keys: Data<Arc<KeySet>>) -> Json<Jwks> {
+    Json(keys.jwks())
+}
+
+/// User whose credentials were accepted by `BasicAuthMiddleware`, stored in request extensions.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Authenticated {
+    pub user_id: i32,
+}
+
+/// Response of [`login`], tokens are issued right away only to users without two-factor authentication.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenPair),
+    MfaRequired(MfaChallenge),
+}
+
+/// Password was accepted, `mfa_token` is exchanged for [`TokenPair`] at [`verify_mfa`] with TOTP code.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_token: String,
+    pub token_type: String,
+    /// lifetime of the MFA token in seconds
+    pub expires_in: i64,
+    /// user has to enroll at [`enroll_mfa`] first, two-factor authentication is required for them
+    pub enrollment_required: bool,
+}
+
+/// What [`login`] ends with once the password is accepted.
+enum LoginOutcome {
+    Session(i32),
+    MfaRequired { enrollment_required: bool },
+}
+
+/// Issues access token together with refresh token which starts a new session. Users with two-factor
+/// authentication, and admins without it when it is required for them, get just a short-lived MFA token.
+#[get("")]
+#[tracing::instrument(skip(req, db, keys, policy))]
 pub(crate) async fn login(
-    credentials: Option<Credentials>,
+    req: HttpRequest,
+    authenticated: ReqData<Authenticated>,
     db: Data<Arc<Db>>,
-    secret: Data<Arc<Secret>>,
-) -> TicxResult<String> {
-    let span = tracing::span::Span::current();
-    let credentials = credentials.unwrap(); // we can do this since credentials are validated by middleware, if we panic here it means there is bug in middleware
+    keys: Data<Arc<KeySet>>,
+    policy: Data<MfaPolicy>,
+) -> TicxResult<Json<LoginResponse>> {
+    let user_id = authenticated.user_id;
+    let policy = *policy.get_ref();
+    let client = Client::of(&req);
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_USERS, "SELECT"])
         .start_timer();
 
-    let user = block(move || {
-        let _guard = span.enter();
-        db.check_credentials(credentials.username(), credentials.password())
+    let refresh_token = random_token(32);
+    let stored_token = refresh_token.clone();
+    let result = block(move || -> TicxResult<(db::dbo::User, LoginOutcome)> {
+        let user = db.select_user(user_id)?;
+        let outcome = start_login(&db, &user, policy, &stored_token, &client)?;
+        Ok((user, outcome))
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    let (user, outcome) = result?;
+    login_response(&keys, &user, outcome, refresh_token).map(Json)
+}
+
+/// Starts session of `user` whose first factor was accepted, unless the second one is required from them.
+fn start_login(
+    db: &Db,
+    user: &db::dbo::User,
+    policy: MfaPolicy,
+    refresh_token: &str,
+    client: &Client,
+) -> TicxResult<LoginOutcome> {
+    let enrolled = db
+        .select_totp(user.id)?
+        .is_some_and(|totp| totp.confirmed.is_some());
+    if enrolled {
+        return Ok(LoginOutcome::MfaRequired {
+            enrollment_required: false,
+        });
+    }
+    if policy.required_for_admins && user.role.parse::<Role>()? == Role::Admin {
+        return Ok(LoginOutcome::MfaRequired {
+            enrollment_required: true,
+        });
+    }
+
+    let session = start_session(db, user.id, refresh_token, client)?;
+    Ok(LoginOutcome::Session(session.id))
+}
+
+/// Tokens of the session [`start_login`] started, or MFA token the login is finished with.
+fn login_response(
+    keys: &KeySet,
+    user: &db::dbo::User,
+    outcome: LoginOutcome,
+    refresh_token: String,
+) -> TicxResult<LoginResponse> {
+    match outcome {
+        LoginOutcome::Session(session_id) => Ok(LoginResponse::Tokens(TokenPair::new(
+            issue_token(keys, user, Some(session_id))?,
+            refresh_token,
+        ))),
+        LoginOutcome::MfaRequired {
+            enrollment_required,
+        } => {
+            tracing::debug!(
+                user_id = user.id,
+                enrollment_required,
+                "second factor required"
+            );
+            let role = user.role.parse::<Role>()?;
+            Ok(LoginResponse::MfaRequired(MfaChallenge {
+                mfa_token: keys.encode(&Claims::mfa_pending(user.id().to_string(), role))?,
+                token_type: "mfa".into(),
+                expires_in: MFA_TOKEN_LIFETIME,
+                enrollment_required,
+            }))
+        }
+    }
+}
+
+/// Claims of MFA token issued by [`login`].
+fn mfa_claims(keys: &KeySet, mfa_token: &str) -> TicxResult<Claims> {
+    keys.decode::<Claims>(mfa_token, validation(MFA_AUD))
+}
+
+#[derive(Deserialize)]
+pub struct MfaEnrollmentRequest {
+    mfa_token: String,
+}
+
+impl std::fmt::Debug for MfaEnrollmentRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MfaEnrollmentRequest")
+            .field("mfa_token", &"censored")
+            .finish()
+    }
+}
+
+/// Enrollment during login of user who is required to have two-factor authentication but has none yet,
+/// it is confirmed by the first code given to [`verify_mfa`].
+#[post("/enroll")]
+#[tracing::instrument(skip(db, keys))]
+pub(crate) async fn enroll_mfa(
+    json: Json<MfaEnrollmentRequest>,
+    db: Data<Arc<Db>>,
+    keys: Data<Arc<KeySet>>,
+) -> TicxResult<Json<Enrollment>> {
+    let user_id = mfa_claims(&keys, &json.mfa_token)?.user_id()?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_TOTP, "INSERT"])
+        .start_timer();
+
+    let result = block(move || -> TicxResult<Enrollment> {
+        let user = db.select_user(user_id)?;
+        mfa::enroll(&db, &user)
     })
     .await
-    .unwrap(); // same as above
+    .map(Json)
+    .map_err(TicxError::from);
 
     timer.observe_duration();
+    result
+}
+
+#[derive(Deserialize)]
+pub struct MfaVerification {
+    mfa_token: String,
+    /// TOTP code or one of the recovery codes
+    code: String,
+}
+
+impl std::fmt::Debug for MfaVerification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MfaVerification")
+            .field("mfa_token", &"censored")
+            .field("code", &"censored")
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaTokens {
+    #[serde(flatten)]
+    pub tokens: TokenPair,
+    /// returned only when the code confirmed enrollment started at [`enroll_mfa`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
 
-    jsonwebtoken::encode(
-        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS512),
-        &Claims::new(user.id().to_string()),
-        &jsonwebtoken::EncodingKey::from_secret(secret.0.as_bytes()),
+/// Exchanges MFA token for the usual pair of tokens. Wrong codes count as failed logins of the user,
+/// the MFA token cannot be used again once it is exchanged.
+#[post("/verify")]
+#[tracing::instrument(skip(req, db, keys, revocations, lockout))]
+pub(crate) async fn verify_mfa(
+    req: HttpRequest,
+    json: Json<MfaVerification>,
+    db: Data<Arc<Db>>,
+    keys: Data<Arc<KeySet>>,
+    revocations: Data<Arc<RevocationList>>,
+    lockout: Data<LoginLockout>,
+) -> TicxResult<Json<MfaTokens>> {
+    let verification = json.into_inner();
+    let claims = mfa_claims(&keys, &verification.mfa_token)?;
+    let user_id = claims.user_id()?;
+    let client = Client::of(&req);
+    let lockout = *lockout.get_ref();
+    let revocations = revocations.get_ref().clone();
+    let span = tracing::span::Span::current();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_TOTP, "UPDATE"])
+        .start_timer();
+
+    let refresh_token = random_token(32);
+    let stored_token = refresh_token.clone();
+    let result = block(
+        move || -> TicxResult<(db::dbo::User, i32, Option<Vec<String>>)> {
+            let _guard = span.enter();
+            let ip = client.ip.as_deref();
+            if revocations.is_revoked(&claims.jti, claims.exp)? {
+                return Err(TicxError::InvalidToken("MFA token was already used".into()));
+            }
+            let user = db.select_user(user_id)?;
+            ensure_active(&user)?;
+            lockout.check(&db, &user.username, ip)?;
+
+            let verified = match mfa::verify_code(&db, user.id, &verification.code) {
+                Ok(verified) => verified,
+                Err(err @ TicxError::InvalidToken(_)) => {
+                    lockout.record_failure(&db, &user.username, ip)?;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            };
+            if let Err(err) = lockout.record_success(&db, &user.username) {
+                tracing::error!(%err, "failed to clear failed logins");
+            }
+            revocations.revoke(&claims.jti, claims.exp)?;
+
+            let session = start_session(&db, user.id, &stored_token, &client)?;
+            tracing::info!(user_id = user.id, ?verified, "second factor verified");
+            match verified {
+                Verified::Enrolled(codes) => Ok((user, session.id, Some(codes))),
+                Verified::Code | Verified::RecoveryCode => Ok((user, session.id, None)),
+            }
+        },
     )
-    .map_err(|err| {
-        tracing::trace!(%err, "failed to encode JWT token");
-        TicxError::GenericError {
-            what: "encode JWT token",
-            error: err.to_string(),
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    let (user, session_id, recovery_codes) = result?;
+    Ok(Json(MfaTokens {
+        tokens: TokenPair::new(issue_token(&keys, &user, Some(session_id))?, refresh_token),
+        recovery_codes,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+impl std::fmt::Debug for RefreshRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshRequest")
+            .field("refresh_token", &"censored")
+            .finish()
+    }
+}
+
+/// Exchanges refresh token for a new pair of tokens, the presented refresh token cannot be used again.
+#[post("/refresh")]
+#[tracing::instrument(skip(db, keys))]
+pub(crate) async fn refresh(
+    json: Json<RefreshRequest>,
+    db: Data<Arc<Db>>,
+    keys: Data<Arc<KeySet>>,
+) -> TicxResult<Json<TokenPair>> {
+    let token = json.into_inner().refresh_token;
+    let refresh_token = random_token(32);
+    let stored_token = refresh_token.clone();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_REFRESH_TOKENS, "UPDATE"])
+        .start_timer();
+
+    let rotated = block(move || -> TicxResult<(db::dbo::User, Option<i32>)> {
+        match db.rotate_refresh_token(&token, &stored_token, refresh_token_expiration()) {
+            Ok(Rotation::Rotated(rotated, session_id)) => {
+                let user = db.select_user(rotated.user_id)?;
+                ensure_active(&user)?;
+                Ok((user, session_id))
+            }
+            Ok(Rotation::Reused) => Err(TicxError::InvalidToken(
+                "refresh token was already used".into(),
+            )),
+            Ok(Rotation::Expired) => {
+                Err(TicxError::InvalidToken("refresh token has expired".into()))
+            }
+            Err(db::errors::DbError::NotFound(_)) => {
+                Err(TicxError::InvalidToken("unknown refresh token".into()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    let (user, session_id) = rotated?;
+    Ok(Json(TokenPair::new(
+        issue_token(&keys, &user, session_id)?,
+        refresh_token,
+    )))
+}
+
+/// User the request is made by, taken from claims `JWTValidationMiddleware` stored in request extensions.
+/// Handlers take it instead of trusting user ids sent by the client.
+#[derive(Debug, Clone)]
+pub(crate) struct CurrentUser {
+    pub id: i32,
+    pub role: Role,
+    /// session of the access token, `None` for personal access tokens
+    pub session_id: Option<i32>,
+    /// scopes the request is limited to when it is made with personal access token
+    pub scopes: Option<Vec<Permission>>,
+    /// admin impersonating the user, see `admin::impersonate`
+    pub actor_id: Option<i32>,
+}
+
+impl TryFrom<&Claims> for CurrentUser {
+    type Error = TicxError;
+
+    fn try_from(claims: &Claims) -> Result<Self, Self::Error> {
+        Ok(CurrentUser {
+            id: claims.user_id()?,
+            role: claims.role,
+            session_id: claims.sid,
+            scopes: claims.scopes.clone(),
+            actor_id: claims.actor_id()?,
+        })
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = TicxError;
+    type Future = Ready<TicxResult<CurrentUser>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        match req
+            .extensions()
+            .get::<Claims>()
+            .ok_or_else(|| {
+                tracing::error!("no validated claims found, route is not behind JWT validation");
+                TicxError::InvalidToken("no validated claims".into())
+            })
+            .and_then(CurrentUser::try_from)
+        {
+            Ok(user) => ok(user),
+            Err(e) => err(e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    refresh_token: Option<String>,
+}
+
+impl std::fmt::Debug for LogoutRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogoutRequest")
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| "censored"),
+            )
+            .finish()
+    }
+}
+
+/// Ends session of the access token the request was made with, the token itself and refresh tokens
+/// of the session are revoked. Refresh token given in the body is revoked too, tokens issued before
+/// sessions were recorded belong to none.
+#[post("")]
+#[tracing::instrument(skip(db, revocations))]
+pub(crate) async fn logout(
+    claims: ReqData<Claims>,
+    json: Option<Json<LogoutRequest>>,
+    db: Data<Arc<Db>>,
+    revocations: Data<Arc<RevocationList>>,
+) -> TicxResult<HttpResponse> {
+    let claims = claims.into_inner();
+    let user_id = claims.user_id()?;
+    let refresh_token = json.and_then(|j| j.into_inner().refresh_token);
+
+    let revocations = revocations.get_ref().clone();
+    block(move || -> TicxResult<()> {
+        revocations.revoke(&claims.jti, claims.exp)?;
+        if let Some(session_id) = claims.sid {
+            match revocations.revoke_session(user_id, session_id) {
+                // revoked already, e.g. from another device
+                Ok(()) | Err(TicxError::NotFound(_)) => (),
+                Err(e) => return Err(e),
+            }
         }
+        if let Some(token) = refresh_token {
+            db.revoke_refresh_token(user_id, &token)?;
+        }
+        Ok(())
     })
+    .await
+    .map_err(TicxError::from)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+/// Sends password reset link to the user with given email. Response is the same whether such user exists
+/// or not, so it cannot be used to find out who has an account.
+#[post("/password-reset")]
+#[tracing::instrument(skip(db, mailer))]
+pub(crate) async fn request_password_reset(
+    json: Json<PasswordResetRequest>,
+    db: Data<Arc<Db>>,
+    mailer: Data<Arc<Mailer>>,
+) -> TicxResult<HttpResponse> {
+    let email = json.into_inner().email;
+    let span = tracing::span::Span::current();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PASSWORD_RESETS, "INSERT"])
+        .start_timer();
+
+    let result = block(move || -> TicxResult<()> {
+        let _guard = span.enter();
+        let user = match db.select_user_by_email(&email) {
+            Ok(user) if !user.active => {
+                tracing::debug!(
+                    user_id = user.id,
+                    "password reset requested for deactivated user"
+                );
+                return Ok(());
+            }
+            Ok(user) => user,
+            Err(db::errors::DbError::NotFound(_)) => {
+                tracing::debug!("password reset requested for unknown email");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let token = random_token(32);
+        db.insert_password_reset(
+            user.id,
+            &token,
+            chrono::Utc::now().naive_utc() + chrono::Duration::seconds(PASSWORD_RESET_LIFETIME),
+        )?;
+
+        mailer.send(Mail {
+            to: email,
+            subject: "TicX password reset".into(),
+            body: format!(
+                "Hi {},\n\nto set a new password of your TicX account '{}' open {}\n\
+                The link is valid for {} minutes and can be used only once. \
+                If you did not ask for a password reset, just ignore this mail.",
+                user.firstname,
+                user.username,
+                mailer.link(&format!("/reset-password?token={}", token)),
+                PASSWORD_RESET_LIFETIME / 60,
+            ),
+        })
+    })
+    .await;
+
+    timer.observe_duration();
+
+    if let Err(err) = result.map_err(TicxError::from) {
+        // failure is not reported to the client, it would tell that the email belongs to someone
+        tracing::error!(%err, "failed to send password reset");
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirmation {
+    token: String,
+    password: String,
+}
+
+impl std::fmt::Debug for PasswordResetConfirmation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordResetConfirmation")
+            .field("token", &"censored")
+            .field("password", &"censored")
+            .finish()
+    }
+}
+
+/// Sets new password with token from the reset mail, all refresh tokens of the user are revoked.
+#[post("/password-reset/confirm")]
+#[tracing::instrument(skip(db))]
+pub(crate) async fn confirm_password_reset(
+    json: Json<PasswordResetConfirmation>,
+    db: Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let confirmation = json.into_inner();
+    validate_password(&confirmation.password)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PASSWORD_RESETS, "UPDATE"])
+        .start_timer();
+
+    let result = block(move || db.reset_password(&confirmation.token, &confirmation.password))
+        .await
+        .map_err(|err| match err {
+            actix_web::error::BlockingError::Error(db::errors::DbError::NotFound(_)) => {
+                TicxError::InvalidToken("password reset token is invalid or expired".into())
+            }
+            err => err.into(),
+        });
+
+    timer.observe_duration();
+
+    result.map(|user| {
+        tracing::info!(user_id = user.id, "password reset");
+        HttpResponse::Ok().finish()
+    })
+}
+
+/// Starts OpenID Connect login by redirecting to the provider, which sends the user back to [`oidc_callback`].
+#[get("/login")]
+#[tracing::instrument(skip(db, oidc))]
+pub(crate) async fn oidc_login(
+    db: Data<Arc<Db>>,
+    oidc: Data<Arc<OidcProvider>>,
+) -> TicxResult<HttpResponse> {
+    let state = random_token(16);
+    let nonce = random_token(16);
+    let code_verifier = random_token(32);
+    let expires = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(OIDC_LOGIN_LIFETIME);
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_OIDC_LOGINS, "INSERT"])
+        .start_timer();
+
+    let result = block(move || -> TicxResult<String> {
+        db.insert_oidc_login(&state, &nonce, &code_verifier, expires)?;
+        oidc.authorization_url(&state, &nonce, &code_verifier)
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    Ok(HttpResponse::Found()
+        .header(actix_web::http::header::LOCATION, result?)
+        .finish())
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallback {
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+impl std::fmt::Debug for OidcCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OidcCallback")
+            .field("state", &"censored")
+            .field("code", &self.code.as_ref().map(|_| "censored"))
+            .field("error", &self.error)
+            .field("error_description", &self.error_description)
+            .finish()
+    }
+}
+
+/// Finishes OpenID Connect login the same way as [`login`] does, second factor is required by the same rules.
+/// User is linked by the identity from ID token, on the first login to existing user with the same verified
+/// email or to a newly created one.
+#[get("/callback")]
+#[tracing::instrument(skip(req, db, keys, oidc, policy))]
+pub(crate) async fn oidc_callback(
+    req: HttpRequest,
+    query: Query<OidcCallback>,
+    db: Data<Arc<Db>>,
+    keys: Data<Arc<KeySet>>,
+    oidc: Data<Arc<OidcProvider>>,
+    policy: Data<MfaPolicy>,
+) -> TicxResult<Json<LoginResponse>> {
+    let callback = query.into_inner();
+    if let Some(error) = callback.error {
+        return Err(TicxError::InvalidToken(format!(
+            "identity provider refused the login: {} {}",
+            error,
+            callback.error_description.unwrap_or_default()
+        )));
+    }
+    let code = callback
+        .code
+        .ok_or_else(|| TicxError::BadRequest("authorization code is missing".into()))?;
+    let client = Client::of(&req);
+    let policy = *policy.get_ref();
+    let span = tracing::span::Span::current();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_IDENTITIES, "UPSERT"])
+        .start_timer();
+
+    let refresh_token = random_token(32);
+    let stored_token = refresh_token.clone();
+    let logged_in = block(move || -> TicxResult<(db::dbo::User, LoginOutcome)> {
+        let _guard = span.enter();
+        let (nonce, code_verifier) = match db.take_oidc_login(&callback.state) {
+            Ok(pending) => pending,
+            Err(db::errors::DbError::NotFound(_)) => {
+                return Err(TicxError::InvalidToken(
+                    "login state is unknown or expired".into(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let claims = oidc.exchange_code(&code, &code_verifier, &nonce)?;
+        let user = db.link_identity(&claims.iss, &claims.sub, claims.new_user(), true)?;
+        ensure_active(&user)?;
+        let outcome = start_login(&db, &user, policy, &stored_token, &client)?;
+        tracing::info!(user_id = user.id, "logged in through OpenID Connect");
+        Ok((user, outcome))
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    let (user, outcome) = logged_in?;
+    login_response(&keys, &user, outcome, refresh_token).map(Json)
 }
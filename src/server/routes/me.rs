@@ -0,0 +1,632 @@
+use super::auth::{random_token, validate_password, CurrentUser, PERSONAL_ACCESS_TOKEN_PREFIX};
+use crate::errors::{TicxError, TicxResult};
+use crate::metrics::*;
+use crate::server::avatar;
+use crate::server::mail::Mailer;
+use crate::server::mfa::{self, Enrollment, MfaPolicy, Verified};
+use crate::server::permissions::{Permission, Role};
+use crate::server::registration;
+use crate::server::revocation::RevocationList;
+use crate::server::timezone;
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use db::Db;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const DEFAULT_TOKEN_LIFETIME_DAYS: i64 = 90;
+const MAX_TOKEN_LIFETIME_DAYS: i64 = 365;
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    old_password: String,
+    new_password: String,
+}
+
+impl std::fmt::Debug for PasswordChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordChange")
+            .field("old_password", &"*censored*")
+            .field("new_password", &"*censored*")
+            .finish()
+    }
+}
+
+/// Changes password of the logged in user, other logins of the user have to log in again once their
+/// access token expires.
+#[post("/password")]
+#[tracing::instrument(skip(db))]
+pub async fn change_password(
+    current_user: CurrentUser,
+    json: web::Json<PasswordChange>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = current_user.id;
+    let change = json.into_inner();
+    validate_password(&change.new_password)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "UPDATE"])
+        .start_timer();
+
+    let result =
+        web::block(move || db.change_password(user_id, &change.old_password, &change.new_password))
+            .await
+            .map(|_| HttpResponse::Ok().finish())
+            .map_err(|err| match err {
+                actix_web::error::BlockingError::Error(db::errors::DbError::NotFound(_)) => {
+                    TicxError::InvalidCredentials
+                }
+                err => err.into(),
+            });
+
+    timer.observe_duration();
+    result
+}
+
+fn default_token_lifetime_days() -> i64 {
+    DEFAULT_TOKEN_LIFETIME_DAYS
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPersonalAccessToken {
+    name: String,
+    /// e.g. `ticket:read`, see [`Permission::scope`]
+    scopes: Vec<String>,
+    #[serde(default = "default_token_lifetime_days")]
+    expires_in_days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub expires: chrono::NaiveDateTime,
+    #[serde(serialize_with = "timezone::serialize_option")]
+    pub last_used: Option<chrono::NaiveDateTime>,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub created: chrono::NaiveDateTime,
+    /// the token itself, returned only when it is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl From<db::dbo::PersonalAccessToken> for PersonalAccessToken {
+    fn from(t: db::dbo::PersonalAccessToken) -> Self {
+        PersonalAccessToken {
+            id: t.id,
+            name: t.name,
+            scopes: t.scopes,
+            expires: t.expires,
+            last_used: t.last_used,
+            created: t.created,
+            token: None,
+        }
+    }
+}
+
+/// Personal access tokens are managed only with regular login, token could extend its own access otherwise.
+/// Neither can admin impersonating the user, the access has to end with the impersonation token.
+fn token_manager(current_user: &CurrentUser) -> TicxResult<i32> {
+    if current_user.scopes.is_some() {
+        return Err(TicxError::Forbidden(
+            "personal access tokens cannot manage personal access tokens".into(),
+        ));
+    }
+    if current_user.actor_id.is_some() {
+        return Err(TicxError::Forbidden(
+            "impersonation token cannot manage credentials of the user".into(),
+        ));
+    }
+    Ok(current_user.id)
+}
+
+#[get("/tokens")]
+#[tracing::instrument(skip(db))]
+pub async fn tokens(
+    current_user: CurrentUser,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<web::Json<Vec<PersonalAccessToken>>> {
+    let user_id = token_manager(&current_user)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PERSONAL_ACCESS_TOKENS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_personal_access_tokens(user_id))
+        .await
+        .map(|tokens| web::Json(tokens.into_iter().map(PersonalAccessToken::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Creates personal access token, the response is the only time the token is shown.
+#[post("/tokens")]
+#[tracing::instrument(skip(db))]
+pub async fn create_token(
+    current_user: CurrentUser,
+    json: web::Json<NewPersonalAccessToken>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = token_manager(&current_user)?;
+    let new_token = json.into_inner();
+
+    if new_token.name.trim().is_empty() {
+        return Err(TicxError::BadRequest("token name cannot be empty".into()));
+    }
+    if !(1..=MAX_TOKEN_LIFETIME_DAYS).contains(&new_token.expires_in_days) {
+        return Err(TicxError::BadRequest(format!(
+            "token has to expire in 1 to {} days",
+            MAX_TOKEN_LIFETIME_DAYS
+        )));
+    }
+    if new_token.scopes.is_empty() {
+        return Err(TicxError::BadRequest(
+            "token needs at least one scope".into(),
+        ));
+    }
+    for scope in &new_token.scopes {
+        let permission = scope.parse::<Permission>()?;
+        // token with such scope would be refused by every route anyway
+        if !current_user.role.has(permission) {
+            return Err(TicxError::BadRequest(format!(
+                "role '{}' does not grant scope '{}'",
+                current_user.role.as_str(),
+                scope
+            )));
+        }
+    }
+
+    let token = format!("{}{}", PERSONAL_ACCESS_TOKEN_PREFIX, random_token(32));
+    let expires =
+        chrono::Utc::now().naive_utc() + chrono::Duration::days(new_token.expires_in_days);
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PERSONAL_ACCESS_TOKENS, "INSERT"])
+        .start_timer();
+
+    let stored_token = token.clone();
+    let result = web::block(move || {
+        db.insert_personal_access_token(
+            user_id,
+            new_token.name.trim(),
+            &stored_token,
+            &new_token.scopes,
+            expires,
+        )
+    })
+    .await
+    .map(|created| {
+        HttpResponse::Created().json(PersonalAccessToken {
+            token: Some(token),
+            ..created.into()
+        })
+    })
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+#[delete("/tokens/{id}")]
+#[tracing::instrument(skip(db))]
+pub async fn delete_token(
+    current_user: CurrentUser,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = token_manager(&current_user)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_PERSONAL_ACCESS_TOKENS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_personal_access_token(user_id, id.into_inner()))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Starts two-factor authentication of the logged in user, it is used once confirmed by the first code.
+#[post("/mfa")]
+#[tracing::instrument(skip(db))]
+pub async fn enroll_mfa(
+    current_user: CurrentUser,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<web::Json<Enrollment>> {
+    let user_id = token_manager(&current_user)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_TOTP, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Enrollment> {
+        let user = db.select_user(user_id)?;
+        mfa::enroll(&db, &user)
+    })
+    .await
+    .map(web::Json)
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+#[derive(Deserialize)]
+pub struct MfaCode {
+    code: String,
+}
+
+impl std::fmt::Debug for MfaCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MfaCode")
+            .field("code", &"*censored*")
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    /// shown only this once, each of them can be used instead of TOTP code one time
+    pub recovery_codes: Vec<String>,
+}
+
+/// Confirms enrollment started by [`enroll_mfa`] with code from the authenticator app.
+#[post("/mfa/confirm")]
+#[tracing::instrument(skip(db))]
+pub async fn confirm_mfa(
+    current_user: CurrentUser,
+    json: web::Json<MfaCode>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<web::Json<RecoveryCodes>> {
+    let user_id = token_manager(&current_user)?;
+    let code = json.into_inner().code;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_TOTP, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || mfa::verify_code(&db, user_id, &code))
+        .await
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    match result? {
+        Verified::Enrolled(recovery_codes) => Ok(web::Json(RecoveryCodes { recovery_codes })),
+        Verified::Code | Verified::RecoveryCode => Err(TicxError::BadRequest(
+            "two-factor authentication is already enabled".into(),
+        )),
+    }
+}
+
+/// Turns two-factor authentication off, current code is required so stolen access token is not enough.
+/// Admins cannot turn it off when it is required for them.
+#[delete("/mfa")]
+#[tracing::instrument(skip(db, policy))]
+pub async fn disable_mfa(
+    current_user: CurrentUser,
+    json: web::Json<MfaCode>,
+    db: web::Data<Arc<Db>>,
+    policy: web::Data<MfaPolicy>,
+) -> TicxResult<HttpResponse> {
+    let user_id = token_manager(&current_user)?;
+    if policy.required_for_admins && current_user.role == Role::Admin {
+        return Err(TicxError::Forbidden(
+            "two-factor authentication is required for admins".into(),
+        ));
+    }
+    let code = json.into_inner().code;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_TOTP, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<()> {
+        // code of enrollment which was not confirmed yet confirms it first, it is deleted right away anyway
+        mfa::verify_code(&db, user_id, &code)?;
+        Ok(db.delete_totp(user_id)?)
+    })
+    .await
+    .map(|_| {
+        tracing::info!(user_id, "two-factor authentication disabled");
+        HttpResponse::Ok().finish()
+    })
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub created: chrono::NaiveDateTime,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub last_used: chrono::NaiveDateTime,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub expires: chrono::NaiveDateTime,
+    /// session of the token the request was made with
+    pub current: bool,
+}
+
+/// Logins of the user which were neither logged out nor revoked and did not expire.
+#[get("/sessions")]
+#[tracing::instrument(skip(db))]
+pub async fn sessions(
+    current_user: CurrentUser,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<web::Json<Vec<Session>>> {
+    let user_id = token_manager(&current_user)?;
+    let current = current_user.session_id;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_SESSIONS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_active_sessions(user_id))
+        .await
+        .map(|active| {
+            web::Json(
+                active
+                    .into_iter()
+                    .map(|s| Session {
+                        current: Some(s.id) == current,
+                        id: s.id,
+                        user_agent: s.user_agent,
+                        ip: s.ip,
+                        created: s.created,
+                        last_used: s.last_used,
+                        expires: s.expires,
+                    })
+                    .collect(),
+            )
+        })
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Revokes session of the user, its refresh token cannot be used anymore and its access tokens are refused.
+#[delete("/sessions/{id}")]
+#[tracing::instrument(skip(revocations))]
+pub async fn revoke_session(
+    current_user: CurrentUser,
+    id: web::Path<i32>,
+    revocations: web::Data<Arc<RevocationList>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = token_manager(&current_user)?;
+    let revocations = revocations.get_ref().clone();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_SESSIONS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || revocations.revoke_session(user_id, id.into_inner()))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+lazy_static::lazy_static! {
+    // BCP 47 language tag, e.g. `en`, `en-GB` or `zh-Hant-TW`
+    static ref LOCALE: regex::Regex = regex::Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").unwrap();
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    pub firstname: String,
+    pub lastname: String,
+    pub display_name: Option<String>,
+    /// name the user is shown by, see [`db::dbo::User::name`]
+    pub name: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub timezone: String,
+    pub locale: String,
+    pub role: String,
+    pub avatar_url: String,
+    #[serde(serialize_with = "timezone::serialize")]
+    pub created: chrono::NaiveDateTime,
+    /// address the verification link was just sent to, it replaces `email` once verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<String>,
+}
+
+impl From<db::dbo::User> for Profile {
+    fn from(user: db::dbo::User) -> Self {
+        Profile {
+            name: user.name(),
+            avatar_url: format!("/api/user/{}/avatar", user.id),
+            email_verified: user.email_verified.is_some(),
+            id: user.id,
+            username: user.username,
+            firstname: user.firstname,
+            lastname: user.lastname,
+            display_name: user.display_name,
+            email: user.email,
+            timezone: user.timezone,
+            locale: user.locale,
+            role: user.role,
+            created: user.created,
+            pending_email: None,
+        }
+    }
+}
+
+/// Fields which are missing are left as they are, empty `display_name` clears it.
+#[derive(Debug, Deserialize)]
+pub struct ProfileUpdate {
+    firstname: Option<String>,
+    lastname: Option<String>,
+    display_name: Option<String>,
+    /// IANA name, e.g. `Europe/Prague`
+    timezone: Option<String>,
+    /// BCP 47 language tag, e.g. `en-GB`
+    locale: Option<String>,
+    /// changed once the user opens verification link sent to the new address
+    email: Option<String>,
+}
+
+impl ProfileUpdate {
+    fn validate(self) -> TicxResult<(db::dbo::ProfileChange, Option<String>)> {
+        if let Some(timezone) = &self.timezone {
+            timezone::parse(timezone)?;
+        }
+        if let Some(locale) = &self.locale {
+            if !LOCALE.is_match(locale) {
+                return Err(TicxError::BadRequest(format!(
+                    "'{}' is not a language tag, e.g. 'en-GB'",
+                    locale
+                )));
+            }
+        }
+        let email = self.email.map(|email| email.trim().to_string());
+        if let Some(email) = &email {
+            registration::email_domain(email)?;
+        }
+
+        Ok((
+            db::dbo::ProfileChange {
+                firstname: self.firstname.map(|name| name.trim().to_string()),
+                lastname: self.lastname.map(|name| name.trim().to_string()),
+                display_name: self
+                    .display_name
+                    .map(|name| Some(name.trim().to_string()).filter(|name| !name.is_empty())),
+                timezone: self.timezone,
+                locale: self.locale,
+            },
+            email,
+        ))
+    }
+}
+
+#[get("")]
+#[tracing::instrument(skip(db))]
+pub async fn profile(
+    current_user: CurrentUser,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<web::Json<Profile>> {
+    let user_id = current_user.id;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_user(user_id))
+        .await
+        .map(|user| web::Json(Profile::from(user)))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Changes profile of the logged in user. New email is verified first, the link is sent there and the current
+/// email stays until it is opened.
+#[patch("")]
+#[tracing::instrument(skip(db, mailer))]
+pub async fn update_profile(
+    current_user: CurrentUser,
+    json: web::Json<ProfileUpdate>,
+    db: web::Data<Arc<Db>>,
+    mailer: web::Data<Arc<Mailer>>,
+) -> TicxResult<web::Json<Profile>> {
+    let user_id = current_user.id;
+    let (change, email) = json.into_inner().validate()?;
+    // the address could be used to reset password of the user
+    if email.is_some() && current_user.actor_id.is_some() {
+        return Err(TicxError::Forbidden(
+            "impersonation token cannot change email of the user".into(),
+        ));
+    }
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Profile> {
+        let user = db.update_profile(user_id, &change)?;
+        let pending_email = match email {
+            Some(email) if user.email.as_deref() != Some(email.as_str()) => {
+                if db.is_email_taken(&email, user_id)? {
+                    return Err(TicxError::BadRequest("email is already taken".into()));
+                }
+                registration::send_verification(&db, &mailer, &user, &email)?;
+                Some(email)
+            }
+            _ => None,
+        };
+        Ok(Profile {
+            pending_email,
+            ..user.into()
+        })
+    })
+    .await
+    .map(web::Json)
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Replaces avatar of the logged in user with image in the request body.
+#[put("/avatar")]
+#[tracing::instrument(skip(db, body))]
+pub async fn upload_avatar(
+    current_user: CurrentUser,
+    body: web::Bytes,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = current_user.id;
+    let content_type = avatar::detect_image_type(&body)?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_AVATARS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.upsert_avatar(user_id, content_type, &body))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+/// Removes uploaded avatar, identicon is shown instead.
+#[delete("/avatar")]
+#[tracing::instrument(skip(db))]
+pub async fn delete_avatar(
+    current_user: CurrentUser,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = current_user.id;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_AVATARS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_avatar(user_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
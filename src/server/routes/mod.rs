@@ -1,11 +1,22 @@
+mod admin;
 pub(super) mod auth;
+pub(super) mod me;
 mod metrics;
+mod registration;
+mod report;
+mod team;
 #[cfg(test)]
 mod tests;
-mod ticket;
+pub(super) mod ticket;
 mod user;
 
+use super::auth_providers::AuthProviders;
+use super::lockout::LoginLockout;
+use super::mfa::MfaPolicy;
+use super::middlewares::{BasicAuthMiddleware, JWTValidationMiddleware};
+use super::oidc::OidcProvider;
 use actix_web::get;
+use std::sync::Arc;
 
 #[get("/")]
 pub(super) async fn index() -> &'static str {
@@ -34,7 +45,129 @@ macro_rules! routes {
     };
 }
 
-routes!(user_routes, user);
-routes!(ticket_routes, ticket);
-routes!(auth_routes, auth, login);
+// avatar has to be registered before `get`, which would refuse `/{id}/avatar` otherwise
+routes!(
+    user_routes,
+    user,
+    avatar & get & get_all & post & put & delete
+);
+routes!(
+    me_routes,
+    me,
+    change_password
+        & tokens
+        & create_token
+        & delete_token
+        & enroll_mfa
+        & confirm_mfa
+        & disable_mfa
+        & sessions
+        & revoke_session
+        & profile
+        & update_profile
+        & upload_avatar
+        & delete_avatar
+);
+// export and similar have to be registered before `get` otherwise `/export.csv` and `/similar` would be matched as ticket id
+routes!(
+    ticket_routes,
+    ticket,
+    export
+        & similar
+        & import
+        & merge_into
+        & assign
+        & watchers
+        & add_watcher
+        & remove_watcher
+        & get
+        & get_all
+        & post
+        & put
+        & delete
+);
+routes!(
+    team_routes,
+    team,
+    get & get_all & post & put & delete & members & add_member & remove_member
+);
+/// Only login takes basic auth credentials, logout needs valid access token, refresh and password reset are
+/// authorized by the token they carry, so are registration, email verification and invitations, second factor of login by MFA token from login. OpenID Connect login is served only when the provider is configured.
+pub(super) fn auth_routes(
+    db: Arc<db::Db>,
+    providers: Arc<AuthProviders>,
+    lockout: LoginLockout,
+    jwt_validation: JWTValidationMiddleware,
+    oidc: Option<Arc<OidcProvider>>,
+    mfa_policy: MfaPolicy,
+) -> actix_web::Scope {
+    let scope = actix_web::Scope::new("auth")
+        .service(
+            actix_web::Scope::new("login")
+                .wrap(BasicAuthMiddleware {
+                    db,
+                    providers,
+                    lockout,
+                })
+                .data(mfa_policy)
+                .service(auth::login),
+        )
+        .service(
+            actix_web::Scope::new("mfa")
+                .data(lockout)
+                .service(auth::enroll_mfa)
+                .service(auth::verify_mfa),
+        )
+        .service(
+            actix_web::Scope::new("logout")
+                .wrap(jwt_validation)
+                .service(auth::logout),
+        )
+        .service(auth::refresh)
+        .service(auth::request_password_reset)
+        .service(auth::confirm_password_reset)
+        .service(registration::register)
+        .service(registration::verify_email)
+        .service(registration::resend_verification)
+        .service(registration::accept_invitation);
+
+    match oidc {
+        Some(oidc) => scope.service(
+            actix_web::Scope::new("oidc")
+                .data(oidc)
+                .data(mfa_policy)
+                .service(auth::oidc_login)
+                .service(auth::oidc_callback),
+        ),
+        None => scope,
+    }
+}
+routes!(
+    admin_routes,
+    admin,
+    import_issues
+        & set_user_role
+        & list_projects
+        & create_project
+        & project_members
+        & set_project_member
+        & remove_project_member
+        & project_teams
+        & set_project_team
+        & remove_project_team
+        & unlock_user
+        & revoke_user_sessions
+        & deactivate_user
+        & reactivate_user
+        & impersonate
+        & list_impersonations
+        & create_invitation
+        & list_invitations
+        & delete_invitation
+);
+routes!(
+    report_routes,
+    report,
+    throughput & cycle_time & time_in_status & cumulative_flow
+);
 routes!(metrics_routes, metrics, prometheus_metrics);
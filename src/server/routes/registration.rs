@@ -0,0 +1,241 @@
+use super::auth::validate_password;
+use super::user::User;
+use crate::errors::{TicxError, TicxResult};
+use crate::metrics::*;
+use crate::server::mail::Mailer;
+use crate::server::registration::{self, RegistrationPolicy};
+use actix_web::{post, web, HttpResponse};
+use db::dbo::NewUser;
+use db::Db;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct Registration {
+    username: String,
+    password: String,
+    firstname: String,
+    lastname: String,
+    email: String,
+}
+
+impl std::fmt::Debug for Registration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registration")
+            .field("username", &self.username)
+            .field("password", &"*censored*")
+            .field("firstname", &self.firstname)
+            .field("lastname", &self.lastname)
+            .field("email", &self.email)
+            .finish()
+    }
+}
+
+/// Creates user with email of one of the allowed domains, the user can log in once the email is verified
+/// with the link sent there.
+#[post("/register")]
+#[tracing::instrument(skip(db, mailer, policy))]
+pub(crate) async fn register(
+    json: web::Json<Registration>,
+    db: web::Data<Arc<Db>>,
+    mailer: web::Data<Arc<Mailer>>,
+    policy: web::Data<Arc<RegistrationPolicy>>,
+) -> TicxResult<HttpResponse> {
+    let registration = json.into_inner();
+    let email = registration.email.trim().to_string();
+    policy.check(&email)?;
+    validate_password(&registration.password)?;
+    if registration.username.trim().is_empty() {
+        return Err(TicxError::BadRequest("username cannot be empty".into()));
+    }
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USERS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<db::dbo::User> {
+        let user = db.register_user(
+            NewUser::new(
+                registration.username.trim().to_string(),
+                registration.password,
+                registration.firstname,
+                registration.lastname,
+            )
+            .with_unverified_email(email.clone()),
+        )?;
+        tracing::info!(user_id = user.id, "user registered");
+        registration::send_verification(&db, &mailer, &user, &email)?;
+        Ok(user)
+    })
+    .await
+    .map(|user| HttpResponse::Created().json(User::from(user)))
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
+
+#[derive(Deserialize)]
+pub struct EmailVerification {
+    token: String,
+}
+
+impl std::fmt::Debug for EmailVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailVerification")
+            .field("token", &"censored")
+            .finish()
+    }
+}
+
+/// Verifies email with token from the verification mail.
+#[post("/verify-email")]
+#[tracing::instrument(skip(db))]
+pub(crate) async fn verify_email(
+    json: web::Json<EmailVerification>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let token = json.into_inner().token;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_EMAIL_VERIFICATIONS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || db.verify_email(&token))
+        .await
+        .map(|user| {
+            tracing::info!(user_id = user.id, "email verified");
+            HttpResponse::Ok().finish()
+        })
+        .map_err(|err| match err {
+            actix_web::error::BlockingError::Error(db::errors::DbError::NotFound(_)) => {
+                TicxError::InvalidToken("verification token is invalid or expired".into())
+            }
+            err => err.into(),
+        });
+
+    timer.observe_duration();
+    result
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerificationRequest {
+    email: String,
+}
+
+/// Sends new verification link to user whose email is not verified yet. Response is the same whether such
+/// user exists or not, so it cannot be used to find out who has an account.
+#[post("/verify-email/resend")]
+#[tracing::instrument(skip(db, mailer))]
+pub(crate) async fn resend_verification(
+    json: web::Json<VerificationRequest>,
+    db: web::Data<Arc<Db>>,
+    mailer: web::Data<Arc<Mailer>>,
+) -> TicxResult<HttpResponse> {
+    let email = json.into_inner().email;
+    let span = tracing::span::Span::current();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_EMAIL_VERIFICATIONS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<()> {
+        let _guard = span.enter();
+        match db.select_user_by_email(&email) {
+            Ok(user) if user.email_unverified() => {
+                registration::send_verification(&db, &mailer, &user, &email)
+            }
+            Ok(_) | Err(db::errors::DbError::NotFound(_)) => {
+                tracing::debug!("verification requested for unknown or verified email");
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await;
+
+    timer.observe_duration();
+
+    if let Err(err) = result.map_err(TicxError::from) {
+        // failure is not reported to the client, it would tell that the email belongs to someone
+        tracing::error!(%err, "failed to send email verification");
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Deserialize)]
+pub struct InvitationAcceptance {
+    token: String,
+    username: String,
+    password: String,
+    firstname: String,
+    lastname: String,
+    /// used only when the invitation was not sent to an address, it has to be verified then
+    #[serde(default)]
+    email: Option<String>,
+}
+
+impl std::fmt::Debug for InvitationAcceptance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InvitationAcceptance")
+            .field("token", &"censored")
+            .field("username", &self.username)
+            .field("password", &"*censored*")
+            .field("firstname", &self.firstname)
+            .field("lastname", &self.lastname)
+            .field("email", &self.email)
+            .finish()
+    }
+}
+
+/// Creates user with the role of the invitation, regardless of whether self-registration is enabled.
+#[post("/invitation/accept")]
+#[tracing::instrument(skip(db, mailer))]
+pub(crate) async fn accept_invitation(
+    json: web::Json<InvitationAcceptance>,
+    db: web::Data<Arc<Db>>,
+    mailer: web::Data<Arc<Mailer>>,
+) -> TicxResult<HttpResponse> {
+    let acceptance = json.into_inner();
+    validate_password(&acceptance.password)?;
+    if acceptance.username.trim().is_empty() {
+        return Err(TicxError::BadRequest("username cannot be empty".into()));
+    }
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_INVITATIONS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<db::dbo::User> {
+        let mut new_user = NewUser::new(
+            acceptance.username.trim().to_string(),
+            acceptance.password,
+            acceptance.firstname,
+            acceptance.lastname,
+        );
+        if let Some(email) = acceptance.email.map(|e| e.trim().to_string()) {
+            new_user = new_user.with_unverified_email(email);
+        }
+
+        let user = match db.accept_invitation(&acceptance.token, new_user) {
+            Ok(user) => user,
+            Err(db::errors::DbError::NotFound(_)) => {
+                return Err(TicxError::InvalidToken(
+                    "invitation is invalid, expired or already accepted".into(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        tracing::info!(user_id = user.id, "invitation accepted");
+        if let (true, Some(email)) = (user.email_unverified(), user.email.as_deref()) {
+            registration::send_verification(&db, &mailer, &user, email)?;
+        }
+        Ok(user)
+    })
+    .await
+    .map(|user| HttpResponse::Created().json(User::from(user)))
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    result
+}
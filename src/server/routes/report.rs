@@ -0,0 +1,296 @@
+use crate::errors::{TicxError, TicxResult};
+use crate::metrics::*;
+use crate::server::permissions::{require, ProjectAccess};
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use db::Db;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+/// Daily reports have a row per day of the range, longer ranges are refused.
+const MAX_RANGE_DAYS: i64 = 366;
+
+#[derive(Debug, Deserialize)]
+pub struct Range {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl Range {
+    /// Missing bounds default to the last [`DEFAULT_RANGE_DAYS`] days, the range can span at most
+    /// [`MAX_RANGE_DAYS`] days.
+    fn resolve(self) -> TicxResult<(NaiveDate, NaiveDate)> {
+        let to = self
+            .to
+            .unwrap_or_else(|| chrono::Local::today().naive_local());
+        let from = self
+            .from
+            .unwrap_or_else(|| to - chrono::Duration::days(DEFAULT_RANGE_DAYS));
+
+        if from > to {
+            return Err(TicxError::BadRequest(format!(
+                "'from' ({}) must not be after 'to' ({})",
+                from, to
+            )));
+        }
+        if (to - from).num_days() > MAX_RANGE_DAYS {
+            return Err(TicxError::BadRequest(format!(
+                "range from {} to {} is longer than {} days",
+                from, to, MAX_RANGE_DAYS
+            )));
+        }
+
+        Ok((from, to))
+    }
+}
+
+/// Row of a report, columns are the CSV header which is written also when there are no rows.
+trait ReportRow: Serialize {
+    const COLUMNS: &'static [&'static str];
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeInStatusQuery {
+    ticket: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Throughput {
+    day: NaiveDate,
+    created: i64,
+    resolved: i64,
+}
+
+impl ReportRow for Throughput {
+    const COLUMNS: &'static [&'static str] = &["day", "created", "resolved"];
+}
+
+impl From<db::dbo::DailyThroughput> for Throughput {
+    fn from(t: db::dbo::DailyThroughput) -> Self {
+        Throughput {
+            day: t.day,
+            created: t.created,
+            resolved: t.resolved,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CycleTime {
+    severity: i16,
+    tickets: i64,
+    mean_seconds: f64,
+    p50_seconds: f64,
+    p90_seconds: f64,
+    p95_seconds: f64,
+}
+
+impl ReportRow for CycleTime {
+    const COLUMNS: &'static [&'static str] = &[
+        "severity",
+        "tickets",
+        "mean_seconds",
+        "p50_seconds",
+        "p90_seconds",
+        "p95_seconds",
+    ];
+}
+
+impl From<db::dbo::CycleTime> for CycleTime {
+    fn from(c: db::dbo::CycleTime) -> Self {
+        CycleTime {
+            severity: c.severity,
+            tickets: c.tickets,
+            mean_seconds: c.mean_seconds,
+            p50_seconds: c.p50_seconds,
+            p90_seconds: c.p90_seconds,
+            p95_seconds: c.p95_seconds,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeInStatus {
+    status: i16,
+    tickets: i64,
+    total_seconds: f64,
+    mean_seconds: f64,
+}
+
+impl ReportRow for TimeInStatus {
+    const COLUMNS: &'static [&'static str] =
+        &["status", "tickets", "total_seconds", "mean_seconds"];
+}
+
+impl From<db::dbo::TimeInStatus> for TimeInStatus {
+    fn from(t: db::dbo::TimeInStatus) -> Self {
+        TimeInStatus {
+            status: t.status,
+            tickets: t.tickets,
+            total_seconds: t.total_seconds,
+            mean_seconds: t.mean_seconds,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CumulativeFlow {
+    day: NaiveDate,
+    status: i16,
+    tickets: i64,
+}
+
+impl ReportRow for CumulativeFlow {
+    const COLUMNS: &'static [&'static str] = &["day", "status", "tickets"];
+}
+
+impl From<db::dbo::CumulativeFlow> for CumulativeFlow {
+    fn from(c: db::dbo::CumulativeFlow) -> Self {
+        CumulativeFlow {
+            day: c.day,
+            status: c.status,
+            tickets: c.tickets,
+        }
+    }
+}
+
+/// Reports are returned as JSON unless client asks for `text/csv` in `Accept` header.
+fn negotiate<T: ReportRow>(req: &HttpRequest, rows: Vec<T>) -> TicxResult<HttpResponse> {
+    let wants_csv = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("text/csv"))
+        .unwrap_or(false);
+
+    if !wants_csv {
+        return Ok(HttpResponse::Ok().json(rows));
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    writer
+        .write_record(T::COLUMNS)
+        .map_err(|err| TicxError::GenericError {
+            what: "write CSV report header",
+            error: err.to_string(),
+        })?;
+    for row in rows {
+        writer
+            .serialize(row)
+            .map_err(|err| TicxError::GenericError {
+                what: "serialize report row to CSV",
+                error: err.to_string(),
+            })?;
+    }
+
+    let body = writer.into_inner().map_err(|err| TicxError::GenericError {
+        what: "flush CSV report",
+        error: err.to_string(),
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .body(body))
+}
+
+#[get("/throughput")]
+#[tracing::instrument(skip(req, db))]
+pub async fn throughput(
+    access: ProjectAccess<require::ReportRead>,
+    req: HttpRequest,
+    range: web::Query<Range>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested throughput report");
+    let (from, to) = range.into_inner().resolve()?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_HISTORY, "REPORT"])
+        .start_timer();
+
+    let result = web::block(move || db.report_throughput(&access.scope, from, to))
+        .await
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    negotiate(&req, result?.into_iter().map(Throughput::from).collect())
+}
+
+#[get("/cycle-time")]
+#[tracing::instrument(skip(req, db))]
+pub async fn cycle_time(
+    access: ProjectAccess<require::ReportRead>,
+    req: HttpRequest,
+    range: web::Query<Range>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested cycle time report");
+    let (from, to) = range.into_inner().resolve()?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_HISTORY, "REPORT"])
+        .start_timer();
+
+    let result = web::block(move || db.report_cycle_time(&access.scope, from, to))
+        .await
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    negotiate(&req, result?.into_iter().map(CycleTime::from).collect())
+}
+
+#[get("/time-in-status")]
+#[tracing::instrument(skip(req, db))]
+pub async fn time_in_status(
+    access: ProjectAccess<require::ReportRead>,
+    req: HttpRequest,
+    query: web::Query<TimeInStatusQuery>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested time in status report");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_HISTORY, "REPORT"])
+        .start_timer();
+
+    let result =
+        web::block(move || db.report_time_in_status(&access.scope, query.into_inner().ticket))
+            .await
+            .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    negotiate(&req, result?.into_iter().map(TimeInStatus::from).collect())
+}
+
+#[get("/cumulative-flow")]
+#[tracing::instrument(skip(req, db))]
+pub async fn cumulative_flow(
+    access: ProjectAccess<require::ReportRead>,
+    req: HttpRequest,
+    range: web::Query<Range>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested cumulative flow report");
+    let (from, to) = range.into_inner().resolve()?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_HISTORY, "REPORT"])
+        .start_timer();
+
+    let result = web::block(move || db.report_cumulative_flow(&access.scope, from, to))
+        .await
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    negotiate(
+        &req,
+        result?.into_iter().map(CumulativeFlow::from).collect(),
+    )
+}
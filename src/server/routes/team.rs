@@ -0,0 +1,227 @@
+use crate::errors::{TicxError, TicxResult};
+use crate::metrics::*;
+use crate::server::permissions::{require, Authorized};
+use actix_web::web::Json;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use db::Db;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Team {
+    id: Option<i32>,
+    name: String,
+}
+
+impl From<db::dbo::Team> for Team {
+    fn from(t: db::dbo::Team) -> Self {
+        Team {
+            id: Some(t.id),
+            name: t.name,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TeamMember {
+    team_id: i32,
+    user_id: i32,
+}
+
+impl From<db::dbo::TeamMember> for TeamMember {
+    fn from(m: db::dbo::TeamMember) -> Self {
+        TeamMember {
+            team_id: m.team_id,
+            user_id: m.user_id,
+        }
+    }
+}
+
+#[get("/{id}")]
+#[tracing::instrument(skip(db))]
+pub async fn get(
+    _auth: Authorized<require::UserRead>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Team>> {
+    tracing::trace!("requested team");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAMS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_team(id.into_inner()))
+        .await
+        .map(|t| Json(t.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[get("")]
+#[tracing::instrument(skip(db))]
+pub async fn get_all(
+    _auth: Authorized<require::UserRead>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<Team>>> {
+    tracing::trace!("requested all teams");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAMS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_teams())
+        .await
+        .map(|t| Json(t.into_iter().map(Team::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[post("")]
+#[tracing::instrument(skip(db))]
+pub async fn post(
+    _auth: Authorized<require::UserWrite>,
+    json: Json<Team>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested to create new team");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAMS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.insert_team(&json.into_inner().name))
+        .await
+        .map(|t| HttpResponse::Created().json(Team::from(t)))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[put("")]
+#[tracing::instrument(skip(db))]
+pub async fn put(
+    _auth: Authorized<require::UserWrite>,
+    json: Json<Team>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Team>> {
+    tracing::trace!("requested to update team");
+    let team = json.into_inner();
+    let id = team
+        .id
+        .ok_or_else(|| TicxError::BadRequest("team id is required".into()))?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAMS, "UPDATE"])
+        .start_timer();
+
+    let result = web::block(move || db.update_team(id, &team.name))
+        .await
+        .map(|t| Json(t.into()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[delete("/{id}")]
+#[tracing::instrument(skip(db))]
+pub async fn delete(
+    _auth: Authorized<require::UserWrite>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested to delete team");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAMS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_team(id.into_inner()))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[get("/{id}/member")]
+#[tracing::instrument(skip(db))]
+pub async fn members(
+    _auth: Authorized<require::UserRead>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<TeamMember>>> {
+    tracing::trace!("requested team members");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAM_MEMBERS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_team_members(id.into_inner()))
+        .await
+        .map(|m| Json(m.into_iter().map(TeamMember::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[put("/{id}/member/{user_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn add_member(
+    _auth: Authorized<require::UserWrite>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested to add team member");
+    let (team_id, user_id) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAM_MEMBERS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.insert_team_member(team_id, user_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[delete("/{id}/member/{user_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn remove_member(
+    _auth: Authorized<require::UserWrite>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    tracing::trace!("requested to remove team member");
+    let (team_id, user_id) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TEAM_MEMBERS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.delete_team_member(team_id, user_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
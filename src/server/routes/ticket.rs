@@ -1,24 +1,103 @@
+use super::auth::CurrentUser;
 use crate::errors::{TicxError, TicxResult};
+use crate::markdown;
 use crate::metrics::*;
-use actix_web::web::Json;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::server::permissions::{require, ProjectAccess, Role};
+use actix_web::web::{Bytes, BytesMut, Json};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use db::errors::DbResult;
 use db::Db;
+use futures::{future, stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::Arc;
 use tracing::trace;
 
+/// How many tickets are fetched from DB at once when streaming CSV export.
+const EXPORT_BATCH_SIZE: i64 = 500;
+/// Largest CSV body accepted by import.
+const IMPORT_MAX_BYTES: usize = 10 * 1024 * 1024;
+/// Header with `field=column` pairs, separated by `,`, mapping ticket fields to CSV columns of imported file.
+const COLUMN_MAPPING_HEADER: &str = "X-Column-Mapping";
+const EXPORT_COLUMNS: [&str; 7] = [
+    "id",
+    "author_id",
+    "description",
+    "severity",
+    "status",
+    "created",
+    "project_id",
+];
+
+/// Most similar tickets returned by similarity search and as possible duplicates of new ticket.
+const SIMILAR_LIMIT: i64 = 10;
+
+/// How newly created tickets are checked for duplicates, configured by `TICX_DUPLICATE_THRESHOLD`
+/// (trigram similarity from 0 to 1) and `TICX_DUPLICATE_REJECT`.
+#[derive(Debug, Clone)]
+pub(crate) struct DuplicateDetection {
+    pub threshold: f32,
+    /// when set, ticket with likely duplicates is created only if client confirms it with `?force=true`
+    pub reject_unconfirmed: bool,
+}
+
+impl DuplicateDetection {
+    pub(crate) fn from_env() -> Self {
+        let threshold = dotenv::var("TICX_DUPLICATE_THRESHOLD")
+            .ok()
+            .and_then(|t| t.parse::<f32>().ok())
+            .unwrap_or(0.5);
+        let reject_unconfirmed = dotenv::var("TICX_DUPLICATE_REJECT")
+            .map(|r| r == "true" || r == "1")
+            .unwrap_or(false);
+
+        DuplicateDetection {
+            threshold,
+            reject_unconfirmed,
+        }
+    }
+}
+
+fn default_project_id() -> i32 {
+    db::dbo::DEFAULT_PROJECT_ID
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Ticket {
     id: Option<i32>,
+    /// tickets are created in the default project and updates keep the project when absent
+    #[serde(default)]
+    project_id: Option<i32>,
+    /// output only, the author is the user who created the ticket
+    #[serde(default, skip_deserializing)]
     author_id: i32,
     description: String,
     severity: i16,
     status: Option<i16>,
+    /// `description` rendered from markdown, output only
+    #[serde(default, skip_deserializing)]
+    description_html: Option<String>,
+    #[serde(default, skip_deserializing)]
+    resolution: Option<String>,
+    /// id of ticket this one was merged into as duplicate, output only
+    #[serde(default, skip_deserializing)]
+    merged_into: Option<i32>,
+    /// output only, changed through `PUT /ticket/{id}/assignee`
+    #[serde(default, skip_deserializing)]
+    assignee_id: Option<i32>,
+    #[serde(default, skip_deserializing)]
+    assignee_team_id: Option<i32>,
 }
 
-impl From<Ticket> for db::dbo::Ticket {
+impl From<Ticket> for db::dbo::TicketChanges {
     fn from(t: Ticket) -> Self {
-        db::dbo::Ticket::new(t.id, t.author_id, t.description, t.severity, t.status)
+        db::dbo::TicketChanges {
+            id: t.id.unwrap_or(0),
+            project_id: t.project_id,
+            description: t.description,
+            severity: t.severity,
+            status: t.status.unwrap_or(db::dbo::status::OPEN),
+        }
     }
 }
 
@@ -26,33 +105,255 @@ impl From<db::dbo::Ticket> for Ticket {
     fn from(t: db::dbo::Ticket) -> Self {
         Ticket {
             id: Some(t.id),
+            project_id: Some(t.project_id),
             author_id: t.author_id,
             description: t.description,
             severity: t.severity,
             status: Some(t.status),
+            description_html: t.description_html,
+            resolution: t.resolution,
+            merged_into: t.merged_into,
+            assignee_id: t.assignee_id,
+            assignee_team_id: t.assignee_team_id,
         }
     }
 }
 
-impl From<Ticket> for db::dbo::NewTicket {
-    fn from(t: Ticket) -> Self {
-        db::dbo::NewTicket::new(t.author_id, t.description, t.severity)
+/// Filters accepted by ticket list and CSV export.
+#[derive(Debug, Default, Deserialize)]
+pub struct TicketQuery {
+    project_id: Option<i32>,
+    author_id: Option<i32>,
+    status: Option<i16>,
+    severity: Option<i16>,
+    assignee_id: Option<i32>,
+    /// tickets assigned to the team with this id
+    team_id: Option<i32>,
+    /// tickets assigned to any team of the caller
+    #[serde(default)]
+    my_team: bool,
+}
+
+impl TicketQuery {
+    fn into_filter(self, user_id: i32) -> db::dbo::TicketFilter {
+        db::dbo::TicketFilter {
+            project_id: self.project_id,
+            author_id: self.author_id,
+            status: self.status,
+            severity: self.severity,
+            assignee_id: self.assignee_id,
+            assignee_team_id: self.team_id,
+            assigned_to_teams_of: if self.my_team { Some(user_id) } else { None },
+        }
+    }
+}
+
+impl Ticket {
+    /// project a new ticket is created in
+    fn new_project_id(&self) -> i32 {
+        self.project_id.unwrap_or_else(default_project_id)
+    }
+
+    fn into_new_ticket(self, author_id: i32) -> db::dbo::NewTicket {
+        let project_id = self.new_project_id();
+        db::dbo::NewTicket::new(author_id, self.description, self.severity).with_project(project_id)
     }
 }
 
 #[get("/{id}")]
-#[tracing::instrument(skip(db))]
-pub async fn get(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<Json<Ticket>> {
+#[tracing::instrument(skip(req, db))]
+pub async fn get(
+    access: ProjectAccess<require::TicketRead>,
+    req: HttpRequest,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     tracing::trace!("requested ticket");
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_TICKETS, "SELECT"])
         .start_timer();
 
-    let result = web::block(move || db.select_ticket(id.into_inner()))
+    let result = web::block(move || -> DbResult<db::dbo::Ticket> {
+        let mut ticket = db.select_ticket(&access.scope, id.into_inner())?;
+        markdown::render_missing(&db, std::slice::from_mut(&mut ticket))?;
+        Ok(ticket)
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    let ticket = result?;
+    match ticket.merged_into {
+        // merged duplicate lives on only as a pointer to the ticket it was merged into
+        Some(target) => {
+            let location = match req.path().rsplit_once('/') {
+                Some((prefix, _)) => format!("{}/{}", prefix, target),
+                None => target.to_string(),
+            };
+            trace!(%location, "ticket was merged, redirecting");
+            Ok(HttpResponse::MovedPermanently()
+                .header(actix_web::http::header::LOCATION, location)
+                .json(Ticket::from(ticket)))
+        }
+        None => Ok(HttpResponse::Ok().json(Ticket::from(ticket))),
+    }
+}
+
+#[post("/{id}/merge-into/{target}")]
+#[tracing::instrument(skip(db))]
+pub async fn merge_into(
+    access: ProjectAccess<require::TicketWrite>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Ticket>> {
+    trace!("requested to merge ticket into another one");
+    let (source, target) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "MERGE"])
+        .start_timer();
+
+    let result = web::block(move || db.merge_ticket(&access.scope, source, target))
         .await
         .map(|t| Json(t.into()))
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Assignee of a ticket, both user and team can be set at once. Missing value unassigns.
+#[derive(Debug, Deserialize)]
+pub struct Assignee {
+    user_id: Option<i32>,
+    team_id: Option<i32>,
+}
+
+#[put("/{id}/assignee")]
+#[tracing::instrument(skip(db))]
+pub async fn assign(
+    access: ProjectAccess<require::TicketWrite>,
+    id: web::Path<i32>,
+    json: Json<Assignee>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Ticket>> {
+    trace!("requested to assign ticket");
+    let Assignee { user_id, team_id } = json.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "UPDATE"])
+        .start_timer();
+
+    let result =
+        web::block(move || db.assign_ticket(&access.scope, id.into_inner(), user_id, team_id))
+            .await
+            .map(|t| Json(t.into()))
+            .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TicketWatcher {
+    id: Option<i32>,
+    user_id: Option<i32>,
+    team_id: Option<i32>,
+}
+
+impl From<db::dbo::TicketWatcher> for TicketWatcher {
+    fn from(w: db::dbo::TicketWatcher) -> Self {
+        TicketWatcher {
+            id: Some(w.id),
+            user_id: w.user_id,
+            team_id: w.team_id,
+        }
+    }
+}
+
+impl TicketWatcher {
+    fn watcher(&self) -> TicxResult<db::dbo::Watcher> {
+        match (self.user_id, self.team_id) {
+            (Some(user_id), None) => Ok(db::dbo::Watcher::User(user_id)),
+            (None, Some(team_id)) => Ok(db::dbo::Watcher::Team(team_id)),
+            _ => Err(TicxError::BadRequest(
+                "watcher has to be either user_id or team_id".into(),
+            )),
+        }
+    }
+}
+
+#[get("/{id}/watchers")]
+#[tracing::instrument(skip(db))]
+pub async fn watchers(
+    access: ProjectAccess<require::TicketRead>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<TicketWatcher>>> {
+    trace!("requested ticket watchers");
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_WATCHERS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || db.select_watchers(&access.scope, id.into_inner()))
+        .await
+        .map(|w| Json(w.into_iter().map(TicketWatcher::from).collect()))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+/// Anyone who can read the ticket can watch it, or make their team watch it.
+#[post("/{id}/watchers")]
+#[tracing::instrument(skip(db))]
+pub async fn add_watcher(
+    access: ProjectAccess<require::TicketRead>,
+    id: web::Path<i32>,
+    json: Json<TicketWatcher>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    trace!("requested to add ticket watcher");
+    let watcher = json.watcher()?;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_WATCHERS, "INSERT"])
+        .start_timer();
+
+    let result = web::block(move || db.add_watcher(&access.scope, id.into_inner(), watcher))
+        .await
+        .map(|w| HttpResponse::Created().json(TicketWatcher::from(w)))
+        .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    result
+}
+
+#[delete("/{id}/watchers/{watcher_id}")]
+#[tracing::instrument(skip(db))]
+pub async fn remove_watcher(
+    access: ProjectAccess<require::TicketRead>,
+    path: web::Path<(i32, i32)>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    trace!("requested to remove ticket watcher");
+    let (ticket_id, watcher_id) = path.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKET_WATCHERS, "DELETE"])
+        .start_timer();
+
+    let result = web::block(move || db.remove_watcher(&access.scope, ticket_id, watcher_id))
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+        .map_err(TicxError::from);
 
     timer.observe_duration();
 
@@ -61,17 +362,95 @@ pub async fn get(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<Json<
 
 #[get("")]
 #[tracing::instrument(skip(db))]
-pub async fn get_all(db: web::Data<Arc<Db>>) -> TicxResult<Json<Vec<Ticket>>> {
+pub async fn get_all(
+    access: ProjectAccess<require::TicketRead>,
+    query: web::Query<TicketQuery>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<Ticket>>> {
     tracing::trace!("requested all tickets");
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_TICKETS, "SELECT"])
         .start_timer();
 
-    let result = web::block(move || db.select_tickets())
-        .await
-        .map(|t| Json(t.into_iter().map(Ticket::from).collect::<Vec<Ticket>>()))
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+    let filter = query.into_inner().into_filter(access.user_id);
+    let result = web::block(move || -> DbResult<Vec<db::dbo::Ticket>> {
+        let mut tickets = db.select_tickets(&access.scope, &filter)?;
+        markdown::render_missing(&db, &mut tickets)?;
+        Ok(tickets)
+    })
+    .await
+    .map(|t| Json(t.into_iter().map(Ticket::from).collect::<Vec<Ticket>>()))
+    .map_err(|err| TicxError::DbFail(err.to_string()));
+
+    timer.observe_duration();
+
+    result
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimilarTicket {
+    id: i32,
+    description: String,
+    status: i16,
+    similarity: f32,
+}
+
+impl From<db::dbo::SimilarTicket> for SimilarTicket {
+    fn from(t: db::dbo::SimilarTicket) -> Self {
+        SimilarTicket {
+            id: t.id,
+            description: t.description,
+            status: t.status,
+            similarity: t.similarity,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SimilarQuery {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedTicket {
+    /// `None` when the ticket was not created because of likely duplicates
+    id: Option<i32>,
+    duplicates: Vec<SimilarTicket>,
+}
+
+#[get("/similar")]
+#[tracing::instrument(skip(db, duplicate_detection))]
+pub async fn similar(
+    access: ProjectAccess<require::TicketRead>,
+    query: web::Query<SimilarQuery>,
+    db: web::Data<Arc<Db>>,
+    duplicate_detection: web::Data<Arc<DuplicateDetection>>,
+) -> TicxResult<Json<Vec<SimilarTicket>>> {
+    trace!("requested similar tickets");
+    let threshold = duplicate_detection.threshold;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "SIMILAR"])
+        .start_timer();
+
+    let result = web::block(move || {
+        db.select_similar_tickets(
+            &access.scope,
+            &query.into_inner().text,
+            threshold,
+            SIMILAR_LIMIT,
+        )
+    })
+    .await
+    .map(|t| Json(t.into_iter().map(SimilarTicket::from).collect()))
+    .map_err(TicxError::from);
 
     timer.observe_duration();
 
@@ -79,56 +458,494 @@ pub async fn get_all(db: web::Data<Arc<Db>>) -> TicxResult<Json<Vec<Ticket>>> {
 }
 
 #[post("")]
-#[tracing::instrument(skip(db))]
-pub async fn post(json: web::Json<Ticket>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+#[tracing::instrument(skip(db, duplicate_detection))]
+pub async fn post(
+    access: ProjectAccess<require::TicketCreate>,
+    current_user: CurrentUser,
+    json: web::Json<Ticket>,
+    query: web::Query<CreateQuery>,
+    db: web::Data<Arc<Db>>,
+    duplicate_detection: web::Data<Arc<DuplicateDetection>>,
+) -> TicxResult<HttpResponse> {
     trace!("requested to create new ticket");
+    let ticket = json.into_inner();
+    let project_id = ticket.new_project_id();
+    if !access.scope.allows(project_id) {
+        return Err(TicxError::NotFound(format!("project {}", project_id)));
+    }
+    let threshold = duplicate_detection.threshold;
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "SIMILAR"])
+        .start_timer();
+
+    let description = ticket.description.clone();
+    let db_clone = db.clone();
+    // duplicates are looked for only in the project the ticket is created in
+    let project_scope = db::dbo::ProjectScope::Only(vec![project_id]);
+    let duplicates = web::block(move || {
+        db_clone.select_similar_tickets(&project_scope, &description, threshold, SIMILAR_LIMIT)
+    })
+    .await
+    .map(|t| t.into_iter().map(SimilarTicket::from).collect::<Vec<_>>())
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+    let duplicates = duplicates?;
+
+    if duplicate_detection.reject_unconfirmed && !duplicates.is_empty() && !query.force {
+        tracing::debug!(
+            duplicates = duplicates.len(),
+            "ticket not created, likely duplicates found"
+        );
+        return Ok(HttpResponse::Conflict().json(CreatedTicket {
+            id: None,
+            duplicates,
+        }));
+    }
+
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_TICKETS, "INSERT"])
         .start_timer();
 
-    let result = web::block(move || db.insert_ticket(json.into_inner().into()))
-        .await
-        .map(|_| HttpResponse::Created().finish())
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+    let result = web::block(move || {
+        db.insert_ticket(&access.scope, ticket.into_new_ticket(current_user.id))
+    })
+    .await
+    .map(|inserted| {
+        HttpResponse::Created().json(CreatedTicket {
+            id: Some(inserted.id),
+            duplicates,
+        })
+    })
+    .map_err(TicxError::from);
 
     timer.observe_duration();
 
     result
 }
 
+/// Any member of the ticket's project can update it, not only its author, members triage tickets of each other.
+/// Reporters, who only file tickets, cannot update them at all. The author itself never changes.
 #[put("")]
 #[tracing::instrument(skip(db))]
-pub async fn put(json: web::Json<Ticket>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+pub async fn put(
+    access: ProjectAccess<require::TicketWrite>,
+    json: web::Json<Ticket>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     trace!("requested to update ticket");
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_TICKETS, "UPDATE"])
         .start_timer();
 
-    let result = web::block(move || db.update_ticket(json.into_inner().into()))
+    let result = web::block(move || db.update_ticket(&access.scope, json.into_inner().into()))
         .await
         .map(|_| HttpResponse::Ok().finish())
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+        .map_err(TicxError::from);
 
     timer.observe_duration();
 
     result
 }
 
+/// Deleting is reserved to admins of the ticket's project, authors cannot delete their own tickets.
 #[delete("/{id}")]
 #[tracing::instrument(skip(db))]
-pub async fn delete(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+pub async fn delete(
+    access: ProjectAccess<require::TicketDelete>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     trace!("requested to delete ticket");
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_TICKETS, "DELETE"])
         .start_timer();
 
-    let result = web::block(move || db.delete_ticket(id.into_inner()))
+    let result = web::block(move || db.delete_ticket(&access.scope, id.into_inner()))
         .await
         .map(|_| HttpResponse::Ok().finish())
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+        .map_err(TicxError::from);
 
     timer.observe_duration();
 
     result
 }
+
+#[get("/export.csv")]
+#[tracing::instrument(skip(db))]
+pub async fn export(
+    access: ProjectAccess<require::TicketRead>,
+    query: web::Query<TicketQuery>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    trace!("requested tickets CSV export");
+
+    let filter = query.into_inner().into_filter(access.user_id);
+    let scope = access.scope;
+    let db = db.get_ref().clone();
+
+    let header = stream::once(future::ready(csv_chunk(|writer| {
+        writer.write_record(EXPORT_COLUMNS)
+    })));
+
+    // `None` as state means there is nothing more to fetch
+    let rows = stream::unfold(Some(0), move |after_id| {
+        let db = db.clone();
+        let scope = scope.clone();
+        let filter = filter.clone();
+        async move {
+            let after_id = after_id?;
+
+            let timer = DB_QUERY_HISTOGRAM
+                .with_label_values(&[DB_TABLE_TICKETS, "SELECT"])
+                .start_timer();
+            let page = web::block(move || {
+                db.select_tickets_page(&scope, &filter, after_id, EXPORT_BATCH_SIZE)
+            })
+            .await
+            .map_err(TicxError::from);
+            timer.observe_duration();
+
+            match page {
+                Ok(page) if page.is_empty() => None,
+                Ok(page) => {
+                    let next = page.last().map(|t| t.id);
+                    let chunk = csv_chunk(|writer| {
+                        page.iter().try_for_each(|t| {
+                            writer.write_record(&[
+                                t.id.to_string(),
+                                t.author_id.to_string(),
+                                t.description.clone(),
+                                t.severity.to_string(),
+                                t.status.to_string(),
+                                t.created.to_string(),
+                                t.project_id.to_string(),
+                            ])
+                        })
+                    });
+                    Some((chunk, next))
+                }
+                Err(err) => {
+                    tracing::error!(%err, "failed to fetch tickets page for export");
+                    Some((Err(err), None))
+                }
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(
+            actix_web::http::header::CONTENT_DISPOSITION,
+            "attachment; filename=\"tickets.csv\"",
+        )
+        .streaming(Box::pin(header.chain(rows))))
+}
+
+fn csv_chunk<F>(write: F) -> TicxResult<Bytes>
+where
+    F: FnOnce(&mut csv::Writer<Vec<u8>>) -> csv::Result<()>,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+
+    write(&mut writer)
+        .map_err(|err| TicxError::GenericError {
+            what: "write CSV rows",
+            error: err.to_string(),
+        })
+        .and_then(|_| {
+            writer.into_inner().map_err(|err| TicxError::GenericError {
+                what: "flush CSV rows",
+                error: err.to_string(),
+            })
+        })
+        .map(Bytes::from)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// project all imported tickets are created in
+    #[serde(default = "default_project_id")]
+    project_id: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    row: u64,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    dry_run: bool,
+    rows: usize,
+    imported: usize,
+    errors: Vec<RowError>,
+}
+
+/// Names of CSV columns the ticket fields are read from, by default the columns are named same as fields.
+/// Author column is optional, tickets without it are imported in the name of the caller.
+#[derive(Debug)]
+struct ColumnMapping {
+    author_id: String,
+    description: String,
+    severity: String,
+    status: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping {
+            author_id: "author_id".into(),
+            description: "description".into(),
+            severity: "severity".into(),
+            status: "status".into(),
+        }
+    }
+}
+
+impl ColumnMapping {
+    fn from_request(req: &HttpRequest) -> TicxResult<Self> {
+        let mut mapping = ColumnMapping::default();
+
+        let value = match req.headers().get(COLUMN_MAPPING_HEADER) {
+            Some(value) => value.to_str().map_err(|err| TicxError::InvalidHeader {
+                header: COLUMN_MAPPING_HEADER,
+                value: "failed to decode".into(),
+                error: err.to_string(),
+            })?,
+            None => return Ok(mapping),
+        };
+
+        for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| TicxError::InvalidHeader {
+                    header: COLUMN_MAPPING_HEADER,
+                    value: value.to_string(),
+                    error: format!("expected 'field=column', got '{}'", pair),
+                })?;
+
+            let column = column.trim().to_string();
+            match field.trim() {
+                "author_id" => mapping.author_id = column,
+                "description" => mapping.description = column,
+                "severity" => mapping.severity = column,
+                "status" => mapping.status = column,
+                unknown => {
+                    return Err(TicxError::InvalidHeader {
+                        header: COLUMN_MAPPING_HEADER,
+                        value: value.to_string(),
+                        error: format!("unknown ticket field '{}'", unknown),
+                    })
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    fn resolve(&self, headers: &csv::StringRecord) -> TicxResult<ColumnIndexes> {
+        let find = |column: &str| headers.iter().position(|h| h.trim() == column);
+        let required = |column: &str| {
+            find(column).ok_or_else(|| {
+                TicxError::BadRequest(format!("CSV is missing required column '{}'", column))
+            })
+        };
+
+        Ok(ColumnIndexes {
+            author_id: find(&self.author_id),
+            description: required(&self.description)?,
+            severity: required(&self.severity)?,
+            status: find(&self.status),
+        })
+    }
+}
+
+struct ColumnIndexes {
+    author_id: Option<usize>,
+    description: usize,
+    severity: usize,
+    status: Option<usize>,
+}
+
+impl ColumnIndexes {
+    fn parse(
+        &self,
+        record: &csv::StringRecord,
+        project_id: i32,
+        caller_id: i32,
+    ) -> Result<db::dbo::NewTicket, String> {
+        let field = |idx: usize, name: &str| {
+            record
+                .get(idx)
+                .map(str::trim)
+                .ok_or_else(|| format!("missing value for '{}'", name))
+        };
+
+        let author_id = match self.author_id {
+            Some(idx) => field(idx, "author_id")?
+                .parse::<i32>()
+                .map_err(|err| format!("invalid author_id: {}", err))?,
+            None => caller_id,
+        };
+
+        let description = field(self.description, "description")?;
+        if description.is_empty() {
+            return Err("description must not be empty".into());
+        }
+
+        let severity = field(self.severity, "severity")?
+            .parse::<i16>()
+            .map_err(|err| format!("invalid severity: {}", err))?;
+
+        let ticket = db::dbo::NewTicket::new(author_id, description.to_string(), severity)
+            .with_project(project_id);
+
+        match self.status.map(|idx| field(idx, "status")).transpose()? {
+            Some(raw) if !raw.is_empty() => {
+                let status = raw
+                    .parse::<i16>()
+                    .map_err(|err| format!("invalid status: {}", err))?;
+                if !(db::dbo::status::OPEN..=db::dbo::status::CLOSED).contains(&status) {
+                    return Err(format!("unknown status {}", status));
+                }
+                Ok(ticket.with_status(status))
+            }
+            _ => Ok(ticket),
+        }
+    }
+}
+
+/// Imports tickets from CSV. They are authored by the caller, only admins can import tickets in the name of
+/// others through the author column.
+#[post("/import")]
+#[tracing::instrument(skip(req, payload, db))]
+pub async fn import(
+    access: ProjectAccess<require::TicketWrite>,
+    current_user: CurrentUser,
+    req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    trace!("requested tickets CSV import");
+    let ImportQuery {
+        dry_run,
+        project_id,
+    } = query.into_inner();
+    let mapping = ColumnMapping::from_request(&req)?;
+
+    if !access.scope.allows(project_id) {
+        return Err(TicxError::NotFound(format!("project {}", project_id)));
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| TicxError::BadRequest(err.to_string()))?;
+        if body.len() + chunk.len() > IMPORT_MAX_BYTES {
+            return Err(TicxError::BadRequest(format!(
+                "CSV is larger than {} bytes",
+                IMPORT_MAX_BYTES
+            )));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(body.as_ref());
+
+    let columns = reader
+        .headers()
+        .map_err(|err| TicxError::BadRequest(format!("failed to read CSV header: {}", err)))
+        .and_then(|headers| mapping.resolve(headers))?;
+    if columns.author_id.is_some() && current_user.role != Role::Admin {
+        return Err(TicxError::Forbidden(format!(
+            "only admins can import tickets of other authors, leave out column '{}'",
+            mapping.author_id
+        )));
+    }
+
+    let mut errors = vec![];
+    let mut parsed = vec![];
+    let mut rows = 0;
+    for record in reader.records() {
+        rows += 1;
+        match record {
+            Ok(record) => {
+                let row = record.position().map(|p| p.line()).unwrap_or_default();
+                match columns.parse(&record, project_id, current_user.id) {
+                    Ok(ticket) => parsed.push((row, ticket)),
+                    Err(error) => errors.push(RowError { row, error }),
+                }
+            }
+            Err(err) => errors.push(RowError {
+                row: err.position().map(|p| p.line()).unwrap_or_default(),
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    let author_ids = parsed
+        .iter()
+        .map(|(_, t)| t.author_id())
+        .collect::<HashSet<i32>>();
+    let db_clone = db.clone();
+    let existing =
+        web::block(move || db_clone.select_existing_user_ids(author_ids.into_iter().collect()))
+            .await
+            .map_err(TicxError::from)?
+            .into_iter()
+            .collect::<HashSet<i32>>();
+
+    for (row, ticket) in parsed.iter() {
+        if !existing.contains(&ticket.author_id()) {
+            errors.push(RowError {
+                row: *row,
+                error: format!("author {} does not exist", ticket.author_id()),
+            });
+        }
+    }
+    errors.sort_by_key(|e| e.row);
+
+    let mut report = ImportReport {
+        dry_run,
+        rows,
+        imported: 0,
+        errors,
+    };
+
+    if !report.errors.is_empty() {
+        tracing::debug!(errors = report.errors.len(), "CSV import rejected");
+        return Ok(HttpResponse::UnprocessableEntity().json(report));
+    }
+
+    if dry_run {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_TICKETS, "INSERT"])
+        .start_timer();
+
+    let imported = web::block(move || {
+        db.insert_tickets(
+            &access.scope,
+            parsed.into_iter().map(|(_, ticket)| ticket).collect(),
+        )
+    })
+    .await
+    .map_err(TicxError::from);
+
+    timer.observe_duration();
+
+    report.imported = imported?;
+    Ok(HttpResponse::Created().json(report))
+}
@@ -1,11 +1,17 @@
 use crate::errors::{TicxError, TicxResult};
 use crate::metrics::*;
+use crate::server::avatar::identicon;
+use crate::server::permissions::{require, Authorized, Role};
+use actix_web::error::BlockingError;
 use actix_web::web::Json;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use actix_web::{delete, get, http::header, post, put, web, HttpResponse};
+use db::errors::DbError;
 use db::Db;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+/// `role` and `password` are used only when the user is created. Role is changed through
+/// `PUT /api/admin/user/{id}/role`, password through `POST /api/me/password` or password reset.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct User {
     pub(super) username: String,
@@ -14,11 +20,26 @@ pub struct User {
     pub(super) lastname: String,
     pub(super) id: Option<i32>,
     pub(super) role: String,
+    #[serde(default)]
+    pub(super) email: Option<String>,
+    /// whether `email` is verified, on update `true` lets admin vouch for changed address which has to be
+    /// verified by the user otherwise
+    #[serde(default)]
+    pub(super) email_verified: bool,
+    /// ignored on input, changed through `POST /api/admin/user/{id}/deactivate` and `/reactivate`
+    #[serde(default = "default_active")]
+    pub(super) active: bool,
+}
+
+fn default_active() -> bool {
+    true
 }
 
 impl From<User> for db::dbo::NewUser {
     fn from(user: User) -> db::dbo::NewUser {
         db::dbo::NewUser::new(user.username, user.password, user.firstname, user.lastname)
+            .with_role(user.role)
+            .with_email(user.email)
     }
 }
 
@@ -31,6 +52,7 @@ impl From<User> for db::dbo::User {
             user.firstname,
             user.lastname,
         )
+        .with_email(user.email, user.email_verified)
     }
 }
 
@@ -42,7 +64,10 @@ impl From<db::dbo::User> for User {
             firstname: db_user.firstname,
             lastname: db_user.lastname,
             id: Some(db_user.id),
-            role: "NotImplemented".into(),
+            role: db_user.role,
+            email_verified: db_user.email_verified.is_some(),
+            email: db_user.email,
+            active: db_user.active,
         }
     }
 }
@@ -56,13 +81,20 @@ impl std::fmt::Display for User {
             .field("lastname", &self.lastname)
             .field("id", &self.id)
             .field("role", &self.role)
+            .field("email", &self.email)
+            .field("email_verified", &self.email_verified)
+            .field("active", &self.active)
             .finish()
     }
 }
 
 #[get("/{id}")]
 #[tracing::instrument(skip(db))]
-pub async fn get(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<Json<User>> {
+pub async fn get(
+    _auth: Authorized<require::UserRead>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<User>> {
     tracing::trace!("requested user information");
 
     let timer = DB_QUERY_HISTOGRAM
@@ -81,7 +113,10 @@ pub async fn get(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<Json<
 
 #[get("")]
 #[tracing::instrument(skip(db))]
-pub async fn get_all(db: web::Data<Arc<Db>>) -> TicxResult<Json<Vec<User>>> {
+pub async fn get_all(
+    _auth: Authorized<require::UserRead>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<Json<Vec<User>>> {
     tracing::trace!("requested all users information");
 
     let timer = DB_QUERY_HISTOGRAM
@@ -100,8 +135,13 @@ pub async fn get_all(db: web::Data<Arc<Db>>) -> TicxResult<Json<Vec<User>>> {
 
 #[post("")]
 #[tracing::instrument(skip(db))]
-pub async fn post(json: web::Json<User>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+pub async fn post(
+    _auth: Authorized<require::UserWrite>,
+    json: web::Json<User>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     tracing::trace!("requested to create new user");
+    json.role.parse::<Role>()?;
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_USERS, "INSERT"])
@@ -119,7 +159,11 @@ pub async fn post(json: web::Json<User>, db: web::Data<Arc<Db>>) -> TicxResult<H
 
 #[put("")]
 #[tracing::instrument(skip(db))]
-pub async fn put(json: web::Json<User>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+pub async fn put(
+    _auth: Authorized<require::UserWrite>,
+    json: web::Json<User>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     tracing::trace!("requested to update user");
 
     let timer = DB_QUERY_HISTOGRAM
@@ -137,17 +181,65 @@ pub async fn put(json: web::Json<User>, db: web::Data<Arc<Db>>) -> TicxResult<Ht
 
 #[delete("/{id}")]
 #[tracing::instrument(skip(db))]
-pub async fn delete(id: web::Path<i32>, db: web::Data<Arc<Db>>) -> TicxResult<HttpResponse> {
+pub async fn delete(
+    _auth: Authorized<require::UserDelete>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
     tracing::trace!("requested to delete user");
 
     let timer = DB_QUERY_HISTOGRAM
         .with_label_values(&[DB_TABLE_USERS, "DELETE"])
         .start_timer();
 
-    let result = web::block(move || db.delete_user(id.into_inner()))
+    let user_id = id.into_inner();
+    let result = web::block(move || db.delete_user(user_id))
         .await
         .map(|_| HttpResponse::Ok().finish())
-        .map_err(|err| TicxError::DbFail(err.to_string()));
+        .map_err(|err| match err {
+            BlockingError::Error(DbError::Conflict(reason)) => TicxError::Conflict(format!(
+                "{}, deactivate it with POST /api/admin/user/{}/deactivate",
+                reason, user_id
+            )),
+            err => err.into(),
+        });
+
+    timer.observe_duration();
+    result
+}
+
+/// Uploaded avatar of the user, identicon when the user has none.
+#[get("/{id}/avatar")]
+#[tracing::instrument(skip(db))]
+pub async fn avatar(
+    _auth: Authorized<require::UserRead>,
+    id: web::Path<i32>,
+    db: web::Data<Arc<Db>>,
+) -> TicxResult<HttpResponse> {
+    let user_id = id.into_inner();
+
+    let timer = DB_QUERY_HISTOGRAM
+        .with_label_values(&[DB_TABLE_USER_AVATARS, "SELECT"])
+        .start_timer();
+
+    let result = web::block(move || -> TicxResult<Option<db::dbo::Avatar>> {
+        // identicon is not generated for users who do not exist
+        db.select_user(user_id)?;
+        Ok(db.select_avatar(user_id)?)
+    })
+    .await
+    .map(|uploaded| {
+        let (content_type, data) = match uploaded {
+            Some(uploaded) => (uploaded.content_type, uploaded.data),
+            None => ("image/svg+xml".to_string(), identicon(user_id).into_bytes()),
+        };
+        HttpResponse::Ok()
+            .content_type(content_type)
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::CACHE_CONTROL, "private, max-age=300")
+            .body(data)
+    })
+    .map_err(TicxError::from);
 
     timer.observe_duration();
     result
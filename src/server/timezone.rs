@@ -0,0 +1,85 @@
+//! Timestamps of API responses are UTC. Client which sends `X-Timezone` header gets them in the given timezone
+//! instead, with the offset, e.g. `2026-10-20T14:00:00+02:00`. The header is either IANA name of the timezone or
+//! `user` for the timezone of the logged in user's profile. Only timestamp fields of DTOs serialized with
+//! [`serialize`] are converted, strings which merely look like timestamps are left alone.
+
+use crate::errors::{TicxError, TicxResult};
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Serialize, Serializer};
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub(crate) const TIMEZONE_HEADER: &str = "X-Timezone";
+/// value of [`TIMEZONE_HEADER`] asking for the timezone of the user's profile
+pub(crate) const USER_TIMEZONE: &str = "user";
+
+pub(crate) fn parse(name: &str) -> TicxResult<Tz> {
+    name.parse::<Tz>()
+        .map_err(|_| TicxError::BadRequest(format!("unknown timezone '{}'", name)))
+}
+
+thread_local! {
+    /// timezone of the request whose handler is being polled on this thread, see [`InTimezone`]
+    static REQUESTED: Cell<Option<Tz>> = const { Cell::new(None) };
+}
+
+/// Handles request in `tz`, timestamps serialized by [`serialize`] while the handler is polled are rendered in it.
+/// Workers are single threaded, so the timezone is set for each poll and reset right after.
+pub(crate) struct InTimezone<F> {
+    tz: Tz,
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future> InTimezone<F> {
+    pub fn new(tz: Tz, fut: F) -> Self {
+        InTimezone {
+            tz,
+            fut: Box::pin(fut),
+        }
+    }
+}
+
+impl<F: Future> Future for InTimezone<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        struct Reset(Option<Tz>);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                REQUESTED.with(|requested| requested.set(self.0));
+            }
+        }
+
+        let _reset = Reset(REQUESTED.with(|requested| requested.replace(Some(self.tz))));
+        self.fut.as_mut().poll(cx)
+    }
+}
+
+/// `serialize_with` of timestamps in responses, UTC unless the request asked for another timezone.
+pub(crate) fn serialize<S: Serializer>(
+    utc: &NaiveDateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match REQUESTED.with(Cell::get) {
+        Some(tz) => serializer.serialize_str(&tz.from_utc_datetime(utc).to_rfc3339()),
+        None => utc.serialize(serializer),
+    }
+}
+
+/// [`serialize`] of optional timestamps.
+pub(crate) fn serialize_option<S: Serializer>(
+    utc: &Option<NaiveDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    struct Localized<'a>(&'a NaiveDateTime);
+    impl Serialize for Localized<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize(self.0, serializer)
+        }
+    }
+
+    utc.as_ref().map(Localized).serialize(serializer)
+}